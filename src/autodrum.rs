@@ -1,24 +1,111 @@
 use std::collections::HashMap;
-use std::env;
 use std::error::Error;
-use std::time::{Instant, UNIX_EPOCH};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use crate::system::configuration::{Configuration};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use crate::system::configuration::{Configuration, KitData, DEFAULT_CONFIGURATION_PATH, DEFAULT_KIT_NAME};
 
-use crate::hardware::striker::{Striker, StrikerData};
-use crate::debug::logger::{StrikeLogEntry, LogEntry, Logger};
-use crate::comms::midi_ble::MidiBle;
-use crate::hardware::modifier::{Modifier, ModifierHardwareKind};
-use crate::comms::remote_command::Command;
-use crate::system::system_constants::SYSTEM_CONSTANTS;
+use crate::hardware::striker::{RetriggerPolicy, RetriggerStats, StrikeOutcome, Striker, StrikerData};
+use crate::debug::logger::{
+    timestamp, ConfigurationLogEntry, ErrorLogEntry, LogEntry, Logger, ModifierLogEntry, RetriggerLogEntry, StrikeLogEntry,
+    VerificationLogEntry,
+};
+use crate::comms::cc_mapping::{global_setting, CcMapping, CcTarget};
+use crate::comms::console::Console;
+use crate::comms::midi_ble::{BleConfig, MidiBle};
+use crate::hardware::hit_sensor::HitSensors;
+use crate::hardware::modifier::{Modifier, ModifierData};
+use crate::hardware::striker_hardware_util::HardwareKind;
+use crate::hardware::trigger::{TriggerConfig, TriggerReader};
+use crate::comms::remote_command::{Command, MIDI_CONTROL_CHANGE_BYTE, MIDI_NOTE_OFF_BYTE, MIDI_NOTE_ON_BYTE, MIDI_PROGRAM_CHANGE_BYTE};
+use crate::hardware::output::{self, Output, OutputMode};
+use crate::hardware::pin_address::PinAddress;
+use crate::hardware::watchdog::{Watchdog, WatchdogConfig};
+use crate::playback::articulation::{Articulation, ArticulationData, MAX_ARTICULATION_LENGTH_MS};
+use crate::playback::dynamics::DynamicsConfig;
+use crate::playback::humanize::{shift, HumanizeAmounts, HumanizeConfig, Humanizer};
+use crate::playback::midi_file;
+use crate::playback::recorder::Recorder;
+use crate::playback::scheduler::Scheduler;
+use crate::system::signals::{arm_shutdown_timeout, ShutdownSignals, SHUTDOWN_TIMEOUT};
+use crate::system::file_watcher::{spawn_file_watcher, HotReloadConfig};
+use crate::system::migration::{migrate, Schema, CONFIGURATION_VERSION};
+use crate::system::persistence::{find_backup, restore_backup, save_with_backup, PersistenceConfig};
+use crate::system::system_constants::{get_system_constants, set_system_constants, SystemConstants, DEFAULT_SYSTEM_CONSTANTS_PATH};
+use crate::system::validation::{validate_configuration, ValidationReport};
+
+/// How long to wait for hits that are already in progress to finish when shutting down or exiting
+const PULSE_WAIT_TIMEOUT: Duration = Duration::from_millis(500);
+/// How often to check whether hits in progress have finished
+const PULSE_POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// How often the run loop checks in with the watchdog
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// How remote commands reach the AutoDrum instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Transport {
+    /// Serve the BLE MIDI service
+    Ble,
+    /// Don't listen for remote commands at all (e.g. when firing strikers from the command line)
+    None,
+}
+
+/// Options for creating an AutoDrum instance, usually filled in from the command line
+#[derive(Debug, Clone)]
+pub struct AutoDrumOptions {
+    /// Path of the configuration file to load
+    pub config_path: String,
+    /// Path of the system constants file to load
+    pub constants_path: String,
+    /// Directory that logs are written to, instead of the one in the configuration
+    pub log_dir: Option<String>,
+    /// How remote commands are received
+    pub transport: Transport,
+    /// Simulate outputs instead of driving GPIO pins
+    pub dry_run: bool,
+    /// Whether or not to log every hit, on top of what the configuration logs
+    pub debug: bool,
+    /// Name of the kit to load at startup (the configuration's default kit if None)
+    pub kit: Option<String>,
+    /// Path of a MIDI file to record every hit played to, from startup until shutdown (or until recording is stopped)
+    pub record: Option<String>,
+}
+
+impl Default for AutoDrumOptions {
+    fn default() -> Self {
+        Self {
+            config_path: DEFAULT_CONFIGURATION_PATH.to_string(),
+            constants_path: DEFAULT_SYSTEM_CONSTANTS_PATH.to_string(),
+            log_dir: None,
+            transport: Transport::Ble,
+            dry_run: false,
+            debug: false,
+            kit: None,
+            record: None,
+        }
+    }
+}
 
 
 /// Main application struct
 pub struct AutoDrum {
-    /// The BLE MIDI manager that brings us any relevant MIDI data sent to the BLE MIDI service
-    midi_ble_manager: MidiBle,
+    /// The BLE MIDI manager that brings us any relevant MIDI data sent to the BLE MIDI service (None if BLE is disabled)
+    midi_ble_manager: Option<MidiBle>,
+    /// The channel that all incoming commands are sent over, regardless of where they came from
+    command_tx: broadcast::Sender<Command>,
+    /// The BLE parameters from the configuration, kept so they can be exported even when BLE is disabled
+    ble_config: BleConfig,
+    /// Every kit in the configuration. The active kit's entry is only brought up to date when switching away from it
+    /// or exporting, since the loaded strikers and modifiers may have been tuned since
+    kits: Vec<KitData>,
+    /// The configuration's default kit
+    default_kit: Option<String>,
+    /// Name of the kit whose strikers and modifiers are loaded
+    active_kit: String,
     /// A map of striker names to their respective MIDI note numbers (mainly for linking modifiers to strikers with human-readable names)
     striker_name_to_note: HashMap<String, u8>,
     /// A map of MIDI note numbers to their respective Strikers
@@ -30,23 +117,87 @@ pub struct AutoDrum {
     modifier_targets: HashMap<u8, u8>,
     /// A map of striker note numbers to a vec of their respective modifier note numbers
     striker_modifiers: HashMap<u8, Vec<u8>>,
-    /// Whether or not to collect log data to save on exit
+    /// Reads the sensors of the active kit's strikers, to check their hits land
+    hit_sensors: HitSensors,
+    /// The articulations of the active kit, triggered by name, note or control change
+    articulations: Vec<ArticulationData>,
+    /// Every note-on velocity is multiplied by this before it's played (the master dynamics knob)
+    velocity_scale: f64,
+    /// Compression and limiting applied to every note-on velocity after scaling
+    dynamics: DynamicsConfig,
+    /// MIDI files are played at this multiple of the tempo they're written at (the sequencer tempo)
+    tempo_scale: f64,
+    /// Humanizes the timing and velocity of scheduled notes, and of live notes if enabled for them
+    humanizer: Humanizer,
+    /// MIDI controllers mapped to live parameters
+    cc_mappings: Vec<CcMapping>,
+    /// What controllers without `persist` changed their targets from, so saving can keep those values. Keyed by the
+    /// kit that was active, since striker names are only unique within a kit
+    unsaved_cc_values: HashMap<(String, CcTarget), f64>,
+    /// Whether or not to log every hit, on top of what the configuration logs
     debug: bool,
-    /// The logger that collects and saves log data
-    logger: Logger,
+    /// BLE MIDI timestamp of the note being handled, if it's being played straight from a BLE MIDI packet (for the
+    /// hit log)
+    ble_timestamp: Option<u16>,
+    /// The logger that writes log entries to disk (shared with the tasks that log hits once they finish, and BLE)
+    logger: Arc<Mutex<Logger>>,
+    /// Records every hit played and every modifier change to a MIDI file (None when not recording)
+    recorder: Option<Recorder>,
+    /// Tasks waiting for a hit to finish (or for its sensor's window to pass) so they can log it
+    log_tasks: Vec<JoinHandle<()>>,
+    /// Notes waiting to be played at a later time (rolls, MIDI file playback)
+    scheduler: Scheduler,
+    /// Path of the configuration file, which the configuration is saved back to
+    config_path: String,
+    /// Path of the system constants file, which is re-read when reloading if hot-reload is watching it
+    constants_path: String,
+    /// The hot-reload settings from the configuration
+    hot_reload_config: HotReloadConfig,
+    /// The backup settings from the configuration
+    persistence_config: PersistenceConfig,
+    /// Watches the configuration file for changes while running (None if hot-reload is disabled or not running)
+    file_watcher: Option<JoinHandle<()>>,
+    /// Striker and modifier names offered for tab-completion by the console
+    console_names: Arc<RwLock<Vec<String>>>,
+    /// Set when a Quit command is received, ending the run loop
+    quit_requested: bool,
+    /// Set once the run loop has started, so the inputs it reads are restarted when the configuration changes
+    running: bool,
+    /// The trigger pad settings from the configuration
+    trigger_config: TriggerConfig,
+    /// Reads the trigger pads while running
+    trigger_reader: TriggerReader,
+    /// Forces outputs low if they're held high too long or the run loop stalls (None if disabled)
+    watchdog: Option<Watchdog>,
+    /// The watchdog settings from the configuration
+    watchdog_config: WatchdogConfig,
 }
 
 impl AutoDrum {
-    /// Create a new AutoDrum instance
-    pub async fn new() -> Self {
-        let midi_ble_manager = MidiBle::new().await;
+    /// Create a new AutoDrum instance, refusing to start if the configuration or system constants have any problems
+    pub async fn new(options: AutoDrumOptions) -> Result<Self, Box<dyn Error>> {
+        let constants = SystemConstants::load(&options.constants_path)?;
+        let config = Configuration::load(&options.config_path, &constants)?;
+        set_system_constants(constants);
+        output::set_dry_run(options.dry_run);
+        let (command_tx, _rx) = broadcast::channel::<Command>(120);
+        let logger = Arc::new(Mutex::new(Logger::new(config.logging.clone(), options.log_dir, options.debug)));
+        let midi_ble_manager = match options.transport {
+            Transport::Ble => Some(MidiBle::new(config.ble.clone(), command_tx.clone(), logger.clone()).await),
+            Transport::None => None,
+        };
+        let ble_config = config.ble.clone();
+        let watchdog_config = config.watchdog.clone();
+        let hot_reload_config = config.hot_reload.clone();
+        let persistence_config = config.persistence.clone();
+        let watchdog = watchdog_config.enabled.then(|| Watchdog::start(watchdog_config.clone())).transpose()?;
         let striker_name_to_note = HashMap::new();
         let strikers = HashMap::new();
         let modifiers = HashMap::new();
         let modifier_targets = HashMap::new();
         let striker_modifiers = HashMap::new();
 
-        let debug = env::args().any(|arg| arg == "--debug");
+        let debug = options.debug;
         if debug {
             println!(
                 "\n---------------------------------------------\
@@ -57,16 +208,49 @@ impl AutoDrum {
 
         let mut instance = AutoDrum {
             midi_ble_manager,
+            command_tx,
+            ble_config,
+            kits: vec![],
+            default_kit: None,
+            active_kit: String::new(),
             striker_name_to_note,
             strikers,
             modifiers,
             modifier_targets,
             striker_modifiers,
+            hit_sensors: HitSensors::none(),
+            articulations: vec![],
+            velocity_scale: 1.0,
+            tempo_scale: 1.0,
+            dynamics: DynamicsConfig::default(),
+            humanizer: Humanizer::new(HumanizeConfig::default()),
+            cc_mappings: vec![],
+            unsaved_cc_values: HashMap::new(),
             debug,
-            logger: Logger::new(),
+            ble_timestamp: None,
+            logger,
+            recorder: None,
+            log_tasks: vec![],
+            scheduler: Scheduler::new(),
+            config_path: options.config_path,
+            constants_path: options.constants_path,
+            hot_reload_config,
+            persistence_config,
+            file_watcher: None,
+            console_names: Arc::new(RwLock::new(vec![])),
+            quit_requested: false,
+            running: false,
+            trigger_config: TriggerConfig::default(),
+            trigger_reader: TriggerReader::none(),
+            watchdog,
+            watchdog_config,
         };
-        instance.load_configuration();
-        instance
+        instance.load_configuration(config, options.kit.as_deref())?;
+        instance.log_configuration_change(format!("loaded {} with kit {}", instance.config_path, instance.active_kit));
+        if let Some(path) = options.record.as_deref() {
+            instance.start_recording(Some(path))?;
+        }
+        Ok(instance)
     }
 
     /// Ensure that a given note number is not already in use by a striker or modifier
@@ -79,10 +263,12 @@ impl AutoDrum {
         Ok(())
     }
 
-    /// Ensure that a given name is not already in use by a striker
+    /// Ensure that a given name is not already in use by a striker or modifier
     pub fn enforce_unique_name(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
         if self.striker_name_to_note.contains_key(name) {
             return Err(format!("Striker with name {} already exists", name).into());
+        } else if self.modifiers.values().any(|modifier| modifier.name == name) {
+            return Err(format!("Modifier with name {} already exists", name).into());
         }
         Ok(())
     }
@@ -96,54 +282,305 @@ impl AutoDrum {
         Ok(())
     }
 
+    /// Add the strikers and modifiers of a kit, each modifier given with the name of its target
+    fn insert_kit(&mut self, strikers: Vec<Striker>, modifiers: Vec<(String, Modifier)>) -> Result<(), Box<dyn Error>> {
+        for striker in strikers {
+            self.add_striker(striker)?;
+        }
+        for (target, modifier) in modifiers {
+            self.insert_modifier(&target, modifier)?;
+        }
+        Ok(())
+    }
+
     /// Add a new modifier to the AutoDrum instance
     pub fn add_modifier(
         &mut self,
         target_striker_name: &str,
         note: u8,
-        pin_num: u8,
+        pin_num: PinAddress,
         name: &str,
-        striker_kind: ModifierHardwareKind,
+        striker_kind: HardwareKind,
     ) -> Result<(), Box<dyn Error>> {
         self.enforce_unique_note_num(note)?;
         self.enforce_unique_name(name)?;
-        self.modifiers.insert(note, Modifier::new(name, note, pin_num, striker_kind));
-        self.modifier_targets.insert(note, self.striker_name_to_note.get(target_striker_name).ok_or(format!("No striker with name {} exists", target_striker_name))?.clone());
+        let modifier = Modifier::new(name, note, pin_num, striker_kind)?;
+        self.insert_modifier(target_striker_name, modifier)
+    }
+
+    /// Add an already-built modifier to the AutoDrum instance, linking it to the named striker
+    fn insert_modifier(&mut self, target_striker_name: &str, modifier: Modifier) -> Result<(), Box<dyn Error>> {
+        self.enforce_unique_note_num(modifier.note)?;
+        self.enforce_unique_name(&modifier.name)?;
+        let target_note = *self.striker_name_to_note.get(target_striker_name).ok_or(format!("No striker with name {} exists", target_striker_name))?;
+        self.modifier_targets.insert(modifier.note, target_note);
+        self.striker_modifiers.entry(target_note).or_default().push(modifier.note);
+        self.modifiers.insert(modifier.note, modifier);
         Ok(())
     }
 
-    /// The main loop of the AutoDrum application
-    pub async fn run(&mut self) -> Result<(), Box<dyn Error>> {
-        self.midi_ble_manager.init().await.expect("Task panicked in MidiBle.init()");
-        println!("BLE MIDI service ready. Press enter to quit.");
-        let stdin = BufReader::new(tokio::io::stdin());
-        // Get a stream of lines from stdin
-        let mut lines = stdin.lines();
-        // Get a receiver for the MIDI BLE manager's tx channel so we can receive MIDI events
-        let mut rx = self.midi_ble_manager.tx.subscribe();
-
-        loop {
+    /// Get the note number of the striker with the given name (case-insensitive)
+    pub fn get_striker_note(&self, name: &str) -> Option<u8> {
+        self.striker_name_to_note.iter()
+            .find(|(striker_name, _)| striker_name.eq_ignore_ascii_case(name))
+            .map(|(_, note)| *note)
+    }
+
+    /// Get the note number of the modifier with the given name (case-insensitive)
+    pub fn get_modifier_note(&self, name: &str) -> Option<u8> {
+        self.modifiers.values()
+            .find(|modifier| modifier.name.eq_ignore_ascii_case(name))
+            .map(|modifier| modifier.note)
+    }
+
+    /// Get the configuration of every modifier, sorted by note number
+    pub fn list_modifiers(&self) -> Vec<ModifierData> {
+        let mut modifiers: Vec<ModifierData> = self.modifiers.values().map(|modifier| {
+            let target_note = self.modifier_targets[&modifier.note];
+            modifier.export_raw(&self.strikers[&target_note].get_name())
+        }).collect();
+        modifiers.sort_by_key(|modifier| modifier.note);
+        modifiers
+    }
+
+    /// Get the configuration of every striker, sorted by note number
+    pub fn list_strikers(&self) -> Vec<StrikerData> {
+        let mut strikers: Vec<StrikerData> = self.strikers.values().map(|striker| striker.export_raw()).collect();
+        strikers.sort_by_key(|striker| striker.note);
+        strikers
+    }
+
+    /// Fire the striker (or modified striker) with the given name, as if a note-on for it had been received, or play
+    /// the articulation with the given name
+    pub async fn fire(&mut self, name: &str, velocity: u8) -> Result<(), Box<dyn Error>> {
+        if let Some(articulation) = self.articulations.iter().find(|articulation| articulation.name.eq_ignore_ascii_case(name)).cloned() {
+            return self.articulate(&articulation.striker, &articulation.articulation, velocity);
+        }
+        let note = self.get_striker_note(name)
+            .or_else(|| self.get_modifier_note(name))
+            .ok_or(format!("No striker or modifier with name {} exists", name))?;
+        self.handle_note((MIDI_NOTE_ON_BYTE, note, velocity)).await
+    }
+
+    /// Schedule a number of hits on the named striker as sixteenth notes at the given tempo
+    pub fn roll(&mut self, name: &str, hits: u32, bpm: f64, velocity: u8) -> Result<(), Box<dyn Error>> {
+        self.articulate(name, &Articulation::Roll { strokes: hits, rate: bpm / 60.0 * 4.0 }, velocity)
+    }
+
+    /// Schedule the strokes of an articulation on the named striker, spaced so each stroke has finished and the
+    /// striker has recovered before the next
+    pub fn articulate(&mut self, name: &str, articulation: &Articulation, velocity: u8) -> Result<(), Box<dyn Error>> {
+        let note = self.get_striker_note(name).ok_or(format!("No striker with name {} exists", name))?;
+        // Articulations from the console or a remote haven't been through validation
+        articulation.check()?;
+        let amounts = self.humanize_amounts(note);
+        let striker = &self.strikers[&note];
+        let strokes = articulation.strokes(velocity, |velocity| striker.min_stroke_interval(velocity));
+        // Recovery time can stretch an articulation well past what its settings ask for
+        let length = strokes.last().map(|stroke| stroke.offset).unwrap_or_default();
+        if length.as_secs_f64() * 1000.0 > MAX_ARTICULATION_LENGTH_MS {
+            return Err(format!(
+                "{:?} would take {:.1}s on {}, longer than the {}ms an articulation may last",
+                articulation, length.as_secs_f64(), striker.get_name(), MAX_ARTICULATION_LENGTH_MS,
+            ).into());
+        }
+        let start = tokio::time::Instant::now();
+        let start_ms = self.humanizer.elapsed_ms();
+        let mut previous: Option<(Duration, u8)> = None;
+        for stroke in strokes {
+            let stroke_ms = start_ms + stroke.offset.as_secs_f64() * 1000.0;
+            let (offset, velocity) = self.humanizer.humanize(amounts, stroke_ms, None, stroke.velocity);
+            let mut at = shift(stroke.offset, offset);
+            // Humanizing mustn't bring a stroke closer to the last than the striker can recover in
+            if let Some((last_at, last_velocity)) = previous {
+                at = at.max(last_at.saturating_add(striker.min_stroke_interval(last_velocity)));
+            }
+            let at_time = start.checked_add(at).ok_or(format!("{:?} runs too far into the future to schedule", articulation))?;
+            self.scheduler.schedule(at_time, (MIDI_NOTE_ON_BYTE, note, velocity));
+            previous = Some((at, velocity));
+        }
+        Ok(())
+    }
+
+    /// Play the notes of a Standard MIDI File in real time, then return
+    pub async fn play(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let notes = midi_file::read_notes(path)?;
+        println!("Playing {} note events from {}", notes.len(), path);
+        let start = tokio::time::Instant::now();
+        for note in notes {
+            let (status, number, velocity) = note.midi_data;
+            if status == MIDI_NOTE_ON_BYTE && velocity > 0 {
+                // Timed from the start of the file, so a seeded run drifts the same way every time
+                let time_ms = note.time.as_secs_f64() * 1000.0;
+                let amounts = self.humanize_amounts(number);
+                let (offset, velocity) = self.humanizer.humanize(amounts, time_ms, note.sixteenth, velocity);
+                self.scheduler.schedule_sequenced(start + shift(note.time.div_f64(self.tempo_scale), offset), (status, number, velocity));
+            } else {
+                self.scheduler.schedule_sequenced(start + note.time.div_f64(self.tempo_scale), note.midi_data);
+            }
+        }
+        // MIDI from the transport (e.g. a tempo controller) is handled while the file plays
+        self.start_transport().await;
+        self.play_scheduled().await?;
+        // Let the last hits ring out before shutting down
+        self.wait_for_pulses(PULSE_WAIT_TIMEOUT).await;
+        self.shutdown().await
+    }
+
+    /// Play everything scheduled in real time, returning once nothing is left, a Quit command or a shutdown signal
+    /// is received. Commands that come in meanwhile are handled as they would be by the run loop, so controllers
+    /// (e.g. the sequencer tempo) change what's left as it plays
+    pub async fn play_scheduled(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut rx = self.command_tx.subscribe();
+        let mut signals = ShutdownSignals::new()?;
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        while let Some(deadline) = self.scheduler.next_deadline() {
+            if self.quit_requested {
+                break;
+            }
             tokio::select! {
-                // If we get a line from stdin, exit the program
-                _ = lines.next_line() => {
-                    if self.debug {
-                        self.logger.save().await?;
+                read_res = rx.recv() => {
+                    match read_res {
+                        Ok(command) => {
+                            if let Err(e) = self.route_command(&command).await {
+                                self.report_error(format!("Error handling {:?}: {}", command, e));
+                            }
+                        },
+                        Err(e) => {
+                            eprintln!("Error: {:?}", e)
+                        }
                     }
+                },
+                _ = tokio::time::sleep_until(deadline) => self.play_due_notes().await?,
+                _ = heartbeat.tick() => self.heartbeat(),
+                signal = signals.recv() => {
+                    println!("Received {}, stopping playback", signal);
+                    arm_shutdown_timeout(SHUTDOWN_TIMEOUT);
                     break;
                 },
-                // If we get a command from the MIDI BLE manager, route it to the appropriate handler
+            }
+        }
+        Ok(())
+    }
+
+    /// The main loop of the AutoDrum application
+    pub async fn run(&mut self) -> Result<(), Box<dyn Error>> {
+        // Get a receiver for the command channel before starting any transports so we can't miss anything
+        let mut rx = self.command_tx.subscribe();
+        if self.start_transport().await {
+            println!("Type help for a list of commands, or quit to exit.");
+        } else {
+            println!("No transport enabled. Type help for a list of commands, or quit to exit.");
+        }
+        // Read commands from stdin, sending them over the same channel as remote commands
+        Console::spawn(self.command_tx.clone(), self.console_names.clone());
+        self.running = true;
+        self.restart_file_watcher();
+        self.restart_triggers();
+        let mut signals = ShutdownSignals::new()?;
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+        while !self.quit_requested {
+            let next_deadline = self.scheduler.next_deadline();
+            tokio::select! {
+                // If we get a command from the console or a remote, route it to the appropriate handler
                 read_res = rx.recv() => {
                     match read_res {
                         Ok(command) => {
-                            self.route_command(&command).await?
+                            // A bad command shouldn't bring the whole drum down, so just report it
+                            if let Err(e) = self.route_command(&command).await {
+                                self.report_error(format!("Error handling {:?}: {}", command, e));
+                            }
                         },
                         Err(e) => {
                             eprintln!("Error: {:?}", e)
                         }
                     }
-                }
+                },
+                // If a scheduled note is due, play it
+                _ = tokio::time::sleep_until(next_deadline.unwrap_or_else(tokio::time::Instant::now)), if next_deadline.is_some() => {
+                    self.play_due_notes().await?;
+                },
+                // Let the watchdog know we're still alive
+                _ = heartbeat.tick() => self.heartbeat(),
+                // If we're asked to shut down (Ctrl-C, systemd stopping the service), do so gracefully
+                signal = signals.recv() => {
+                    println!("Received {}, shutting down", signal);
+                    // Make sure a hung task can't keep us (and a coil) running forever
+                    arm_shutdown_timeout(SHUTDOWN_TIMEOUT);
+                    break;
+                },
+            }
+        }
+        self.shutdown().await
+    }
+
+    /// Start receiving MIDI and remote commands over the transport, returning whether there is one
+    async fn start_transport(&mut self) -> bool {
+        let Some(midi_ble_manager) = self.midi_ble_manager.as_mut() else { return false };
+        midi_ble_manager.init().await.expect("Task panicked in MidiBle.init()");
+        println!("BLE MIDI service ready.");
+        true
+    }
+
+    /// Play every scheduled note that is due
+    async fn play_due_notes(&mut self) -> Result<(), Box<dyn Error>> {
+        for note in self.scheduler.pop_due(tokio::time::Instant::now()) {
+            self.handle_note(note.midi_data).await?;
+        }
+        Ok(())
+    }
+
+    /// Let the watchdog know the control loop is still running
+    fn heartbeat(&self) {
+        if let Some(watchdog) = self.watchdog.as_ref() {
+            watchdog.heartbeat();
+        }
+    }
+
+    /// Wait until no striker is mid-hit, or the timeout runs out. Returns whether every hit finished
+    pub async fn wait_for_pulses(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.strikers.values().any(|striker| striker.is_active()) {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(PULSE_POLL_INTERVAL).await;
+        }
+        true
+    }
+
+    /// Shut down gracefully: stop taking remote input, cancel anything scheduled, turn every output off, wait for
+    /// hits in progress to end and be logged, then flush the log
+    pub async fn shutdown(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(midi_ble_manager) = self.midi_ble_manager.as_mut() {
+            midi_ble_manager.shutdown();
+        }
+        if let Some(file_watcher) = self.file_watcher.take() {
+            file_watcher.abort();
+        }
+        self.trigger_reader = TriggerReader::none();
+        let cancelled = self.scheduler.clear();
+        if cancelled > 0 {
+            println!("Cancelled {} scheduled notes", cancelled);
+        }
+        self.stop();
+        if !self.wait_for_pulses(PULSE_WAIT_TIMEOUT).await {
+            eprintln!("Hits still in progress after {:?}", PULSE_WAIT_TIMEOUT);
+        }
+        // Give hits that just finished a chance to be logged
+        for log_task in self.log_tasks.drain(..) {
+            let _ = tokio::time::timeout(PULSE_WAIT_TIMEOUT, log_task).await;
+        }
+        if self.recorder.is_some() {
+            if let Err(e) = self.stop_recording() {
+                self.report_error(e.to_string());
             }
         }
+        self.logger.lock().unwrap().flush();
+        if let Some(watchdog) = self.watchdog.as_mut() {
+            watchdog.stop();
+        }
         Ok(())
     }
 
@@ -153,15 +590,28 @@ impl AutoDrum {
             Command::MIDI(new_value) => self.handle_midi_command(new_value).await?,
             Command::ReadSystemConstants(new_value) => self.handle_read_system_constants_command(new_value)?,
             Command::ReadConfiguration(new_value) => self.handle_read_configuration_command(new_value)?,
-            Command::WriteConfiguration(new_value) => {
-                println!("Received write configuration command: {:?}", new_value);
-                // if &new_value.first().unwrap().clone() == &WRITE_CONFIG_COMMAND_BYTE {
-                //     response_value.lock().unwrap().clear();
-                //     let return_value = vec![0x10, 0x01, 0x01, 0x10];
-                //     response_value.lock().unwrap().extend(return_value);
-                //     return Ok(());
-                // }
+            Command::WriteBleConfiguration(new_value) => self.handle_write_ble_configuration_command(new_value).await?,
+            Command::Trigger { note, velocity } => self.handle_trigger(*note, *velocity).await?,
+            Command::Fire { name, velocity } => self.fire(name, *velocity).await?,
+            Command::Roll { name, hits, bpm, velocity } => self.roll(name, *hits, *bpm, *velocity)?,
+            Command::Articulate { striker, articulation, velocity } => self.articulate(striker, articulation, *velocity)?,
+            Command::SetModifier { name, active } => self.set_modifier(name, *active)?,
+            Command::SetParameter { target, parameter, value } => self.set_striker_parameter(target, parameter, *value)?,
+            Command::SaveConfiguration => {
+                let path = self.config_path.clone();
+                self.save_configuration_file(&path)?;
+                println!("Configuration saved to {}", path);
+                self.log_configuration_change(format!("saved to {}", path));
             },
+            Command::ReloadConfiguration => self.reload_configuration().await?,
+            Command::RollbackConfiguration { steps } => self.rollback_configuration(*steps).await?,
+            Command::SwitchKit { name } => self.switch_kit(name).await?,
+            Command::StartRecording { path } => self.start_recording(path.as_deref())?,
+            Command::StopRecording => self.stop_recording()?,
+            Command::Status => self.print_status(),
+            Command::Panic => self.panic(),
+            Command::Quit => self.quit_requested = true,
+            Command::WriteConfiguration(new_value) => self.handle_write_configuration_command(new_value).await?,
         }
         Ok(())
     }
@@ -176,24 +626,43 @@ impl AutoDrum {
         println!("Received MIDI command: {:?}", message_data);
         let mut last_status: u8 = 0x00;
         let mut midi_data: Vec<u8> = vec![];
+        let header = *message_data.first().unwrap();
+        let mut timestamp = None;
         // iterate bytes (adding first status byte to end as they trigger send of previous data)
-        for byte in message_data.iter().chain([message_data.first().unwrap()]) {
+        for (i, byte) in message_data.iter().chain([message_data.first().unwrap()]).enumerate() {
             // if the byte is a status or timestamp byte (non-data):
             if MidiBle::is_status_byte(*byte) {
                 // if we just finished a note-on or note-off message group, send them over tx
                 if !midi_data.is_empty() {
-                    if last_status == 0x90 || last_status == 0x80 {
+                    if last_status & 0xF0 == MIDI_PROGRAM_CHANGE_BYTE {
+                        // Each program number selects the kit at that position in the configuration, on any channel
+                        for program in midi_data.clone() {
+                            let name = self.kits.get(program as usize).ok_or(format!("No kit for program {}", program))?.name.clone();
+                            self.switch_kit(&name).await?;
+                        }
+                    } else if last_status & 0xF0 == MIDI_CONTROL_CHANGE_BYTE {
+                        for pair in midi_data.chunks(2).filter(|pair| pair.len() == 2) {
+                            self.handle_control_change(last_status & 0x0F, pair[0], pair[1]).await?;
+                        }
+                    } else if last_status == 0x90 || last_status == 0x80 {
                         // split midi data into chunks of 2 bytes (note number and velocity) and send over tx (to be handled by AutoDrum)
                         for pair in midi_data.chunks(2) {
                             let note_number = pair[0];
                             let velocity = pair[1];
-                            self.handle_note(
+                            self.ble_timestamp = timestamp;
+                            let handled = self.handle_live_note(
                                 (last_status, note_number, velocity)
-                            ).await?;
+                            ).await;
+                            self.ble_timestamp = None;
+                            handled?;
                         }
                     }
                     midi_data.clear();
                 }
+                // A timestamp byte comes straight after the header or a message's data, and a status byte after that
+                if i == 1 || (i > 1 && !MidiBle::is_status_byte(message_data[i - 1])) {
+                    timestamp = Some(MidiBle::message_timestamp(header, *byte));
+                }
                 last_status = *byte;
             } else {
                 midi_data.push(*byte);
@@ -202,20 +671,252 @@ impl AutoDrum {
         Ok(())
     }
 
+    /// Send a hit from a trigger pad out over BLE MIDI and play it on the strikers, as the trigger settings ask. It's
+    /// sent first so playing it doesn't hold it up
+    async fn handle_trigger(&mut self, note: u8, velocity: u8) -> Result<(), Box<dyn Error>> {
+        if self.trigger_config.send_ble {
+            if let Some(midi_ble_manager) = self.midi_ble_manager.as_ref() {
+                let channel = self.trigger_config.channel.saturating_sub(1);
+                midi_ble_manager.notify(&[(MIDI_NOTE_ON_BYTE | channel, note, velocity), (MIDI_NOTE_OFF_BYTE | channel, note, 0)]);
+            }
+        }
+        if self.trigger_config.play_locally {
+            self.handle_live_note((MIDI_NOTE_ON_BYTE, note, velocity)).await?;
+        }
+        Ok(())
+    }
+
     fn handle_read_system_constants_command(&mut self, value: &Vec<u8>) -> Result<(), Box<dyn Error>> {
         println!("Received read system constants command: {:?}", value);
-        let stringified_const_map = serde_json::to_string(&SYSTEM_CONSTANTS.clone())?;
-        self.midi_ble_manager.send(&stringified_const_map)
+        let stringified_const_map = serde_json::to_string(&get_system_constants()?)?;
+        self.respond(&stringified_const_map)
     }
 
     /// Collect & serialize the current configuration of the AutoDrum instance then send it over BLE
     fn handle_read_configuration_command(&mut self, value: &Vec<u8>) -> Result<(), Box<dyn Error>> {
         println!("Received read configuration command: {:?}", value);
-        let config = Configuration {
-            strikers: self.strikers.iter().map(|(_, striker)| striker.export_raw()).collect(),
-        };
+        let mut config = self.export_configuration();
+        config.active_kit = Some(self.active_kit.clone());
         let stringified_config = serde_json::to_string(&config)?;
-        self.midi_ble_manager.send(&stringified_config)
+        self.respond(&stringified_config)
+    }
+
+    /// Parse a new configuration from the command payload and apply it, responding with the validation report
+    /// (which lists every problem if the configuration was rejected)
+    async fn handle_write_configuration_command(&mut self, value: &Vec<u8>) -> Result<(), Box<dyn Error>> {
+        println!("Received write configuration command: {:?}", value);
+        let report = self.write_configuration(Command::payload(value)).await;
+        println!("{}", report);
+        self.respond(&serde_json::to_string(&report)?)
+    }
+
+    /// Apply a configuration written by a remote (JSON), if it's valid, returning the report to send back. The
+    /// remote always gets a report, including when a valid configuration can't be applied
+    async fn write_configuration(&mut self, payload: &[u8]) -> ValidationReport {
+        let source = "remote configuration";
+        // Configurations from older apps are migrated just like older files
+        let parsed = serde_json::from_slice::<serde_yaml::Value>(payload)
+            .map_err(|e| e.to_string())
+            .and_then(|mut value| migrate(&mut value, Schema::Configuration).map(|_| value))
+            .and_then(|value| serde_yaml::from_value::<Configuration>(value).map_err(|e| e.to_string()));
+        match parsed.and_then(|config| get_system_constants().map(|constants| (config, constants))) {
+            Ok((config, constants)) => {
+                let mut report = validate_configuration(source, &config, &constants);
+                if report.is_valid() {
+                    match self.apply_configuration(config).await {
+                        Ok(()) => self.log_configuration_change("applied a remote configuration".to_string()),
+                        Err(e) => report.push("", format!("unable to apply: {}", e)),
+                    }
+                }
+                report
+            },
+            Err(e) => ValidationReport::with_issue(source, "", e),
+        }
+    }
+
+    /// Parse a new set of BLE parameters from the command payload and re-advertise with them
+    async fn handle_write_ble_configuration_command(&mut self, value: &Vec<u8>) -> Result<(), Box<dyn Error>> {
+        println!("Received write BLE configuration command: {:?}", value);
+        let ble_config: BleConfig = serde_json::from_slice(Command::payload(value))?;
+        ble_config.validate()?;
+        if let Some(midi_ble_manager) = self.midi_ble_manager.as_mut() {
+            midi_ble_manager.apply_config(ble_config.clone()).await?;
+        }
+        self.ble_config = ble_config;
+        Ok(())
+    }
+
+    /// Activate or deactivate the modifier with the given name
+    fn set_modifier(&mut self, name: &str, active: bool) -> Result<(), Box<dyn Error>> {
+        let note = self.get_modifier_note(name).ok_or(format!("No modifier with name {} exists", name))?;
+        self.switch_modifier(note, active);
+        Ok(())
+    }
+
+    /// Change a parameter of the striker with the given name
+    fn set_striker_parameter(&mut self, target: &str, parameter: &str, value: f64) -> Result<(), Box<dyn Error>> {
+        let note = self.get_striker_note(target).ok_or(format!("No striker with name {} exists", target))?;
+        let striker = self.strikers.get_mut(&note).unwrap();
+        striker.set_parameter(parameter, value)?;
+        let change = format!("set {} of {} to {}", parameter, striker.get_name(), value);
+        let target = CcTarget::for_striker_parameter(striker.get_name(), parameter).unwrap();
+        // A value set by hand is kept when saving, even if a controller had changed it
+        self.unsaved_cc_values.retain(|(kit, cc_target), _| *kit != self.active_kit || *cc_target != target);
+        self.log_configuration_change(change);
+        Ok(())
+    }
+
+    /// Switch a modifier on or off, logging and recording the change if it wasn't already in that state
+    fn switch_modifier(&mut self, note: u8, active: bool) {
+        let Some(modifier) = self.modifiers.get_mut(&note) else { return };
+        let changed = modifier.is_active() != active;
+        if active { modifier.activate() } else { modifier.deactivate() }
+        if changed {
+            let entry = LogEntry::Modifier(ModifierLogEntry {
+                time: timestamp(),
+                modifier_name: modifier.name.clone(),
+                note_num: note,
+                active,
+            });
+            self.log(entry);
+            let target = self.modifier_targets.get(&note).and_then(|target| self.strikers.get(target));
+            if let (Some(recorder), Some(striker)) = (self.recorder.as_mut(), target) {
+                recorder.record_modifier(&striker.get_name(), striker.get_note_num(), note, active, Instant::now());
+            }
+        }
+    }
+
+    /// Start recording every hit played and every modifier change, to save to a MIDI file when recording stops
+    fn start_recording(&mut self, path: Option<&str>) -> Result<(), Box<dyn Error>> {
+        if let Some(recorder) = &self.recorder {
+            return Err(format!("Already recording to {}", recorder.path().display()).into());
+        }
+        let recorder = Recorder::start(path);
+        println!("Recording to {}", recorder.path().display());
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    /// Stop recording and save what was recorded
+    fn stop_recording(&mut self) -> Result<(), Box<dyn Error>> {
+        let recorder = self.recorder.take().ok_or("Not recording")?;
+        recorder.save()?;
+        println!("Saved {} hits over {:.1}s to {}", recorder.hits(), recorder.elapsed().as_secs_f64(), recorder.path().display());
+        Ok(())
+    }
+
+    /// Print the state of every striker and modifier
+    fn print_status(&self) {
+        let kit_names: Vec<&str> = self.kits.iter().map(|kit| kit.name.as_str()).collect();
+        println!("Kit: {} (available: {})", self.active_kit, kit_names.join(", "));
+        println!("Strikers:");
+        for striker in self.list_strikers() {
+            println!(
+                "  {} (note {}, pin {}, {}): {}-{}ms, {}",
+                striker.name, striker.note, striker.pin, striker.kind,
+                striker.min_hit_duration.unwrap(), striker.max_hit_duration.unwrap(),
+                if self.strikers[&striker.note].is_active() { "active" } else { "idle" },
+            );
+            let stats = self.strikers[&striker.note].get_retrigger_stats();
+            if stats != RetriggerStats::default() {
+                println!(
+                    "    retrigger {:?}: {} dropped, {} queued",
+                    striker.retrigger.unwrap_or_default(), stats.dropped, stats.queued,
+                );
+            }
+            if let Some(stats) = self.hit_sensors.stats(striker.note) {
+                let latency = match (stats.mean_latency_ms(), stats.min_latency_ms, stats.max_latency_ms) {
+                    (Some(mean), Some(min), Some(max)) => format!("{:.1}ms average ({:.1}-{:.1}ms)", mean, min, max),
+                    _ => "not measured yet".to_string(),
+                };
+                println!("    sensor: {} verified, {} missed, latency {}", stats.verified, stats.missed, latency);
+            }
+        }
+        println!("Modifiers:");
+        for modifier in self.list_modifiers() {
+            println!(
+                "  {} (note {}, pin {}) -> {}: {}",
+                modifier.name, modifier.note, modifier.pin, modifier.target,
+                if self.modifiers[&modifier.note].is_active() { "on" } else { "off" },
+            );
+        }
+        if !self.articulations.is_empty() {
+            println!("Articulations:");
+            for articulation in &self.articulations {
+                let triggers: Vec<String> = articulation.note.map(|note| format!("note {}", note)).into_iter()
+                    .chain(articulation.cc.map(|cc| format!("CC {}", cc)))
+                    .collect();
+                println!("  {} ({}) -> {}: {:?}", articulation.name, triggers.join(", "), articulation.striker, articulation.articulation);
+            }
+        }
+        println!(
+            "Velocity: scale {}, compression above {} at {}:1, ceiling {}",
+            self.velocity_scale, self.dynamics.threshold, self.dynamics.ratio, self.dynamics.ceiling,
+        );
+        println!("Tempo: {}x as written", self.tempo_scale);
+        let humanize = self.humanizer.config();
+        if humanize.enabled {
+            println!(
+                "Humanize: amount {}, timing jitter {}ms, drift {}ms over {}ms, velocity variation {}{}{}",
+                humanize.amount, humanize.amounts.timing_jitter, humanize.amounts.timing_drift, humanize.drift_period,
+                humanize.amounts.velocity_variation,
+                if humanize.groove.is_empty() { String::new() } else { format!(", groove {:?}", humanize.groove) },
+                if humanize.live_input { ", live input too" } else { "" },
+            );
+        } else {
+            println!("Humanize: off");
+        }
+        if !self.trigger_config.pads.is_empty() {
+            let pads: Vec<String> = self.trigger_config.pads.iter().map(|pad| format!("{} (note {})", pad.name, pad.note)).collect();
+            let destinations: Vec<&str> = [(self.trigger_config.play_locally, "strikers"), (self.trigger_config.send_ble, "BLE")]
+                .into_iter()
+                .filter_map(|(enabled, destination)| enabled.then_some(destination))
+                .collect();
+            println!(
+                "Triggers: {} ({}) -> {}",
+                pads.join(", "),
+                if self.trigger_reader.pads() > 0 { "reading" } else { "not read" },
+                if destinations.is_empty() { "nowhere".to_string() } else { destinations.join(", ") },
+            );
+        }
+        println!("Scheduled notes: {}", self.scheduler.len());
+        match &self.midi_ble_manager {
+            Some(_) => println!("BLE: advertising as {}", self.ble_config.device_name),
+            None => println!("BLE: disabled"),
+        }
+        println!("Watchdog: {}", if self.watchdog.is_some() { "on" } else { "off" });
+        match &self.recorder {
+            Some(recorder) => println!(
+                "Recording: {} ({} hits over {:.1}s)", recorder.path().display(), recorder.hits(), recorder.elapsed().as_secs_f64(),
+            ),
+            None => println!("Recording: off"),
+        }
+        let logger = self.logger.lock().unwrap();
+        match (logger.is_enabled(), logger.current_file()) {
+            (false, _) => println!("Logging: off"),
+            (true, Some(file)) => println!("Logging: {}{}", file.display(), if self.debug { ", every hit" } else { "" }),
+            (true, None) => println!(
+                "Logging: to {} (nothing logged yet){}", logger.directory().display(), if self.debug { ", every hit" } else { "" },
+            ),
+        }
+    }
+
+    /// Cancel every scheduled note and force every output off
+    pub fn panic(&mut self) {
+        let cancelled = self.scheduler.clear();
+        self.stop();
+        println!("Panic: cancelled {} scheduled notes and turned every output off", cancelled);
+    }
+
+    /// Send a response to the remote, or print it if there is no transport to send it over
+    fn respond(&mut self, data: &str) -> Result<(), Box<dyn Error>> {
+        match self.midi_ble_manager.as_mut() {
+            Some(midi_ble_manager) => midi_ble_manager.send(data),
+            None => {
+                println!("{}", data);
+                Ok(())
+            }
+        }
     }
 
 
@@ -223,9 +924,103 @@ impl AutoDrum {
     // MIDI HANDLERS (downstream of handle_midi_command)
     //--------------------------------------------------------------------------------
 
+    /// Handle a control change on the given channel (0-15): adjust whatever the controller is mapped to, and play
+    /// any articulation mapped to it with the controller value as the velocity
+    async fn handle_control_change(&mut self, channel: u8, cc: u8, value: u8) -> Result<(), Box<dyn Error>> {
+        let mappings: Vec<CcMapping> = self.cc_mappings.iter().filter(|mapping| mapping.matches(channel, cc)).cloned().collect();
+        for mapping in mappings {
+            self.apply_cc_mapping(&mapping, value).await?;
+        }
+        if value > 0 {
+            if let Some(articulation) = self.articulations.iter().find(|articulation| articulation.cc == Some(cc)).cloned() {
+                self.articulate(&articulation.striker, &articulation.articulation, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the target of a controller mapping from a controller value, scaled into the mapping's range and clamped
+    /// to what the target allows. Strikers that aren't in the active kit are left alone
+    async fn apply_cc_mapping(&mut self, mapping: &CcMapping, value: u8) -> Result<(), Box<dyn Error>> {
+        if let Some((striker, parameter)) = mapping.target.striker_parameter() {
+            let Some(note) = self.get_striker_note(striker) else { return Ok(()) };
+            let striker = self.strikers.get_mut(&note).unwrap();
+            let (previous, bounds) = striker.get_parameter(parameter)?;
+            striker.set_parameter(parameter, mapping.scale(value, bounds))?;
+            // Recorded with the striker's own name, so it matches however the mapping spelled it
+            let target = CcTarget::for_striker_parameter(striker.get_name(), parameter).unwrap();
+            self.record_unsaved_cc_value(mapping, target, previous);
+        } else if let Some((setting, bounds)) = global_setting(
+            &mapping.target, &mut self.velocity_scale, &mut self.dynamics, self.humanizer.amount_mut(), &mut self.tempo_scale,
+        ) {
+            let previous = std::mem::replace(setting, mapping.scale(value, bounds));
+            if mapping.target == CcTarget::Tempo {
+                // What's left of a MIDI file being played speeds up or slows down from here
+                self.scheduler.retime(previous / self.tempo_scale, tokio::time::Instant::now());
+            }
+            self.record_unsaved_cc_value(mapping, mapping.target.clone(), previous);
+        } else if let CcTarget::Kit = mapping.target {
+            let Some(kit) = self.kits.get(value as usize * self.kits.len() / 128) else { return Ok(()) };
+            let name = kit.name.clone();
+            if name != self.active_kit {
+                self.switch_kit(&name).await?;
+            }
+            if mapping.persist {
+                self.default_kit = Some(name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remember what a controller without `persist` changed its target from, the first time it changes it
+    fn record_unsaved_cc_value(&mut self, mapping: &CcMapping, target: CcTarget, previous: f64) {
+        if !mapping.persist {
+            self.unsaved_cc_values.entry((self.active_kit.clone(), target)).or_insert(previous);
+        }
+    }
+
+    /// Run a note-on velocity for a striker through velocity processing: the striker's gain and offset, the global
+    /// velocity scale (the master dynamics knob), then compression and the ceiling
+    fn process_velocity(&self, striker_note: u8, velocity: u8) -> u8 {
+        let trimmed = self.strikers.get(&striker_note).map_or(velocity as f64, |striker| striker.trim_velocity(velocity));
+        self.dynamics.apply(trimmed * self.velocity_scale)
+    }
+
+    /// Get the humanize amounts for a note, with the changes of the striker it plays (or modifies) applied
+    fn humanize_amounts(&self, note: u8) -> HumanizeAmounts {
+        let striker_note = self.modifier_targets.get(&note).copied().unwrap_or(note);
+        let overrides = self.strikers.get(&striker_note).and_then(|striker| striker.get_humanize_overrides());
+        self.humanizer.config().amounts.with_overrides(overrides)
+    }
+
+    /// Handle a note-on or note-off event from live input, humanizing note-ons if that's enabled for live input.
+    /// A live note can't be played early, so one humanized to be early is played straight away
+    async fn handle_live_note(&mut self, midi_data: (u8, u8, u8)) -> Result<(), Box<dyn Error>> {
+        let (status, note, velocity) = midi_data;
+        if status != MIDI_NOTE_ON_BYTE || velocity == 0 || !self.humanizer.applies_to_live_input() {
+            return self.handle_note(midi_data).await;
+        }
+        let amounts = self.humanize_amounts(note);
+        let time_ms = self.humanizer.elapsed_ms();
+        let (offset, velocity) = self.humanizer.humanize(amounts, time_ms, None, velocity);
+        let delay = shift(Duration::ZERO, offset);
+        if delay.is_zero() {
+            self.handle_note((status, note, velocity)).await
+        } else {
+            self.scheduler.schedule(tokio::time::Instant::now() + delay, (status, note, velocity));
+            Ok(())
+        }
+    }
+
     /// Handle a note-on or note-off event
     pub async fn handle_note(&mut self, midi_data: (u8, u8, u8)) -> Result<(), Box<dyn Error>> {
         let (status, note, velocity) = midi_data;
+        // If it's a note on event for an articulation, play it
+        if status == 0x90 && velocity > 0 {
+            if let Some(articulation) = self.articulations.iter().find(|articulation| articulation.note == Some(note)).cloned() {
+                return self.articulate(&articulation.striker, &articulation.articulation, velocity);
+            }
+        }
         // If it's a note on event, trigger the striker
         if status == 0x90 {
             if !self.debug {
@@ -237,67 +1032,187 @@ impl AutoDrum {
         }
         // If it's a note off event and the note is a modifier, deactivate it
         else if status == 0x80 {
-            if let Some(modifier) = self.modifiers.get_mut(&note) {
-                modifier.deactivate();
-            }
+            self.switch_modifier(note, false);
         }
         Ok(())
     }
 
     /// Trigger a striker, activating any modifiers linked to the given note in tandem
     pub async fn hit(&mut self, note: u8, velocity: u8) -> Result<(), Box<dyn Error>> {
+        // Queued hits keep the velocity as it came in, so they aren't processed twice
+        let striker_note = self.modifier_targets.get(&note).copied().unwrap_or(note);
+        let processed_velocity = self.process_velocity(striker_note, velocity);
+        let time = timestamp();
+        let fired = Instant::now();
         // If firing a striker directly, not a modified version of it:
-        if let Some(striker) = self.strikers.get_mut(&note) {
+        if self.strikers.contains_key(&note) {
             // Deactivate any modifiers that are currently active for this striker
-            if self.striker_modifiers.contains_key(&note) {
-                for modifier_note in self.striker_modifiers.get(&note).unwrap() {
-                    if let Some(modifier) = self.modifiers.get_mut(modifier_note) {
-                        modifier.deactivate();
-                    }
-                }
+            for modifier_note in self.striker_modifiers.get(&note).cloned().unwrap_or_default() {
+                self.switch_modifier(modifier_note, false);
             }
             // Fire the striker
-            striker.strike(velocity).await?;
+            let outcome = self.strike(note, note, velocity, processed_velocity)?;
+            self.queue_if_busy(&outcome, (MIDI_NOTE_ON_BYTE, note, velocity));
+            self.verify_hit(note, velocity, &outcome, fired);
+            self.record_hit(note, processed_velocity, &outcome, fired);
+            self.log_hit(note, velocity, processed_velocity, &outcome, time);
         }
         // If firing with a modifier:
-        else if let Some(modifier) = self.modifiers.get_mut(&note) {
-            if let Some(target_note) = self.modifier_targets.get(&note) {
-                if let Some(striker) = self.strikers.get_mut(target_note) {
+        else if self.modifiers.contains_key(&note) {
+            if let Some(target_note) = self.modifier_targets.get(&note).copied() {
+                if self.strikers.contains_key(&target_note) {
                     // Activate the modifier, then fire the striker, then start a timer to deactivate the modifier
-                    modifier.activate();
+                    self.switch_modifier(note, true);
                     // TODO: May need to add a delay here to ensure the modifier has time to activate before the striker is fired
                     // modifier.start_deactivation_timer() // May need to add this back in
-                    striker.strike(velocity).await?;
+                    let outcome = self.strike(target_note, note, velocity, processed_velocity)?;
+                    self.queue_if_busy(&outcome, (MIDI_NOTE_ON_BYTE, note, velocity));
+                    self.verify_hit(note, velocity, &outcome, fired);
+                    self.record_hit(note, processed_velocity, &outcome, fired);
+                    self.log_hit(note, velocity, processed_velocity, &outcome, time);
                 }
             }
         }
         Ok(())
     }
 
-    /// Fire a striker, logging data about the hit
-    /// TODO: add modifiers to logging once they're fully implemented
-    pub async fn hit_with_debug(&mut self, note: u8, velocity: u8, midi_data: (u8, u8, u8)) -> Result<(), Box<dyn Error>> {
-        if let Some(striker) = self.strikers.get_mut(&note) {
-            let time = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
-            let start = Instant::now();
-            striker.strike(velocity).await?;
-            let actual_duration_ns = start.elapsed().as_nanos() as u64;
-            // Collect data about the hit and give it to the logger
-            let hit_data = StrikeLogEntry {
+    /// Fire a striker for a note, logging what became of the hit if the striker was still mid-hit (dropped, queued,
+    /// retriggered or merged)
+    fn strike(&mut self, striker_note: u8, note: u8, velocity: u8, processed_velocity: u8) -> Result<StrikeOutcome, Box<dyn Error>> {
+        let time = timestamp();
+        let striker = self.strikers.get_mut(&striker_note).ok_or(format!("No striker for note {}", striker_note))?;
+        let was_active = striker.is_active();
+        let outcome = striker.strike(processed_velocity)?;
+        if was_active && self.logger.lock().unwrap().is_enabled() {
+            let (outcome_name, queued_for_ms) = match &outcome {
+                StrikeOutcome::Started(_) if striker.get_retrigger_policy() == RetriggerPolicy::Merge => ("merged", None),
+                StrikeOutcome::Started(_) => ("retriggered", None),
+                StrikeOutcome::Dropped => ("dropped", None),
+                StrikeOutcome::Queued(at) => ("queued", Some(at.saturating_duration_since(Instant::now()).as_millis() as u64)),
+                StrikeOutcome::Merged => ("merged", None),
+            };
+            self.logger.lock().unwrap().log(LogEntry::Retrigger(RetriggerLogEntry {
                 time,
-                ms_since_last: if let Some(last_hit_time) = self.logger.last_hit_time() {
-                    time - last_hit_time
-                } else { 0 },
-                planned_duration_ns: striker.get_strike_duration(velocity).as_nanos() as u64,
-                actual_duration_ns,
-                striker_kind: striker.get_striker_kind(),
-                midi_data,
+                striker_name: striker.get_name(),
                 note_num: note,
                 velocity,
-                striker_name: striker.get_name(),
-                target_pin: striker.get_pin_num(),
-            };
-            self.logger.log(LogEntry::Strike(hit_data));
+                policy: striker.get_retrigger_policy(),
+                outcome: outcome_name.to_string(),
+                queued_for_ms,
+                totals: striker.get_retrigger_stats(),
+            }));
+        }
+        Ok(outcome)
+    }
+
+    /// Schedule a hit to be played again if its striker queued it because it was mid-hit
+    fn queue_if_busy(&mut self, outcome: &StrikeOutcome, midi_data: (u8, u8, u8)) {
+        if let StrikeOutcome::Queued(at) = outcome {
+            self.scheduler.schedule(tokio::time::Instant::from_std(*at), midi_data);
+        }
+    }
+
+    /// Check a hit that was just fired against its striker's sensor, if it has one, once the sensor's window has
+    /// passed. A missed hit is reported straight away, and every hit is logged if logging is enabled. Hits folded into one
+    /// already in progress aren't checked again
+    fn verify_hit(&mut self, note: u8, velocity: u8, outcome: &StrikeOutcome, fired: Instant) {
+        let StrikeOutcome::Started(_) = outcome else { return };
+        let striker_note = self.modifier_targets.get(&note).copied().unwrap_or(note);
+        let Some(check) = self.hit_sensors.check(striker_note).cloned() else { return };
+        let striker = &self.strikers[&striker_note];
+        let Some(start) = striker.get_current_hit_start().filter(|start| *start >= fired) else { return };
+        let striker_name = striker.get_name();
+        let time = timestamp();
+        let logger = self.logger.clone();
+        self.log_tasks.retain(|log_task| !log_task.is_finished());
+        self.log_tasks.push(tokio::spawn(async move {
+            let (latency, totals) = check.verify(start).await;
+            if latency.is_none() {
+                eprintln!(
+                    "{} missed a hit: its sensor picked up nothing within {:?} ({} missed so far)",
+                    striker_name, check.window(), totals.missed,
+                );
+            }
+            logger.lock().unwrap().log(LogEntry::Verification(VerificationLogEntry {
+                time,
+                striker_name,
+                note_num: note,
+                velocity,
+                landed: latency.is_some(),
+                latency_ms: latency.map(|latency| latency.as_secs_f64() * 1000.0),
+                totals,
+            }));
+        }));
+    }
+
+    /// Record a hit that was just fired, if recording, on the striker that played it at the time it started. Hits
+    /// that were dropped, queued or folded into one already in progress aren't recorded (queued ones are once they play)
+    fn record_hit(&mut self, note: u8, processed_velocity: u8, outcome: &StrikeOutcome, fired: Instant) {
+        let Some(recorder) = self.recorder.as_mut() else { return };
+        let StrikeOutcome::Started(_) = outcome else { return };
+        let striker_note = self.modifier_targets.get(&note).copied().unwrap_or(note);
+        let striker = &self.strikers[&striker_note];
+        let Some(start) = striker.get_current_hit_start().filter(|start| *start >= fired) else { return };
+        let duration = striker.get_strike_duration(processed_velocity);
+        recorder.record_hit(&striker.get_name(), striker_note, processed_velocity, start, duration);
+    }
+
+    /// Log a hit that was just fired, if logging is enabled, as soon as it's fired rather than once it ends, so the
+    /// actual duration isn't measured (hit_with_debug measures it). Hits that were dropped, queued or folded into one
+    /// already in progress aren't logged as hits (queued ones are once they play)
+    fn log_hit(&mut self, note: u8, velocity: u8, processed_velocity: u8, outcome: &StrikeOutcome, time: u64) {
+        let StrikeOutcome::Started(_) = outcome else { return };
+        if !self.logger.lock().unwrap().is_enabled() {
+            return;
+        }
+        let striker_note = self.modifier_targets.get(&note).copied().unwrap_or(note);
+        let entry = self.strike_log_entry(striker_note, (MIDI_NOTE_ON_BYTE, note, velocity), processed_velocity, time);
+        self.logger.lock().unwrap().log(LogEntry::Strike(entry));
+    }
+
+    /// Collect data about a hit on a striker for the log, without its actual duration
+    fn strike_log_entry(&self, striker_note: u8, midi_data: (u8, u8, u8), processed_velocity: u8, time: u64) -> StrikeLogEntry {
+        let striker = &self.strikers[&striker_note];
+        let (_, note, velocity) = midi_data;
+        StrikeLogEntry {
+            time,
+            ms_since_last: self.logger.lock().unwrap().mark_hit(time),
+            planned_duration_ns: striker.get_strike_duration(processed_velocity).as_nanos() as u64,
+            actual_duration_ns: None,
+            striker_kind: striker.get_striker_kind(),
+            midi_data,
+            note_num: note,
+            velocity,
+            processed_velocity,
+            striker_name: striker.get_name(),
+            target_pin: striker.get_pin(),
+            ble_timestamp: self.ble_timestamp,
+        }
+    }
+
+    /// Fire a striker, logging data about the hit
+    /// TODO: add modifiers to logging once they're fully implemented
+    pub async fn hit_with_debug(&mut self, note: u8, velocity: u8, midi_data: (u8, u8, u8)) -> Result<(), Box<dyn Error>> {
+        let processed_velocity = self.process_velocity(note, velocity);
+        if self.strikers.contains_key(&note) {
+            let time = timestamp();
+            let fired = Instant::now();
+            let outcome = self.strike(note, note, velocity, processed_velocity)?;
+            self.queue_if_busy(&outcome, midi_data);
+            self.verify_hit(note, velocity, &outcome, fired);
+            self.record_hit(note, processed_velocity, &outcome, fired);
+            let StrikeOutcome::Started(pulse) = outcome else { return Ok(()) };
+            // Collect data about the hit, then give it to the logger once the hit has finished
+            let mut hit_data = self.strike_log_entry(note, midi_data, processed_velocity, time);
+            let logger = self.logger.clone();
+            self.log_tasks.retain(|log_task| !log_task.is_finished());
+            self.log_tasks.push(tokio::spawn(async move {
+                // If the hit was aborted there's no actual duration to log
+                if let Ok(actual_duration) = pulse.await {
+                    hit_data.actual_duration_ns = Some(actual_duration.as_nanos() as u64);
+                    logger.lock().unwrap().log(LogEntry::Strike(hit_data));
+                }
+            }));
         }
         Ok(())
     }
@@ -308,40 +1223,328 @@ impl AutoDrum {
 
     /// Export the current configuration of the AutoDrum instance
     fn export_configuration(&self) -> Configuration {
-        let mut strikers = vec![];
-        for (note, striker) in self.strikers.iter() {
-            strikers.push(StrikerData {
-                name: striker.get_name(),
-                note: *note,
-                pin: striker.get_pin_num(),
-                kind: striker.get_striker_kind(),
-                min_hit_duration: Some(striker.get_min_hit_duration()),
-                max_hit_duration: Some(striker.get_max_hit_duration()),
-            });
+        let mut kits = self.kits.clone();
+        if let Some(kit) = kits.iter_mut().find(|kit| kit.name == self.active_kit) {
+            kit.strikers = self.list_strikers();
+            kit.modifiers = self.list_modifiers();
         }
+        // A configuration without named kits is exported without them too
+        let (strikers, modifiers, articulations) = match kits.as_slice() {
+            [kit] if kit.name == DEFAULT_KIT_NAME => {
+                let kit = kits.remove(0);
+                (kit.strikers, kit.modifiers, kit.articulations)
+            },
+            _ => (vec![], vec![], vec![]),
+        };
         Configuration {
+            version: CONFIGURATION_VERSION,
             strikers,
+            modifiers,
+            articulations,
+            kits,
+            default_kit: self.default_kit.clone(),
+            active_kit: None,
+            velocity_scale: self.velocity_scale,
+            dynamics: self.dynamics,
+            tempo_scale: self.tempo_scale,
+            humanize: self.humanizer.config().clone(),
+            cc_mappings: self.cc_mappings.clone(),
+            triggers: self.trigger_config.clone(),
+            ble: self.ble_config.clone(),
+            watchdog: self.watchdog_config.clone(),
+            hot_reload: self.hot_reload_config.clone(),
+            persistence: self.persistence_config.clone(),
+            logging: self.logger.lock().unwrap().config().clone(),
         }
     }
 
-    /// Load a configuration into the AutoDrum instance
-    fn load_configuration(&mut self) {
-        let config = Configuration::load();
-        for strikerData in config.strikers {
-            let striker = Striker::try_from(strikerData).unwrap();
-            self.add_striker(striker).unwrap();
+    /// Load a configuration into the AutoDrum instance with the named kit active. If no kit is named, the active kit
+    /// stays active if the configuration still has it, otherwise the configuration's default kit is loaded
+    fn load_configuration(&mut self, config: Configuration, kit_name: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let kits = config.all_kits();
+        let kit_name = kit_name.map(str::to_string)
+            .or_else(|| kits.iter().find(|kit| kit.name == self.active_kit).map(|kit| kit.name.clone()));
+        let kit = config.get_kit(kit_name.as_deref())?;
+        self.load_kit(kit)?;
+        self.kits = kits;
+        self.default_kit = config.default_kit;
+        self.velocity_scale = config.velocity_scale;
+        self.dynamics = config.dynamics;
+        if config.tempo_scale != self.tempo_scale {
+            self.scheduler.retime(self.tempo_scale / config.tempo_scale, tokio::time::Instant::now());
+            self.tempo_scale = config.tempo_scale;
         }
+        // Only start over (from the seed, if there is one) when the humanize settings change
+        if *self.humanizer.config() != config.humanize {
+            self.humanizer = Humanizer::new(config.humanize);
+        }
+        self.cc_mappings = config.cc_mappings;
+        self.trigger_config = config.triggers;
+        self.logger.lock().unwrap().configure(config.logging);
+        // The new configuration is what saving should keep
+        self.unsaved_cc_values.clear();
+        Ok(())
     }
 
-    /// Save the current configuration of the AutoDrum instance to a file
-    pub async fn save_configuration_file(&self, path: &str) -> Result<(), Box<dyn Error>> {
-        let config = self.export_configuration();
-        let stringified_config = serde_json::to_string(&config).unwrap();
-        let mut file = File::create(path).await?;
-        file.write_all(stringified_config.as_bytes()).await?;
+    /// Switch to the kit with the given name (case-insensitive). Anything scheduled is cancelled and every output
+    /// is turned off before the new kit's strikers and modifiers are loaded
+    pub async fn switch_kit(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let kit = self.kits.iter()
+            .find(|kit| kit.name.eq_ignore_ascii_case(name))
+            .cloned()
+            .ok_or(format!("No kit with name {} exists", name))?;
+        self.scheduler.clear();
+        self.stop();
+        if !self.wait_for_pulses(PULSE_WAIT_TIMEOUT).await {
+            eprintln!("Hits still in progress after {:?}", PULSE_WAIT_TIMEOUT);
+        }
+        // Keep any tuning done to the outgoing kit for when it's switched back to
+        self.store_active_kit();
+        self.load_kit(kit)?;
+        println!("Switched to kit {}", self.active_kit);
+        self.log_configuration_change(format!("switched to kit {}", self.active_kit));
         Ok(())
     }
 
+    /// Copy the loaded strikers and modifiers back into the active kit's entry
+    fn store_active_kit(&mut self) {
+        let strikers = self.list_strikers();
+        let modifiers = self.list_modifiers();
+        if let Some(kit) = self.kits.iter_mut().find(|kit| kit.name == self.active_kit) {
+            kit.strikers = strikers;
+            kit.modifiers = modifiers;
+        }
+    }
+
+    /// Load the strikers and modifiers of a kit into the AutoDrum instance, replacing any already loaded. Outputs on
+    /// pins the old and new kits share are handed over rather than released and re-claimed, and nothing is replaced
+    /// unless every new pin could be claimed
+    fn load_kit(&mut self, kit: KitData) -> Result<(), Box<dyn Error>> {
+        let mut outputs: HashMap<PinAddress, Output> = self.strikers.values()
+            .flat_map(|striker| striker.get_outputs().into_iter().cloned())
+            .chain(self.modifiers.values().map(|modifier| modifier.pin.clone()))
+            .map(|output| (output.pin(), output))
+            .collect();
+        let mut claim = |pin: PinAddress| match outputs.remove(&pin) {
+            Some(output) => Ok(output),
+            None => Output::new(pin),
+        };
+        let striker_data_list = kit.strikers.clone();
+        // Outputs (some of them the current kit's) and the modes the new kit needs them in, only switched once
+        // everything else has worked, so a kit that fails to load leaves the current one as it was
+        let mut modes: Vec<(Output, OutputMode)> = vec![];
+        let mut strikers = vec![];
+        for striker_data in kit.strikers {
+            let output = claim(striker_data.pin)?;
+            modes.push((output.clone(), striker_data.output_mode()));
+            let dir_pin = match striker_data.stepper {
+                Some(stepper) => {
+                    let dir_pin = claim(stepper.dir_pin)?;
+                    modes.push((dir_pin.clone(), OutputMode::Gpio));
+                    Some(dir_pin)
+                },
+                None => None,
+            };
+            strikers.push(Striker::with_output(striker_data, output, dir_pin)?);
+        }
+        let mut modifiers = vec![];
+        for modifier_data in kit.modifiers {
+            let output = claim(modifier_data.pin)?;
+            modes.push((output.clone(), OutputMode::Gpio));
+            let modifier = Modifier::with_output(&modifier_data.name, modifier_data.note, output, modifier_data.kind)?;
+            modifiers.push((modifier_data.target, modifier));
+        }
+
+        // Everything was built, so swap it in, putting the current kit back if the new one doesn't fit together
+        self.stop();
+        let previous = (
+            std::mem::take(&mut self.strikers),
+            std::mem::take(&mut self.modifiers),
+            std::mem::take(&mut self.striker_name_to_note),
+            std::mem::take(&mut self.modifier_targets),
+            std::mem::take(&mut self.striker_modifiers),
+        );
+        let swapped = self.insert_kit(strikers, modifiers).and_then(|_| set_output_modes(&modes).map_err(Into::into));
+        if let Err(e) = swapped {
+            (self.strikers, self.modifiers, self.striker_name_to_note, self.modifier_targets, self.striker_modifiers) = previous;
+            return Err(e);
+        }
+        // The old kit's sensors are let go first, since the new kit may use the same pins. Sensors only check hits, so
+        // the kit plays without them if they can't be read
+        self.hit_sensors = HitSensors::none();
+        self.hit_sensors = match HitSensors::start(&striker_data_list) {
+            Ok(hit_sensors) => hit_sensors,
+            Err(e) => {
+                self.report_error(format!("Hit sensors not started, so hits won't be verified: {}", e));
+                HitSensors::none()
+            },
+        };
+        self.articulations = kit.articulations;
+        self.active_kit = kit.name;
+        self.refresh_console_names();
+        Ok(())
+    }
+
+    /// Replace the running configuration with a new (already validated) one, waiting for hits in progress to finish
+    /// so the swap happens between hits
+    async fn apply_configuration(&mut self, config: Configuration) -> Result<(), Box<dyn Error>> {
+        if !self.wait_for_pulses(PULSE_WAIT_TIMEOUT).await {
+            eprintln!("Hits still in progress after {:?}, cutting them short", PULSE_WAIT_TIMEOUT);
+        }
+        if config.ble != self.ble_config {
+            if let Some(midi_ble_manager) = self.midi_ble_manager.as_mut() {
+                midi_ble_manager.apply_config(config.ble.clone()).await?;
+            }
+            self.ble_config = config.ble.clone();
+        }
+        if config.watchdog != self.watchdog_config {
+            // Stop the old watchdog before starting the new one so two threads never feed the hardware watchdog
+            self.watchdog = None;
+            self.watchdog = config.watchdog.enabled.then(|| Watchdog::start(config.watchdog.clone())).transpose()?;
+            self.watchdog_config = config.watchdog.clone();
+        }
+        if config.hot_reload != self.hot_reload_config {
+            self.hot_reload_config = config.hot_reload.clone();
+            if self.file_watcher.is_some() {
+                self.restart_file_watcher();
+            }
+        }
+        self.persistence_config = config.persistence.clone();
+        let triggers_changed = config.triggers != self.trigger_config;
+        self.load_configuration(config, None)?;
+        if triggers_changed && self.running {
+            self.restart_triggers();
+        }
+        Ok(())
+    }
+
+    /// Re-read the configuration file (and the system constants file, if hot-reload is watching it) and apply it.
+    /// If either file has any problems they're reported and the current configuration is kept
+    async fn reload_configuration(&mut self) -> Result<(), Box<dyn Error>> {
+        let previous_constants = get_system_constants()?;
+        let loaded = if self.hot_reload_config.watch_system_constants {
+            SystemConstants::load(&self.constants_path)
+        } else {
+            Ok(previous_constants.clone())
+        }.and_then(|constants| Configuration::load(&self.config_path, &constants).map(|config| (constants, config)));
+        let (constants, config) = match loaded {
+            Ok(loaded) => loaded,
+            Err(report) => {
+                self.report_error(format!("{}\nKeeping the current configuration", report));
+                return Ok(());
+            }
+        };
+        set_system_constants(constants);
+        if let Err(e) = self.apply_configuration(config).await {
+            set_system_constants(previous_constants);
+            return Err(format!("{}, keeping the current configuration", e).into());
+        }
+        println!("Reloaded configuration from {}", self.config_path);
+        self.log_configuration_change(format!("reloaded {}", self.config_path));
+        Ok(())
+    }
+
+    /// (Re)start watching the configuration file for changes, if hot-reload is enabled
+    fn restart_file_watcher(&mut self) {
+        if let Some(file_watcher) = self.file_watcher.take() {
+            file_watcher.abort();
+        }
+        if !self.hot_reload_config.enabled {
+            return;
+        }
+        let mut paths = vec![self.config_path.clone()];
+        if self.hot_reload_config.watch_system_constants {
+            paths.push(self.constants_path.clone());
+        }
+        let poll_interval = Duration::from_millis(self.hot_reload_config.poll_interval_ms);
+        self.file_watcher = Some(spawn_file_watcher(paths, poll_interval, self.command_tx.clone()));
+    }
+
+    /// (Re)start reading the trigger pads. Pads that can't be read are reported, and the drum carries on without them
+    fn restart_triggers(&mut self) {
+        // Let go of the SPI bus before opening it again
+        self.trigger_reader = TriggerReader::none();
+        self.trigger_reader = match TriggerReader::start(&self.trigger_config, self.command_tx.clone()) {
+            Ok(trigger_reader) => trigger_reader,
+            Err(e) => {
+                self.report_error(format!("Trigger pads not started: {}", e));
+                TriggerReader::none()
+            },
+        };
+    }
+
+    /// Update the names offered for tab-completion by the console to match the current strikers and modifiers
+    fn refresh_console_names(&self) {
+        let mut names: Vec<String> = self.striker_name_to_note.keys().cloned().collect();
+        names.extend(self.modifiers.values().map(|modifier| modifier.name.clone()));
+        names.extend(self.articulations.iter().map(|articulation| articulation.name.clone()));
+        names.sort();
+        *self.console_names.write().unwrap() = names;
+    }
+
+    /// Save the current configuration of the AutoDrum instance to a file as YAML, backing up the previous version
+    pub fn save_configuration_file(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut config = self.export_configuration();
+        self.restore_unsaved_cc_values(&mut config);
+        let stringified_config = serde_yaml::to_string(&config)?;
+        save_with_backup(Path::new(path), stringified_config.as_bytes(), &self.persistence_config)?;
+        Ok(())
+    }
+
+    /// Put back the values that controllers without `persist` changed, so they aren't saved
+    fn restore_unsaved_cc_values(&self, config: &mut Configuration) {
+        for ((kit, target), value) in &self.unsaved_cc_values {
+            if let Some((striker, parameter)) = target.striker_parameter() {
+                let strikers = match config.kits.iter_mut().find(|kit_data| kit_data.name == *kit) {
+                    Some(kit_data) => &mut kit_data.strikers,
+                    None => &mut config.strikers,
+                };
+                if let Some(setting) = strikers.iter_mut()
+                    .find(|data| data.name.eq_ignore_ascii_case(striker))
+                    .and_then(|data| data.parameter_mut(parameter)) {
+                    *setting = Some(*value);
+                }
+            } else if let Some((setting, _)) = global_setting(
+                target, &mut config.velocity_scale, &mut config.dynamics, &mut config.humanize.amount, &mut config.tempo_scale,
+            ) {
+                *setting = *value;
+            }
+        }
+    }
+
+    /// Restore the configuration file from a backup `steps` versions back and apply it. A backup that fails
+    /// validation is left alone
+    async fn rollback_configuration(&mut self, steps: usize) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(&self.config_path);
+        let backup_path = find_backup(path, steps, &self.persistence_config)?;
+        let config = Configuration::load(&backup_path.to_string_lossy(), &get_system_constants()?)?;
+        restore_backup(path, &backup_path, &self.persistence_config)?;
+        self.apply_configuration(config).await?;
+        println!("Restored {} from {}", self.config_path, backup_path.display());
+        self.log_configuration_change(format!("restored {} from {}", self.config_path, backup_path.display()));
+        Ok(())
+    }
+
+    //--------------------------------------------------------------------------------
+    // LOGGING HELPERS
+    //--------------------------------------------------------------------------------
+
+    /// Write an entry to the log
+    fn log(&self, entry: LogEntry) {
+        self.logger.lock().unwrap().log(entry);
+    }
+
+    /// Log a change to the running configuration
+    fn log_configuration_change(&self, change: String) {
+        self.log(LogEntry::Configuration(ConfigurationLogEntry { time: timestamp(), change }));
+    }
+
+    /// Report an error that's being carried on from, and log it
+    fn report_error(&self, message: String) {
+        eprintln!("{}", message);
+        self.log(LogEntry::Error(ErrorLogEntry { time: timestamp(), message }));
+    }
+
     //--------------------------------------------------------------------------------
     // LIFE CYCLE FUNCTIONS
     //--------------------------------------------------------------------------------
@@ -359,3 +1562,117 @@ impl Drop for AutoDrum {
         self.stop();
     }
 }
+
+/// Switch outputs to the modes they're needed in. If one can't be switched, those already switched are put back
+fn set_output_modes(modes: &[(Output, OutputMode)]) -> Result<(), String> {
+    let mut switched: Vec<(&Output, OutputMode)> = vec![];
+    for (output, mode) in modes {
+        let previous_mode = output.mode();
+        if let Err(e) = output.set_mode(*mode) {
+            for (output, previous_mode) in switched.into_iter().rev() {
+                if let Err(e) = output.set_mode(previous_mode) {
+                    eprintln!("Unable to switch {} back to {:?}: {}", output.pin(), previous_mode, e);
+                }
+            }
+            return Err(format!("Unable to switch {} to {:?}: {}", output.pin(), mode, e));
+        }
+        switched.push((output, previous_mode));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start a drum in dry-run mode from a configuration with one striker and a tempo controller on CC 20, which
+    /// goes from half to double speed
+    async fn drum_with_tempo_controller(name: &str) -> AutoDrum {
+        let dir = std::env::temp_dir().join(format!("auto_drum_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config_path = dir.join("configuration.yaml");
+        std::fs::write(&config_path, format!(
+            "version: {}\n\
+             strikers:\n  - {{ name: Snare, kind: SolenoidSmall, pin: 6, note: 38 }}\n\
+             cc_mappings:\n  - {{ cc: 20, target: {{ type: Tempo }}, min: 0.5, max: 2.0 }}\n\
+             watchdog:\n  enabled: false\n\
+             hot_reload:\n  enabled: false\n",
+            CONFIGURATION_VERSION,
+        )).unwrap();
+        AutoDrum::new(AutoDrumOptions {
+            config_path: config_path.to_str().unwrap().to_string(),
+            constants_path: concat!(env!("CARGO_MANIFEST_DIR"), "/system-constants.yaml").to_string(),
+            log_dir: Some(dir.join("logs").to_str().unwrap().to_string()),
+            transport: Transport::None,
+            dry_run: true,
+            ..AutoDrumOptions::default()
+        }).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn tempo_controller_retimes_sequenced_notes_only() {
+        let mut drum = drum_with_tempo_controller("retime").await;
+        let start = tokio::time::Instant::now();
+        drum.scheduler.schedule_sequenced(start + Duration::from_secs(8), (MIDI_NOTE_OFF_BYTE, 38, 0));
+        drum.scheduler.schedule(start + Duration::from_secs(5), (MIDI_NOTE_OFF_BYTE, 38, 0));
+
+        drum.handle_control_change(0, 20, 127).await.unwrap();
+        assert_eq!(drum.tempo_scale, 2.0);
+        // The sequenced note now comes at half the time it had left, and the other stays put
+        let due: Vec<Duration> = drum.scheduler.pop_due(start + Duration::from_secs(60)).iter().map(|note| note.at - start).collect();
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[1], Duration::from_secs(5));
+        assert!(due[0] >= Duration::from_millis(4000) && due[0] < Duration::from_millis(4100), "retimed to {:?}", due[0]);
+    }
+
+    #[tokio::test]
+    async fn tempo_controller_speeds_up_a_file_while_it_plays() {
+        let mut drum = drum_with_tempo_controller("play").await;
+        let start = tokio::time::Instant::now();
+        drum.scheduler.schedule_sequenced(start + Duration::from_secs(2), (MIDI_NOTE_OFF_BYTE, 38, 0));
+        // A BLE MIDI packet (header and timestamp bytes, then the control change) arriving once playback has started
+        let command_tx = drum.command_tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            command_tx.send(Command::MIDI(vec![0x80, 0x80, MIDI_CONTROL_CHANGE_BYTE, 20, 127])).unwrap();
+        });
+
+        drum.play_scheduled().await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed > Duration::from_millis(900) && elapsed < Duration::from_millis(1500), "played after {:?}", elapsed);
+    }
+
+    #[tokio::test]
+    async fn a_kit_that_fails_to_load_leaves_the_current_one() {
+        let mut drum = drum_with_tempo_controller("load_kit").await;
+        // Reuses the current kit's pin, then fails on a modifier with no target
+        let kit: KitData = serde_yaml::from_str(
+            "name: Broken\n\
+             strikers:\n  - { name: Kick, kind: SolenoidBig, pin: 6, note: 36 }\n\
+             modifiers:\n  - { name: Open, kind: SolenoidBig, pin: 7, note: 46, target: HiHat }\n",
+        ).unwrap();
+        assert!(drum.load_kit(kit).is_err());
+        assert_eq!(drum.get_striker_note("Snare"), Some(38));
+        assert_eq!(drum.get_striker_note("Kick"), None);
+        assert!(drum.modifiers.is_empty());
+        assert_eq!(drum.strikers[&38].get_output().pin(), PinAddress::Gpio(6));
+    }
+
+    #[tokio::test]
+    async fn a_remote_configuration_that_cant_be_applied_is_reported() {
+        let mut drum = drum_with_tempo_controller("write").await;
+        // Valid, but the hardware watchdog device can't be opened
+        let config = serde_json::json!({
+            "version": CONFIGURATION_VERSION,
+            "strikers": [{ "name": "Snare", "kind": "SolenoidSmall", "pin": 6, "note": 38 }],
+            "watchdog": { "enabled": true, "hardware_watchdog": "/nonexistent/watchdog" },
+        });
+        let report = drum.write_configuration(config.to_string().as_bytes()).await;
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].path, "");
+        assert!(report.issues[0].message.starts_with("unable to apply: "), "{}", report.issues[0].message);
+
+        let report = drum.write_configuration(b"{ not json").await;
+        assert!(!report.is_valid());
+    }
+}
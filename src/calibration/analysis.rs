@@ -0,0 +1,331 @@
+use crate::calibration::input::Recording;
+use crate::calibration::plan::{round_to_increment, SweepPlan};
+use crate::playback::dynamics::VELOCITY_CURVE_RANGE;
+use crate::system::system_constants::StrikerConstants;
+
+/// Start of the lead-in that's left out of the noise floor, in case starting the recording makes a click
+const LEAD_IN_SETTLE_MS: f64 = 100.0;
+/// Level of a window with nothing in it, so silence doesn't give minus infinity
+const SILENCE_DB: f64 = -120.0;
+/// Curves tried when fitting the velocity curve, spread evenly on a log scale across its range
+const CURVE_CANDIDATES: usize = 200;
+/// Smallest difference in level between the quietest and loudest calibrated hits a velocity curve is fitted to.
+/// Below this the drum barely changes with hit length, so the curve is left straight
+const MIN_DYNAMIC_RANGE_DB: f64 = 1.0;
+
+/// How the levels of a calibration sweep are judged
+#[derive(Debug, Clone, Copy)]
+pub struct AnalysisOptions {
+    /// How far above the noise floor a hit has to be to count as sounding, in dB
+    pub threshold_db: f64,
+    /// How close to the loudest a hit has to be to count as saturated, in dB
+    pub saturation_db: f64,
+}
+
+/// The levels measured for one hit length
+#[derive(Debug, Clone)]
+pub struct LengthLevels {
+    pub duration: f64,
+    /// Peak level of each hit at this length, in dB relative to full scale
+    pub levels: Vec<f64>,
+    /// Whether every hit at this length rose far enough above the noise floor
+    pub sounds: bool,
+}
+
+impl LengthLevels {
+    /// Get the median level, which one stray loud or quiet hit doesn't move
+    pub fn median(&self) -> f64 {
+        let mut levels = self.levels.clone();
+        levels.sort_by(f64::total_cmp);
+        match levels.len() {
+            0 => SILENCE_DB,
+            len if len % 2 == 1 => levels[len / 2],
+            len => (levels[len / 2 - 1] + levels[len / 2]) / 2.0,
+        }
+    }
+}
+
+/// The outcome of a calibration sweep
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    /// Peak level of the lead-in, in dB relative to full scale
+    pub noise_floor: f64,
+    pub lengths: Vec<LengthLevels>,
+    /// The shortest hit length that reliably sounds, as measured
+    pub shortest_sounding: f64,
+    /// The hit length past which the drum gets no louder, as measured
+    pub saturation: f64,
+    /// min_hit_duration to save: the shortest sounding length, within what the striker's kind allows
+    pub min_hit_duration: f64,
+    /// max_hit_duration to save: what a full velocity hit adds to min_hit_duration to reach saturation, within
+    /// what the striker's kind allows
+    pub max_hit_duration: f64,
+    /// velocity_curve to save, which spreads the drum's loudness evenly across velocity
+    pub velocity_curve: f64,
+}
+
+/// Measure each hit of a sweep in a recording of it, and work out the hit durations and velocity curve that suit
+/// the striker
+pub fn analyze(recording: &Recording, plan: &SweepPlan, constants: &StrikerConstants, options: AnalysisOptions) -> Result<Calibration, String> {
+    if recording.length_ms() < plan.end {
+        return Err(format!(
+            "The recording is {:.0}ms long but the sweep takes {:.0}ms, so it doesn't match the sweep",
+            recording.length_ms(), plan.end,
+        ));
+    }
+    // Measured against the lead-in's average rather than zero, since a piezo read through an ADC sits above zero
+    let lead_in = recording.slice(LEAD_IN_SETTLE_MS.min(plan.lead_in / 2.0), plan.lead_in);
+    if lead_in.is_empty() {
+        return Err("The sweep has no lead-in to measure the noise floor from".to_string());
+    }
+    let baseline = lead_in.iter().sum::<f32>() / lead_in.len() as f32;
+    let noise_floor = peak_level(lead_in, baseline);
+
+    // Each hit is measured up to the start of the next
+    let mut hits = plan.hits.clone();
+    hits.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+    let ends = hits.iter().skip(1).map(|hit| hit.offset).chain([plan.end]);
+    let measured: Vec<(f64, f64)> = hits.iter().zip(ends)
+        .map(|(hit, end)| (hit.duration, peak_level(recording.slice(hit.offset, end), baseline)))
+        .collect();
+    let lengths: Vec<LengthLevels> = plan.durations().into_iter().map(|duration| {
+        let levels: Vec<f64> = measured.iter().filter(|(length, _)| *length == duration).map(|(_, level)| *level).collect();
+        let sounds = levels.iter().all(|level| *level >= noise_floor + options.threshold_db);
+        LengthLevels { duration, levels, sounds }
+    }).collect();
+
+    // Reliable means every length from here up sounds, not just this one
+    let first_sounding = lengths.iter().rposition(|length| !length.sounds).map_or(0, |last_silent| last_silent + 1);
+    let Some(shortest_sounding) = lengths.get(first_sounding) else {
+        return Err(format!(
+            "Not even the longest hits rose {}dB above the noise floor ({:.1}dB). Check the sensor is picking up the drum",
+            options.threshold_db, noise_floor,
+        ));
+    };
+    let sounding = &lengths[first_sounding..];
+    let loudest = sounding.iter().map(LengthLevels::median).fold(f64::MIN, f64::max);
+    let saturation = sounding.iter()
+        .find(|length| length.median() >= loudest - options.saturation_db)
+        .unwrap_or(shortest_sounding);
+
+    let increment = constants.increment;
+    let min_hit_duration = round_to_increment(
+        shortest_sounding.duration.clamp(constants.min_min_hit_duration, constants.max_min_hit_duration),
+        increment,
+    );
+    let max_hit_duration = round_to_increment(
+        (saturation.duration - min_hit_duration).clamp(constants.min_max_hit_duration, constants.max_max_hit_duration),
+        increment,
+    );
+    let velocity_curve = fit_velocity_curve(&lengths, min_hit_duration, max_hit_duration);
+    Ok(Calibration {
+        noise_floor,
+        shortest_sounding: shortest_sounding.duration,
+        saturation: saturation.duration,
+        lengths,
+        min_hit_duration,
+        max_hit_duration,
+        velocity_curve,
+    })
+}
+
+/// Get the peak level of some samples in dB relative to full scale, measured from a baseline
+fn peak_level(samples: &[f32], baseline: f32) -> f64 {
+    let peak = samples.iter().map(|sample| (sample - baseline).abs()).fold(0.0, f32::max) as f64;
+    if peak > 0.0 { (20.0 * peak.log10()).max(SILENCE_DB) } else { SILENCE_DB }
+}
+
+/// Find the velocity curve that makes the level rise as evenly as possible across velocity, given the hit
+/// durations velocity is spread over (see Striker::get_strike_duration)
+fn fit_velocity_curve(lengths: &[LengthLevels], min_hit_duration: f64, max_hit_duration: f64) -> f64 {
+    let points: Vec<(f64, f64)> = lengths.iter().map(|length| (length.duration, length.median())).collect();
+    let level_at = |duration: f64| interpolate(&points, duration);
+    let quietest = level_at(min_hit_duration);
+    let loudest = level_at(min_hit_duration + max_hit_duration);
+    if loudest - quietest < MIN_DYNAMIC_RANGE_DB {
+        return 1.0;
+    }
+    let (lowest, highest) = VELOCITY_CURVE_RANGE;
+    let error = |curve: f64| -> f64 {
+        (1..=127).map(|velocity| {
+            let share = velocity as f64 / 127.0;
+            let level = level_at(min_hit_duration + share.powf(curve) * max_hit_duration);
+            ((level - quietest) / (loudest - quietest) - share).powi(2)
+        }).sum()
+    };
+    let best = (0..CURVE_CANDIDATES)
+        .map(|i| lowest * (highest / lowest).powf(i as f64 / (CURVE_CANDIDATES - 1) as f64))
+        .map(|curve| (curve, error(curve)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map_or(1.0, |(curve, _)| curve);
+    (best * 100.0).round() / 100.0
+}
+
+/// Linearly interpolate between points sorted by x, holding the ends flat
+fn interpolate(points: &[(f64, f64)], x: f64) -> f64 {
+    match points.iter().position(|(point_x, _)| *point_x >= x) {
+        None => points.last().map_or(SILENCE_DB, |(_, y)| *y),
+        Some(0) => points[0].1,
+        Some(i) => {
+            let (x0, y0) = points[i - 1];
+            let (x1, y1) = points[i];
+            y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::striker_hardware_util::DriverType;
+
+    /// A sweep of 10 to 50ms hits at 8kHz, each length played twice over a noise floor at -60dB. The 10ms hits
+    /// barely clear the noise, and the 40ms and 50ms hits are as loud as each other (-6dB)
+    const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/calibration/fixtures/sweep.wav");
+
+    const OPTIONS: AnalysisOptions = AnalysisOptions { threshold_db: 10.0, saturation_db: 1.0 };
+
+    fn constants() -> StrikerConstants {
+        StrikerConstants {
+            driver: DriverType::default(),
+            min_min_hit_duration: 8.0,
+            max_min_hit_duration: 50.0,
+            min_max_hit_duration: 10.0,
+            max_max_hit_duration: 75.0,
+            default_min_hit_duration: 30.0,
+            default_max_hit_duration: 50.0,
+            increment: 0.2,
+            min_duty: 0.0,
+            max_duty: 1.0,
+            max_hold_duration: 5000.0,
+            min_recovery_time: 15.0,
+        }
+    }
+
+    fn fixture() -> (Recording, SweepPlan) {
+        let recording = Recording::read_wav(FIXTURE).unwrap();
+        let plan = SweepPlan::load(&SweepPlan::path_for(FIXTURE)).unwrap();
+        (recording, plan)
+    }
+
+    fn lengths(levels: &[(f64, f64)]) -> Vec<LengthLevels> {
+        levels.iter().map(|&(duration, level)| LengthLevels { duration, levels: vec![level], sounds: true }).collect()
+    }
+
+    #[test]
+    fn read_wav_gets_the_fixture_as_recorded() {
+        let (recording, plan) = fixture();
+        assert_eq!(recording.sample_rate, 8000);
+        assert_eq!(recording.length_ms(), plan.end);
+        // The lead-in is only the noise floor
+        let lead_in = recording.slice(0.0, plan.lead_in);
+        assert!(lead_in.iter().all(|sample| sample.abs() < 0.002));
+        assert!(recording.samples.iter().any(|sample| sample.abs() > 0.45));
+    }
+
+    #[test]
+    fn read_wav_mixes_channels_down_to_mono() {
+        let path = std::env::temp_dir().join(format!("auto_drum_stereo_{}.wav", std::process::id()));
+        let spec = hound::WavSpec { channels: 2, sample_rate: 1000, bits_per_sample: 16, sample_format: hound::SampleFormat::Int };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for (left, right) in [(16384i16, 0i16), (-16384, -16384), (0, 8192)] {
+            writer.write_sample(left).unwrap();
+            writer.write_sample(right).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let recording = Recording::read_wav(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recording.sample_rate, 1000);
+        assert_eq!(recording.samples, vec![0.25, -0.5, 0.125]);
+    }
+
+    #[test]
+    fn analyze_finds_the_shortest_sounding_and_saturated_lengths() {
+        let (recording, plan) = fixture();
+        let calibration = analyze(&recording, &plan, &constants(), OPTIONS).unwrap();
+
+        assert!((calibration.noise_floor + 60.0).abs() < 1.0, "noise floor {}", calibration.noise_floor);
+        let durations: Vec<f64> = calibration.lengths.iter().map(|length| length.duration).collect();
+        assert_eq!(durations, vec![10.0, 20.0, 30.0, 40.0, 50.0]);
+        assert!(calibration.lengths.iter().all(|length| length.levels.len() == 2));
+        let sounds: Vec<bool> = calibration.lengths.iter().map(|length| length.sounds).collect();
+        assert_eq!(sounds, vec![false, true, true, true, true]);
+
+        assert_eq!(calibration.shortest_sounding, 20.0);
+        assert_eq!(calibration.saturation, 40.0);
+        assert_eq!(calibration.min_hit_duration, 20.0);
+        assert_eq!(calibration.max_hit_duration, 20.0);
+    }
+
+    #[test]
+    fn analyze_fits_a_curve_that_evens_out_the_fixture() {
+        let (recording, plan) = fixture();
+        let calibration = analyze(&recording, &plan, &constants(), OPTIONS).unwrap();
+
+        // The level climbs 12dB from 20ms to 30ms but only 8dB from 30ms to 40ms, so velocity has to favour
+        // shorter hits for the level to rise evenly
+        let curve = calibration.velocity_curve;
+        assert!(curve > 1.0 && curve < VELOCITY_CURVE_RANGE.1, "curve {}", curve);
+        let points: Vec<(f64, f64)> = calibration.lengths.iter().map(|length| (length.duration, length.median())).collect();
+        let level_at = |share: f64| interpolate(&points, 20.0 + share.powf(curve) * 20.0);
+        let halfway = (level_at(0.5) - level_at(0.0)) / (level_at(1.0) - level_at(0.0));
+        assert!((halfway - 0.5).abs() < 0.05, "half velocity is {:.2} of the way up", halfway);
+    }
+
+    #[test]
+    fn analyze_clamps_to_what_the_kind_allows() {
+        let (recording, plan) = fixture();
+        let constants = StrikerConstants { max_min_hit_duration: 15.0, max_max_hit_duration: 12.0, ..constants() };
+        let calibration = analyze(&recording, &plan, &constants, OPTIONS).unwrap();
+        assert_eq!(calibration.shortest_sounding, 20.0);
+        assert_eq!(calibration.min_hit_duration, 15.0);
+        assert_eq!(calibration.max_hit_duration, 12.0);
+    }
+
+    #[test]
+    fn analyze_rejects_a_recording_shorter_than_the_sweep() {
+        let (mut recording, plan) = fixture();
+        recording.samples.truncate(recording.samples.len() / 2);
+        assert!(analyze(&recording, &plan, &constants(), OPTIONS).is_err());
+    }
+
+    #[test]
+    fn analyze_fails_when_nothing_sounds() {
+        let (recording, plan) = fixture();
+        let options = AnalysisOptions { threshold_db: 100.0, ..OPTIONS };
+        assert!(analyze(&recording, &plan, &constants(), options).is_err());
+    }
+
+    #[test]
+    fn fit_velocity_curve_leaves_an_even_rise_straight() {
+        let even = lengths(&[(10.0, -40.0), (20.0, -30.0), (30.0, -20.0)]);
+        // Within the spacing of the curves tried, which doesn't land on 1 exactly
+        assert!((fit_velocity_curve(&even, 10.0, 20.0) - 1.0).abs() <= 0.02);
+    }
+
+    #[test]
+    fn fit_velocity_curve_leaves_a_flat_drum_straight() {
+        let flat = lengths(&[(10.0, -20.0), (20.0, -19.5), (30.0, -19.2)]);
+        assert_eq!(fit_velocity_curve(&flat, 10.0, 20.0), 1.0);
+    }
+
+    #[test]
+    fn fit_velocity_curve_bends_towards_where_the_level_changes_most() {
+        let early = lengths(&[(10.0, -40.0), (20.0, -22.0), (30.0, -20.0)]);
+        assert!(fit_velocity_curve(&early, 10.0, 20.0) > 1.0);
+        let late = lengths(&[(10.0, -40.0), (20.0, -38.0), (30.0, -20.0)]);
+        assert!(fit_velocity_curve(&late, 10.0, 20.0) < 1.0);
+    }
+
+    #[test]
+    fn interpolate_is_linear_between_points_and_flat_past_the_ends() {
+        let points = [(10.0, -30.0), (20.0, -10.0), (40.0, -6.0)];
+        assert_eq!(interpolate(&points, 15.0), -20.0);
+        assert_eq!(interpolate(&points, 20.0), -10.0);
+        assert_eq!(interpolate(&points, 30.0), -8.0);
+        assert_eq!(interpolate(&points, 5.0), -30.0);
+        assert_eq!(interpolate(&points, 50.0), -6.0);
+        assert_eq!(interpolate(&[], 10.0), SILENCE_DB);
+    }
+}
@@ -0,0 +1,137 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::calibration::analysis::{analyze, AnalysisOptions, Calibration};
+use crate::calibration::input::{Capture, Recording, SensorInput};
+use crate::calibration::plan::{SweepOptions, SweepPlan};
+use crate::hardware::output::{self, Output};
+use crate::hardware::striker::{Striker, StrikerData};
+use crate::hardware::striker_hardware_util::{DriverType, StrikerHardwareUtil};
+use crate::system::configuration::Configuration;
+use crate::system::persistence::save_with_backup;
+use crate::system::signals::ShutdownSignals;
+use crate::system::system_constants::{set_system_constants, SystemConstants};
+
+/// Where the recording of a calibration sweep comes from
+#[derive(Debug, Clone)]
+pub enum SweepSource {
+    /// Fire the striker and listen to it, optionally saving the recording (and its sweep plan) to analyse again
+    Live { input: SensorInput, dry_run: bool, record: Option<String> },
+    /// Analyse a recording of an earlier sweep, using the sweep plan saved alongside it if there is one
+    Wav(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CalibrateOptions {
+    /// Name of the striker to calibrate (case-insensitive)
+    pub striker: String,
+    pub source: SweepSource,
+    pub sweep: SweepOptions,
+    pub analysis: AnalysisOptions,
+    /// Write the results into the configuration file
+    pub save: bool,
+}
+
+/// Calibrate a striker: sweep through its hit lengths while listening with a microphone or piezo (or analyse a
+/// recording of a sweep), then work out its min_hit_duration, max_hit_duration and velocity_curve and save them
+pub async fn calibrate(config_path: &str, constants_path: &str, kit: Option<&str>, options: CalibrateOptions) -> Result<(), Box<dyn Error>> {
+    let constants = SystemConstants::load(constants_path)?;
+    let mut config = Configuration::load(config_path, &constants)?;
+    set_system_constants(constants);
+    let kit = config.get_kit(kit)?;
+    let data = kit.strikers.iter()
+        .find(|striker| striker.name.eq_ignore_ascii_case(&options.striker))
+        .cloned()
+        .ok_or(format!("No striker with name {} exists in kit {}", options.striker, kit.name))?;
+    let striker_constants = StrikerHardwareUtil::get_constants(&data.kind)?.with_overrides(data.overrides.as_ref());
+    if !matches!(striker_constants.driver, DriverType::Solenoid | DriverType::Pwm) {
+        return Err(format!("{} is a {} striker, and only solenoid and PWM strikers can be calibrated", data.name, data.kind).into());
+    }
+
+    let (recording, plan) = match options.source {
+        SweepSource::Wav(path) => {
+            let plan_path = SweepPlan::path_for(&path);
+            let plan = if Path::new(&plan_path).exists() {
+                SweepPlan::load(&plan_path)?
+            } else {
+                SweepPlan::new(&striker_constants, options.sweep)
+            };
+            (Recording::read_wav(&path)?, plan)
+        },
+        SweepSource::Live { input, dry_run, record } => {
+            output::set_dry_run(dry_run);
+            let plan = SweepPlan::new(&striker_constants, options.sweep);
+            let recording = run_sweep(data.clone(), &plan, &input).await?;
+            if let Some(path) = record {
+                recording.write_wav(&path)?;
+                plan.save(&SweepPlan::path_for(&path))?;
+                println!("Saved the recording to {} (analyse it again with --wav {})", path, path);
+            }
+            (recording, plan)
+        },
+    };
+
+    let calibration = analyze(&recording, &plan, &striker_constants, options.analysis)?;
+    print_calibration(&data.name, &calibration, options.analysis);
+    if options.save {
+        let striker = config.kit_strikers_mut(&kit.name)
+            .and_then(|strikers| strikers.iter_mut().find(|striker| striker.name == data.name))
+            .ok_or(format!("{} went missing from the configuration", data.name))?;
+        striker.min_hit_duration = Some(calibration.min_hit_duration);
+        striker.max_hit_duration = Some(calibration.max_hit_duration);
+        striker.velocity_curve = Some(calibration.velocity_curve);
+        let stringified_config = serde_yaml::to_string(&config)?;
+        save_with_backup(Path::new(config_path), stringified_config.as_bytes(), &config.persistence)?;
+        println!("Saved to {}", config_path);
+    }
+    Ok(())
+}
+
+/// Fire every hit of a sweep on the striker at its planned time while recording from the sensor
+async fn run_sweep(data: StrikerData, plan: &SweepPlan, input: &SensorInput) -> Result<Recording, Box<dyn Error>> {
+    let output = Output::new(data.pin)?;
+    output.set_mode(data.output_mode())?;
+    let mut striker = Striker::with_output(data, output, None)?;
+    println!("Calibrating {}: {} hits over {:.1}s", striker.name, plan.hits.len(), plan.length().as_secs_f64());
+
+    let capture = Capture::start(input)?;
+    let started = tokio::time::Instant::from_std(capture.started());
+    let mut signals = ShutdownSignals::new()?;
+    let wait_until = |ms: f64| tokio::time::sleep_until(started + Duration::from_secs_f64(ms / 1000.0));
+    for (i, hit) in plan.hits.iter().enumerate() {
+        tokio::select! {
+            _ = wait_until(hit.offset) => {},
+            signal = signals.recv() => {
+                striker.abort();
+                capture.stop()?;
+                return Err(format!("Received {}, calibration stopped", signal).into());
+            },
+        }
+        println!("  hit {}/{}: {}ms", i + 1, plan.hits.len(), hit.duration);
+        striker.strike_for(Duration::from_secs_f64(hit.duration / 1000.0))?;
+    }
+    // Let the last hit ring out
+    wait_until(plan.end).await;
+    striker.abort();
+    Ok(capture.stop()?)
+}
+
+/// Print the level of every hit length and the settings worked out from them
+fn print_calibration(name: &str, calibration: &Calibration, options: AnalysisOptions) {
+    println!("Noise floor: {:.1}dB", calibration.noise_floor);
+    println!("{:>12} {:>12}  HITS (dB)", "LENGTH (ms)", "MEDIAN (dB)");
+    for length in &calibration.lengths {
+        let levels: Vec<String> = length.levels.iter().map(|level| format!("{:.1}", level)).collect();
+        println!(
+            "{:>12} {:>12.1}  {}{}",
+            length.duration, length.median(), levels.join(", "), if length.sounds { "" } else { "  (not reliably heard)" },
+        );
+    }
+    println!("Shortest hit that reliably sounds: {}ms", calibration.shortest_sounding);
+    println!("Hits of {}ms and longer are within {}dB of the loudest", calibration.saturation, options.saturation_db);
+    println!(
+        "{}: min_hit_duration {}, max_hit_duration {}, velocity_curve {}",
+        name, calibration.min_hit_duration, calibration.max_hit_duration, calibration.velocity_curve,
+    );
+}
@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use alsa::pcm::{Access, Format, HwParams};
+use alsa::{Direction, ValueOr, PCM};
+
+use crate::hardware::adc::{Mcp3008, Mcp3008Address, MCP3008_MAX_READING};
+
+/// Sample rate asked of ALSA capture devices (the device may pick the nearest it supports)
+const AUDIO_SAMPLE_RATE: u32 = 48_000;
+/// Frames read from an ALSA capture device at a time
+const AUDIO_BUFFER_FRAMES: usize = 1024;
+/// How often a piezo is read through the MCP3008
+const PIEZO_SAMPLE_RATE: u32 = 4_000;
+
+/// Where a calibration sweep is listened to from
+#[derive(Debug, Clone)]
+pub enum SensorInput {
+    /// A microphone on an ALSA capture device (e.g. "default" or "hw:1,0")
+    Audio { device: String },
+    /// A piezo on one channel of an MCP3008
+    Piezo { adc: Mcp3008Address, channel: u8 },
+}
+
+/// A mono recording, with samples from -1 to 1 (or 0 to 1 for a piezo read through an ADC)
+#[derive(Debug, Clone)]
+pub struct Recording {
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Recording {
+    /// Read a WAV file, mixing every channel down to mono
+    pub fn read_wav(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut reader = hound::WavReader::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        let spec = reader.spec();
+        let interleaved: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader.samples::<i32>().map(|sample| sample.map(|sample| sample as f32 / full_scale)).collect::<Result<_, _>>()?
+            },
+        };
+        let channels = spec.channels.max(1) as usize;
+        let samples = interleaved.chunks(channels).map(|frame| frame.iter().sum::<f32>() / frame.len() as f32).collect();
+        Ok(Self { sample_rate: spec.sample_rate, samples })
+    }
+
+    /// Save as a 16-bit mono WAV file
+    pub fn write_wav(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: self.sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+        for sample in &self.samples {
+            writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    /// Get how long the recording lasts in milliseconds
+    pub fn length_ms(&self) -> f64 {
+        self.samples.len() as f64 * 1000.0 / self.sample_rate as f64
+    }
+
+    /// Get the samples between two times in milliseconds from the start, cut short if the recording ends first
+    pub fn slice(&self, from_ms: f64, to_ms: f64) -> &[f32] {
+        let index = |ms: f64| ((ms.max(0.0) * self.sample_rate as f64 / 1000.0) as usize).min(self.samples.len());
+        &self.samples[index(from_ms)..index(to_ms).max(index(from_ms))]
+    }
+}
+
+/// A recording in progress on its own thread, so reading the sensor isn't held up by firing the striker
+pub struct Capture {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<Recording, String>>,
+    /// When the first sample was taken
+    started: Instant,
+}
+
+impl Capture {
+    /// Start recording from a sensor, returning once the first samples are on their way
+    pub fn start(input: &SensorInput) -> Result<Self, String> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (started_tx, started_rx) = mpsc::channel();
+        let thread = {
+            let stop = stop.clone();
+            match input.clone() {
+                SensorInput::Audio { device } => std::thread::spawn(move || record_audio(&device, &stop, started_tx)),
+                SensorInput::Piezo { adc, channel } => std::thread::spawn(move || record_piezo(adc, channel, &stop, started_tx)),
+            }
+        };
+        match started_rx.recv() {
+            Ok(started) => Ok(Self { stop, thread, started }),
+            // The thread failed before it could start, so its result says why
+            Err(_) => match thread.join() {
+                Ok(Err(e)) => Err(e),
+                _ => Err("Recording stopped before it started".to_string()),
+            },
+        }
+    }
+
+    /// Get when the first sample was taken, which times in the recording count from
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Stop recording and get what was recorded
+    pub fn stop(self) -> Result<Recording, String> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.join().map_err(|_| "Recording thread panicked".to_string())?
+    }
+}
+
+/// Record from an ALSA capture device until told to stop, mixing down to mono
+fn record_audio(device: &str, stop: &AtomicBool, started: mpsc::Sender<Instant>) -> Result<Recording, String> {
+    let alsa_error = |e: alsa::Error| format!("ALSA capture device {}: {}", device, e);
+    let pcm = PCM::new(device, Direction::Capture, false).map_err(alsa_error)?;
+    let (sample_rate, channels) = {
+        let params = HwParams::any(&pcm).map_err(alsa_error)?;
+        params.set_access(Access::RWInterleaved).map_err(alsa_error)?;
+        params.set_format(Format::s16()).map_err(alsa_error)?;
+        let channels = params.set_channels_near(1).map_err(alsa_error)?;
+        params.set_rate(AUDIO_SAMPLE_RATE, ValueOr::Nearest).map_err(alsa_error)?;
+        pcm.hw_params(&params).map_err(alsa_error)?;
+        (pcm.hw_params_current().and_then(|params| params.get_rate()).map_err(alsa_error)?, channels.max(1) as usize)
+    };
+    let io = pcm.io_i16().map_err(alsa_error)?;
+    pcm.start().map_err(alsa_error)?;
+    let _ = started.send(Instant::now());
+
+    let mut buffer = vec![0i16; AUDIO_BUFFER_FRAMES * channels];
+    let mut samples = vec![];
+    while !stop.load(Ordering::Relaxed) {
+        match io.readi(&mut buffer) {
+            Ok(frames) => samples.extend(buffer[..frames * channels].chunks(channels).map(|frame| {
+                frame.iter().map(|sample| *sample as f32 / i16::MAX as f32).sum::<f32>() / channels as f32
+            })),
+            // An overrun just loses a few samples, so carry on
+            Err(e) => pcm.try_recover(e, true).map_err(alsa_error)?,
+        }
+    }
+    Ok(Recording { sample_rate, samples })
+}
+
+/// Read a piezo through an MCP3008 at a steady rate until told to stop
+fn record_piezo(adc: Mcp3008Address, channel: u8, stop: &AtomicBool, started: mpsc::Sender<Instant>) -> Result<Recording, String> {
+    let adc = Mcp3008::new(adc)?;
+    let period = Duration::from_secs(1) / PIEZO_SAMPLE_RATE;
+    let start = Instant::now();
+    let _ = started.send(start);
+    let mut samples = vec![];
+    while !stop.load(Ordering::Relaxed) {
+        samples.push(adc.read(channel)? as f32 / MCP3008_MAX_READING as f32);
+        // Keep to the sample rate overall, even if a read runs late
+        let next = start + period * samples.len() as u32;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+    Ok(Recording { sample_rate: PIEZO_SAMPLE_RATE, samples })
+}
@@ -0,0 +1,4 @@
+pub mod analysis;
+pub mod calibrate;
+pub mod input;
+pub mod plan;
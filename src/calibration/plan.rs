@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hardware::striker::Striker;
+use crate::system::system_constants::StrikerConstants;
+
+/// Silence recorded before the first hit, to measure the noise floor from
+const LEAD_IN_MS: f64 = 1000.0;
+
+/// How a calibration sweep is run
+#[derive(Debug, Clone, Copy)]
+pub struct SweepOptions {
+    /// How many hit lengths to try, spread evenly from the shortest to the longest the striker's kind allows
+    pub steps: usize,
+    /// How many times to hit at each length
+    pub repeats: usize,
+    /// Time from the start of one hit to the start of the next in milliseconds, long enough for the drum to ring out
+    pub interval: f64,
+}
+
+/// The hits of a calibration sweep and when they're played, in milliseconds from the start of the recording. Saved
+/// alongside a recording of the sweep, so the recording can be analysed again later
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SweepPlan {
+    /// Silence before the first hit
+    pub lead_in: f64,
+    pub hits: Vec<PlannedHit>,
+    /// When the recording can stop, once the last hit has rung out
+    pub end: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PlannedHit {
+    /// When the hit starts
+    pub offset: f64,
+    /// How long the striker is on for
+    pub duration: f64,
+}
+
+impl SweepPlan {
+    /// Plan a sweep over every hit length a striker with the given constants allows, rounded to its kind's
+    /// increment. Each pass over the lengths goes the opposite way to the last, so a loud hit is never followed
+    /// straight away by the quietest one while it's still ringing
+    pub fn new(constants: &StrikerConstants, options: SweepOptions) -> Self {
+        let shortest = constants.min_min_hit_duration;
+        let longest = Striker::longest_hit_duration(constants).as_secs_f64() * 1000.0;
+        let steps = options.steps.max(2);
+        let mut durations: Vec<f64> = (0..steps)
+            .map(|i| round_to_increment(shortest + (longest - shortest) * i as f64 / (steps - 1) as f64, constants.increment))
+            .map(|duration| duration.clamp(shortest, longest))
+            .collect();
+        durations.dedup();
+
+        let recovery = constants.min_recovery_time;
+        let mut hits = vec![];
+        let mut offset = LEAD_IN_MS;
+        for pass in 0..options.repeats.max(1) {
+            let mut lengths = durations.clone();
+            if pass % 2 == 1 {
+                lengths.reverse();
+            }
+            for duration in lengths {
+                hits.push(PlannedHit { offset, duration });
+                offset += options.interval.max(duration + recovery);
+            }
+        }
+        Self { lead_in: LEAD_IN_MS, hits, end: offset }
+    }
+
+    /// Read the plan saved alongside a recording
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Get the path the plan for a recording is saved at
+    pub fn path_for(recording_path: &str) -> String {
+        format!("{}.json", recording_path)
+    }
+
+    /// Get every hit length in the sweep, shortest first
+    pub fn durations(&self) -> Vec<f64> {
+        let mut durations: Vec<f64> = self.hits.iter().map(|hit| hit.duration).collect();
+        durations.sort_by(f64::total_cmp);
+        durations.dedup();
+        durations
+    }
+
+    /// Get how long the sweep takes
+    pub fn length(&self) -> Duration {
+        Duration::from_secs_f64(self.end / 1000.0)
+    }
+}
+
+/// Round a duration to a multiple of an increment (left alone if the increment is 0)
+pub fn round_to_increment(duration: f64, increment: f64) -> f64 {
+    if increment > 0.0 {
+        // Rounded again to tidy up the float error from dividing, e.g. 0.30000000000000004
+        ((duration / increment).round() * increment * 1e6).round() / 1e6
+    } else {
+        duration
+    }
+}
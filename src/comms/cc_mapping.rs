@@ -0,0 +1,112 @@
+use serde::{Deserialize, Serialize};
+
+use crate::playback::dynamics::{DynamicsConfig, RATIO_RANGE, VELOCITY_RANGE, VELOCITY_SCALE_RANGE};
+use crate::playback::humanize::HUMANIZE_AMOUNT_RANGE;
+use crate::playback::scheduler::TEMPO_SCALE_RANGE;
+
+/// What a controller mapped in the configuration adjusts
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum CcTarget {
+    /// A striker's min_hit_duration in milliseconds
+    MinHitDuration { striker: String },
+    /// A striker's max_hit_duration in milliseconds
+    MaxHitDuration { striker: String },
+    /// A striker's velocity_gain
+    VelocityGain { striker: String },
+    /// A striker's velocity_offset
+    VelocityOffset { striker: String },
+    /// The global velocity scale, which every note-on velocity is multiplied by
+    VelocityScale,
+    /// The dynamics threshold
+    CompressionThreshold,
+    /// The dynamics ratio
+    CompressionRatio,
+    /// The dynamics ceiling
+    Ceiling,
+    /// The overall humanize amount, which scales every humanize amount
+    HumanizeAmount,
+    /// The sequencer tempo, as a multiple of the tempo MIDI files are written at
+    Tempo,
+    /// The active kit, with the controller's range split evenly between the kits in the order they're configured
+    Kit,
+}
+
+/// A MIDI controller (e.g. a fader) mapped to a live parameter, e.g.
+/// `{cc: 7, channel: 10, target: {type: MaxHitDuration, striker: Snare}, min: 30.0, max: 60.0}`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CcMapping {
+    /// Controller number (0-119)
+    pub cc: u8,
+    /// MIDI channel (1-16) to listen on, or every channel if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+    pub target: CcTarget,
+    /// Value at controller value 0, the lowest the target allows if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Value at controller value 127, the highest the target allows if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Keep what the controller sets when the configuration is saved. Otherwise saving keeps the value from before
+    /// the controller changed it, so moving a fader during a show doesn't change the saved setup
+    #[serde(default)]
+    pub persist: bool,
+}
+
+impl CcTarget {
+    /// Get the target for a striker parameter, by the name `set` uses for it
+    pub fn for_striker_parameter(striker: String, parameter: &str) -> Option<Self> {
+        match parameter {
+            "min_hit_duration" => Some(CcTarget::MinHitDuration { striker }),
+            "max_hit_duration" => Some(CcTarget::MaxHitDuration { striker }),
+            "velocity_gain" => Some(CcTarget::VelocityGain { striker }),
+            "velocity_offset" => Some(CcTarget::VelocityOffset { striker }),
+            _ => None,
+        }
+    }
+
+    /// Get the striker and the name `set` uses for its parameter, for targets that adjust a striker
+    pub fn striker_parameter(&self) -> Option<(&str, &'static str)> {
+        match self {
+            CcTarget::MinHitDuration { striker } => Some((striker, "min_hit_duration")),
+            CcTarget::MaxHitDuration { striker } => Some((striker, "max_hit_duration")),
+            CcTarget::VelocityGain { striker } => Some((striker, "velocity_gain")),
+            CcTarget::VelocityOffset { striker } => Some((striker, "velocity_offset")),
+            _ => None,
+        }
+    }
+}
+
+/// Get the global setting a target adjusts, and the lowest and highest it can be set to
+pub fn global_setting<'a>(
+    target: &CcTarget,
+    velocity_scale: &'a mut f64,
+    dynamics: &'a mut DynamicsConfig,
+    humanize_amount: &'a mut f64,
+    tempo_scale: &'a mut f64,
+) -> Option<(&'a mut f64, (f64, f64))> {
+    match target {
+        CcTarget::VelocityScale => Some((velocity_scale, VELOCITY_SCALE_RANGE)),
+        CcTarget::CompressionThreshold => Some((&mut dynamics.threshold, VELOCITY_RANGE)),
+        CcTarget::CompressionRatio => Some((&mut dynamics.ratio, RATIO_RANGE)),
+        CcTarget::Ceiling => Some((&mut dynamics.ceiling, VELOCITY_RANGE)),
+        CcTarget::HumanizeAmount => Some((humanize_amount, HUMANIZE_AMOUNT_RANGE)),
+        CcTarget::Tempo => Some((tempo_scale, TEMPO_SCALE_RANGE)),
+        _ => None,
+    }
+}
+
+impl CcMapping {
+    /// Check whether the mapping listens for the given controller on the given channel (0-15, as in the status byte)
+    pub fn matches(&self, channel: u8, cc: u8) -> bool {
+        self.cc == cc && self.channel.is_none_or(|mapped| mapped == channel + 1)
+    }
+
+    /// Scale a controller value (0-127) into the mapping's range, clamped to the lowest and highest the target allows
+    pub fn scale(&self, value: u8, (lowest, highest): (f64, f64)) -> f64 {
+        let min = self.min.unwrap_or(lowest);
+        let max = self.max.unwrap_or(highest);
+        (min + (max - min) * value.min(127) as f64 / 127.0).clamp(lowest, highest)
+    }
+}
@@ -0,0 +1,214 @@
+use std::io::IsTerminal;
+use std::sync::{Arc, RwLock};
+use std::thread::JoinHandle;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use tokio::sync::broadcast;
+
+use crate::comms::remote_command::Command;
+use crate::playback::articulation::Articulation;
+
+/// File that console history is kept in between sessions
+const HISTORY_FILE: &str = ".auto_drum_history";
+const DEFAULT_VELOCITY: u8 = 100;
+/// Flams and drags from the console use the same grace settings as configured ones that leave them out
+const DEFAULT_GRACE_OFFSET: f64 = 30.0;
+const DEFAULT_GRACE_VELOCITY_RATIO: f64 = 0.5;
+const PROMPT: &str = "autodrum> ";
+
+const COMMANDS: [&str; 16] = [
+    "fire", "roll", "flam", "drag", "buzz", "mod", "set", "kit", "save", "reload", "rollback", "record", "status", "panic", "help",
+    "quit",
+];
+const RECORD_ACTIONS: [&str; 2] = ["start", "stop"];
+const PARAMETERS: [&str; 4] = ["min_hit_duration", "max_hit_duration", "velocity_gain", "velocity_offset"];
+
+const HELP: &str = "\
+Commands:
+  fire <name> [velocity]                fire a striker or modifier once, or play a named articulation
+  roll <striker> <hits> <bpm>bpm [vel]  fire a striker <hits> times as sixteenth notes
+  flam <striker> [velocity]             play a flam (a grace stroke then the main stroke)
+  drag <striker> [velocity]             play a drag (two grace strokes then the main stroke)
+  buzz <striker> <duration>ms [vel]     play a buzz roll, as fast as the striker can recover
+  mod <modifier> on|off                 activate or deactivate a modifier
+  set <striker>.<parameter> <value>     change min_hit_duration, max_hit_duration, velocity_gain or velocity_offset
+  kit <name>                            switch to another kit
+  save                                  save the configuration file
+  reload                                reload the configuration file
+  rollback [steps]                      restore the configuration file from a backup (1 = most recent)
+  record start [path]                   record the hits played to a MIDI file
+  record stop                           stop recording and save the MIDI file
+  status                                show every striker and modifier
+  panic                                 cancel scheduled hits and turn every output off
+  quit                                  shut down";
+
+/// Interactive command shell on stdin, turning each line into a Command sent over the same channel as remote commands
+pub struct Console {}
+
+impl Console {
+    /// Start the console on its own thread (line editing blocks). `names` are the striker and modifier names offered
+    /// for tab-completion, and can be updated while the console is running
+    pub fn spawn(tx: broadcast::Sender<Command>, names: Arc<RwLock<Vec<String>>>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            let mut editor: Editor<ConsoleHelper, DefaultHistory> = match Editor::new() {
+                Ok(editor) => editor,
+                Err(e) => return eprintln!("Unable to start console: {}", e),
+            };
+            editor.set_helper(Some(ConsoleHelper { names }));
+            let _ = editor.load_history(HISTORY_FILE);
+            loop {
+                match editor.readline(PROMPT) {
+                    Ok(line) if line.trim().is_empty() => continue,
+                    Ok(line) => {
+                        let _ = editor.add_history_entry(line.as_str());
+                        match Console::parse_line(&line) {
+                            Ok(Some(command)) => {
+                                let quit = matches!(command, Command::Quit);
+                                if tx.send(command).is_err() || quit {
+                                    break;
+                                }
+                            },
+                            Ok(None) => println!("{}", HELP),
+                            Err(e) => println!("{}", e),
+                        }
+                    },
+                    Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
+                        // Without a terminal (e.g. running as a service) stdin closing doesn't mean anyone asked to quit
+                        if std::io::stdin().is_terminal() {
+                            let _ = tx.send(Command::Quit);
+                        }
+                        break;
+                    },
+                    Err(e) => {
+                        eprintln!("Console error: {}", e);
+                        break;
+                    },
+                }
+            }
+            let _ = editor.save_history(HISTORY_FILE);
+        })
+    }
+
+    /// Parse a console line into a Command. Returns None for `help`
+    pub fn parse_line(line: &str) -> Result<Option<Command>, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["fire", name] => Command::Fire { name: name.to_string(), velocity: DEFAULT_VELOCITY },
+            ["fire", name, velocity] => Command::Fire { name: name.to_string(), velocity: parse_velocity(velocity)? },
+            ["roll", name, hits, bpm, rest @ ..] if rest.len() <= 1 => Command::Roll {
+                name: name.to_string(),
+                hits: hits.parse().map_err(|_| format!("Invalid number of hits: {}", hits))?,
+                bpm: parse_bpm(bpm)?,
+                velocity: rest.first().map(|velocity| parse_velocity(velocity)).transpose()?.unwrap_or(DEFAULT_VELOCITY),
+            },
+            ["flam", name, rest @ ..] if rest.len() <= 1 => Command::Articulate {
+                striker: name.to_string(),
+                articulation: Articulation::Flam { grace_offset: DEFAULT_GRACE_OFFSET, grace_velocity_ratio: DEFAULT_GRACE_VELOCITY_RATIO },
+                velocity: rest.first().map(|velocity| parse_velocity(velocity)).transpose()?.unwrap_or(DEFAULT_VELOCITY),
+            },
+            ["drag", name, rest @ ..] if rest.len() <= 1 => Command::Articulate {
+                striker: name.to_string(),
+                articulation: Articulation::Drag { grace_offset: DEFAULT_GRACE_OFFSET, grace_velocity_ratio: DEFAULT_GRACE_VELOCITY_RATIO },
+                velocity: rest.first().map(|velocity| parse_velocity(velocity)).transpose()?.unwrap_or(DEFAULT_VELOCITY),
+            },
+            ["buzz", name, duration, rest @ ..] if rest.len() <= 1 => Command::Articulate {
+                striker: name.to_string(),
+                articulation: Articulation::BuzzRoll {
+                    duration: duration.trim_end_matches("ms").parse::<f64>().ok()
+                        .filter(|duration| *duration > 0.0)
+                        .ok_or(format!("Invalid duration: {}", duration))?,
+                },
+                velocity: rest.first().map(|velocity| parse_velocity(velocity)).transpose()?.unwrap_or(DEFAULT_VELOCITY),
+            },
+            ["mod", name, state] => Command::SetModifier {
+                name: name.to_string(),
+                active: match *state {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("Expected on or off, got {}", state)),
+                },
+            },
+            ["set", path, value] => {
+                let (target, parameter) = path.split_once('.').ok_or(format!("Expected <striker>.<parameter>, got {}", path))?;
+                Command::SetParameter {
+                    target: target.to_string(),
+                    parameter: parameter.to_string(),
+                    value: value.parse().map_err(|_| format!("Invalid value: {}", value))?,
+                }
+            },
+            ["kit", name] => Command::SwitchKit { name: name.to_string() },
+            ["save"] => Command::SaveConfiguration,
+            ["reload"] => Command::ReloadConfiguration,
+            ["rollback"] => Command::RollbackConfiguration { steps: 1 },
+            ["rollback", steps] => Command::RollbackConfiguration {
+                steps: steps.parse().map_err(|_| format!("Invalid number of steps: {}", steps))?,
+            },
+            ["record", "start"] => Command::StartRecording { path: None },
+            ["record", "start", path] => Command::StartRecording { path: Some(path.to_string()) },
+            ["record", "stop"] => Command::StopRecording,
+            ["status"] => Command::Status,
+            ["panic"] => Command::Panic,
+            ["quit"] | ["exit"] => Command::Quit,
+            ["help"] => return Ok(None),
+            _ => return Err(format!("Unrecognized command: {} (type help for a list of commands)", line.trim())),
+        };
+        Ok(Some(command))
+    }
+}
+
+fn parse_velocity(velocity: &str) -> Result<u8, String> {
+    velocity.parse::<u8>().ok()
+        .filter(|velocity| *velocity <= 127)
+        .ok_or(format!("Velocity must be between 0 and 127, got {}", velocity))
+}
+
+fn parse_bpm(bpm: &str) -> Result<f64, String> {
+    bpm.trim_end_matches("bpm").parse::<f64>().ok()
+        .filter(|bpm| *bpm > 0.0)
+        .ok_or(format!("Invalid tempo: {}", bpm))
+}
+
+/// Tab-completes command names, then striker/modifier names and parameters
+struct ConsoleHelper {
+    names: Arc<RwLock<Vec<String>>>,
+}
+
+impl Completer for ConsoleHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+        let candidates: Vec<String> = if start == 0 {
+            COMMANDS.iter().map(|command| command.to_string()).collect()
+        } else if line.starts_with("set ") && word.contains('.') {
+            let (target, _) = word.split_once('.').unwrap();
+            PARAMETERS.iter().map(|parameter| format!("{}.{}", target, parameter)).collect()
+        } else if line.starts_with("record ") && line[..start].split_whitespace().count() == 1 {
+            RECORD_ACTIONS.iter().map(|action| action.to_string()).collect()
+        } else if line[..start].split_whitespace().count() == 1 {
+            self.names.read().unwrap().iter().map(|name| name.to_lowercase()).collect()
+        } else {
+            vec![]
+        };
+        let matches = candidates.into_iter().filter(|candidate| candidate.starts_with(&word.to_lowercase())).collect();
+        Ok((start, matches))
+    }
+}
+
+impl Hinter for ConsoleHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ConsoleHelper {}
+
+impl Validator for ConsoleHelper {}
+
+impl Helper for ConsoleHelper {}
@@ -1,5 +1,6 @@
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bluer::{
     adv::Advertisement,
@@ -14,15 +15,104 @@ use bluer::{
 use bluer::adv::AdvertisementHandle;
 use bluer::agent::{Agent, AgentHandle};
 use bluer::gatt::local::ApplicationHandle;
+use tokio::sync::broadcast;
 use uuid::{Uuid, uuid};
+use serde::{Deserialize, Serialize};
 use crate::comms::remote_command::Command;
+use crate::debug::logger::{timestamp, ConnectionLogEntry, LogEntry, Logger};
 use rand::Rng;
 
 // Specified by MIDI BLE spec, these are the UUIDs for the MIDI service and characteristic and should never change
 const BLE_MIDI_SERVICE_ID: Uuid = uuid!("03B80E5A-EDE8-4B33-A751-6CE34EC4C700");
 const BLE_MIDI_CHARACTERISTIC_ID: Uuid = uuid!("7772E5DB-3868-4112-A1A9-F2669D106BF3");
 
-type MIDIEvent = (u8, u8, u8);
+/// Advertising data type for the peripheral's preferred connection interval range (Bluetooth Core Spec Supplement, 1.9)
+const AD_TYPE_CONNECTION_INTERVAL_RANGE: u8 = 0x12;
+/// Connection intervals are expressed in units of 1.25ms
+const CONNECTION_INTERVAL_UNIT_MS: f64 = 1.25;
+/// Bounds on the connection interval allowed by the Bluetooth spec
+const MIN_CONNECTION_INTERVAL_MS: f64 = 7.5;
+const MAX_CONNECTION_INTERVAL_MS: f64 = 4000.0;
+/// BLE MIDI timestamps are the low 13 bits of a millisecond clock, so they wrap around this often (in milliseconds)
+pub const BLE_MIDI_TIMESTAMP_PERIOD: u64 = 1 << 13;
+/// Bounds on the advertising interval accepted by BlueZ
+const MIN_ADVERTISING_INTERVAL_MS: f64 = 5.0;
+const MAX_ADVERTISING_INTERVAL_MS: f64 = 10240.0;
+/// Smallest ATT MTU allowed by the Bluetooth spec
+const MIN_MTU: u16 = 23;
+/// BLE MIDI packets waiting to be sent to subscribed centrals, beyond which the oldest are dropped
+const NOTIFY_QUEUE_LENGTH: usize = 64;
+
+/// BLE identity and advertising parameters, loaded from the `ble` section of the configuration
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BleConfig {
+    /// Name the device advertises and shows up as when pairing (also used as the adapter alias)
+    pub device_name: String,
+    /// Name of the bluetooth adapter to use (e.g. "hci1"), or the system default adapter if not set
+    pub adapter: Option<String>,
+    /// Interval between advertising packets in milliseconds
+    pub advertising_interval_ms: f64,
+    /// Advertised TX power in dBm, or the adapter default if not set
+    pub tx_power: Option<i16>,
+    /// Lower bound of the preferred connection interval in milliseconds (MIDI BLE spec asks for <15ms)
+    pub min_connection_interval_ms: f64,
+    /// Upper bound of the preferred connection interval in milliseconds
+    pub max_connection_interval_ms: f64,
+    /// Preferred ATT MTU. BlueZ negotiates the MTU itself, so this is only used to warn when a central settles on less
+    pub preferred_mtu: Option<u16>,
+}
+
+impl Default for BleConfig {
+    fn default() -> Self {
+        Self {
+            device_name: "AutoDrum".to_string(),
+            adapter: None,
+            advertising_interval_ms: 5.0,
+            tx_power: None,
+            min_connection_interval_ms: 7.5,
+            max_connection_interval_ms: 15.0,
+            preferred_mtu: None,
+        }
+    }
+}
+
+impl BleConfig {
+    /// Check that all parameters are within the ranges allowed by the Bluetooth spec
+    pub fn validate(&self) -> Result<(), String> {
+        if self.device_name.is_empty() {
+            return Err("device_name must not be empty".to_string());
+        }
+        if !(MIN_ADVERTISING_INTERVAL_MS..=MAX_ADVERTISING_INTERVAL_MS).contains(&self.advertising_interval_ms) {
+            return Err(format!(
+                "advertising_interval_ms must be between {} and {}", MIN_ADVERTISING_INTERVAL_MS, MAX_ADVERTISING_INTERVAL_MS
+            ));
+        }
+        for interval in [self.min_connection_interval_ms, self.max_connection_interval_ms] {
+            if !(MIN_CONNECTION_INTERVAL_MS..=MAX_CONNECTION_INTERVAL_MS).contains(&interval) {
+                return Err(format!(
+                    "connection intervals must be between {} and {} ms", MIN_CONNECTION_INTERVAL_MS, MAX_CONNECTION_INTERVAL_MS
+                ));
+            }
+        }
+        if self.min_connection_interval_ms > self.max_connection_interval_ms {
+            return Err("min_connection_interval_ms must not be greater than max_connection_interval_ms".to_string());
+        }
+        if let Some(mtu) = self.preferred_mtu {
+            if mtu < MIN_MTU {
+                return Err(format!("preferred_mtu must be at least {}", MIN_MTU));
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the preferred connection interval range as advertising data (min and max as little-endian u16s in 1.25ms units)
+    fn connection_interval_range_data(&self) -> Vec<u8> {
+        let min = (self.min_connection_interval_ms / CONNECTION_INTERVAL_UNIT_MS).round() as u16;
+        let max = (self.max_connection_interval_ms / CONNECTION_INTERVAL_UNIT_MS).round() as u16;
+        [min.to_le_bytes(), max.to_le_bytes()].concat()
+    }
+}
 
 /// Handles the sending and receiving of MIDI data over BLE, forwarding relevant MIDI events to AutoDrum
 pub struct MidiBle {
@@ -38,12 +128,19 @@ pub struct MidiBle {
     pub tx: tokio::sync::broadcast::Sender<Command>,
     /// The value of the MIDI characteristic
     read_value: Arc<Mutex<Vec<u8>>>,
+    /// The BLE identity and advertising parameters currently in use
+    config: BleConfig,
+    /// The MTU most recently negotiated with a connected central (0 if none has written yet)
+    negotiated_mtu: Arc<AtomicU16>,
+    /// BLE MIDI packets to send to every central subscribed to notifications
+    notify_tx: broadcast::Sender<Vec<u8>>,
+    /// Where connection events are logged
+    logger: Arc<Mutex<Logger>>,
 }
 
 impl MidiBle {
-    pub async fn new() -> MidiBle {
+    pub async fn new(config: BleConfig, tx: tokio::sync::broadcast::Sender<Command>, logger: Arc<Mutex<Logger>>) -> MidiBle {
         let ble_session = bluer::Session::new().await.unwrap();
-        let (tx, _rx) = tokio::sync::broadcast::channel::<Command>(120);
         let read_value = Arc::new(Mutex::new(vec![0x00, 0x00, 0x00, 0x00]));
         MidiBle {
             ble_session,
@@ -52,14 +149,23 @@ impl MidiBle {
             app_handle: None,
             tx,
             read_value,
+            config,
+            negotiated_mtu: Arc::new(AtomicU16::new(0)),
+            notify_tx: broadcast::channel(NOTIFY_QUEUE_LENGTH).0,
+            logger,
         }
     }
 
+    /// Get the BLE parameters currently in use
+    pub fn get_config(&self) -> &BleConfig {
+        &self.config
+    }
+
     /// Initialize the BLE MIDI service
     ///
     /// 1. Register the agent (as default and with no PIN code)
-    /// 2. Make sure the adapter is powered on and ready to go, but not discoverable or pairable outside the midi service
-    /// 3. Advertise the MIDI service with a low connection interval (specified <15ms by MIDI BLE spec)
+    /// 2. Make sure the configured adapter is powered on and ready to go, but not discoverable or pairable outside the midi service
+    /// 3. Advertise the MIDI service with the configured name, interval, TX power and preferred connection interval
     /// 4. Serve the GATT application
     pub async fn init(&mut self) -> bluer::Result<()> {
         // Register the agent (as default and with no PIN code)
@@ -78,25 +184,32 @@ impl MidiBle {
         );
 
         // Make sure the adapter is powered on and ready to go, but not discoverable or pairable outside the midi service
-        let adapter = self.ble_session.default_adapter().await?;
+        let adapter = match &self.config.adapter {
+            Some(name) => self.ble_session.adapter(name)?,
+            None => self.ble_session.default_adapter().await?,
+        };
         adapter.set_powered(true).await?;
         adapter.set_pairable(false).await?;
         adapter.set_discoverable(false).await?;
-        adapter.set_alias("AutoDrum".to_string()).await?;
+        adapter.set_alias(self.config.device_name.clone()).await?;
 
-        // Advertise the MIDI service with a low connection interval (specified <15ms by MIDI BLE spec)
+        // Advertise the MIDI service, including the preferred connection interval (specified <15ms by MIDI BLE spec)
         println!("Advertising on Bluetooth adapter {} with address {}", adapter.name(), adapter.address().await?);
+        let advertising_interval = Duration::from_secs_f64(self.config.advertising_interval_ms / 1000.0);
         let le_advertisement = Advertisement {
             advertisement_type: bluer::adv::Type::Peripheral,
-            service_uuids: vec!["03B80E5A-EDE8-4B33-A751-6CE34EC4C700".parse().unwrap()].into_iter().collect(),
+            service_uuids: vec![BLE_MIDI_SERVICE_ID].into_iter().collect(),
             discoverable: Some(true),
-            local_name: Some("AutoDrum".to_string()),
-            min_interval: Some(Duration::from_millis(5)),
-            max_interval: Some(Duration::from_millis(5)),
+            local_name: Some(self.config.device_name.clone()),
+            min_interval: Some(advertising_interval),
+            max_interval: Some(advertising_interval),
+            tx_power: self.config.tx_power,
+            advertising_data: [(AD_TYPE_CONNECTION_INTERVAL_RANGE, self.config.connection_interval_range_data())].into(),
             ..Default::default()
         };
         println!("Advertisement: {:#?}\n\n", &le_advertisement);
         self.advertisement_handle = Some(adapter.advertise(le_advertisement).await?);
+        log_connection(&self.logger, format!("advertising as {} on {}", self.config.device_name, adapter.name()), None);
 
         // Serve the GATT application
         let application = self.midi_application().await;
@@ -105,6 +218,33 @@ impl MidiBle {
         Ok(())
     }
 
+    /// Tear down the advertisement, GATT application and agent, then bring them back up with a new configuration. If
+    /// the new configuration can't be brought up (e.g. its adapter doesn't exist), BLE is brought back up with the
+    /// previous one and the error is returned, so a bad configuration never leaves BLE down
+    pub async fn apply_config(&mut self, config: BleConfig) -> Result<(), Box<dyn std::error::Error>> {
+        config.validate()?;
+        self.shutdown();
+        let previous = std::mem::replace(&mut self.config, config);
+        if let Err(e) = self.init().await {
+            self.shutdown();
+            self.config = previous;
+            if let Err(restore_error) = self.init().await {
+                eprintln!("Unable to bring BLE back up with the previous configuration: {}", restore_error);
+            }
+            return Err(format!("Unable to apply the BLE configuration: {}", e).into());
+        }
+        Ok(())
+    }
+
+    /// Stop advertising and serving the MIDI service (dropping the handles unregisters them with BlueZ)
+    pub fn shutdown(&mut self) {
+        if self.advertisement_handle.take().is_some() {
+            log_connection(&self.logger, "stopped advertising".to_string(), None);
+        }
+        self.app_handle = None;
+        self.agent_handle = None;
+    }
+
     /// Send MIDI data over BLE by updating the MIDI characteristic value
     pub fn send(&mut self, data: &str) -> Result<(), Box<dyn std::error::Error>> {
         let mut value_array = self.read_value.lock().unwrap();
@@ -117,6 +257,25 @@ impl MidiBle {
         Ok(())
     }
 
+    /// Send MIDI messages to every central subscribed to notifications on the MIDI characteristic, as one BLE MIDI
+    /// packet timestamped now
+    pub fn notify(&self, messages: &[(u8, u8, u8)]) {
+        // BLE MIDI timestamps are the low 13 bits of a millisecond clock, split over the header and timestamp bytes
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u16 & 0x1FFF;
+        let mut packet = vec![0x80 | ((time >> 7) as u8 & 0x3F)];
+        for (status, data1, data2) in messages {
+            packet.extend([0x80 | (time as u8 & 0x7F), *status, *data1, *data2]);
+        }
+        // This only fails if nobody is subscribed
+        let _ = self.notify_tx.send(packet);
+    }
+
+    /// Get the timestamp of a message in a BLE MIDI packet from the packet's header byte and the message's
+    /// timestamp byte, in milliseconds (wrapping every BLE_MIDI_TIMESTAMP_PERIOD)
+    pub fn message_timestamp(header: u8, timestamp: u8) -> u16 {
+        ((header as u16 & 0x3F) << 7) | (timestamp as u16 & 0x7F)
+    }
+
     /// Check if a given byte is a status byte (note-on, note-off, aftertouch, etc.)
     pub fn is_status_byte(byte: u8) -> bool {
         byte & 0b1000_0000 != 0
@@ -127,14 +286,19 @@ impl MidiBle {
     /// Characteristics:
     /// - Read: Empty. Characteristic is required but not used. May be used in the future
     /// - Write: MIDI data. This is the characteristic that will be used to send received MIDI data
-    ///     to the core AutoDrum application. Currently only sends note-on messages, as duration is
-    ///     handled by the AutoDrum application.
-    /// - Notify: Empty. Characteristic is required but not used.
+    ///   to the core AutoDrum application. Currently only sends note-on messages, as duration is
+    ///   handled by the AutoDrum application.
+    /// - Notify: MIDI data sent to subscribed centrals, e.g. hits on the trigger pads
     async fn midi_application(&self) -> Application {
         let value_read = self.read_value.clone();
         // Might need this again later:
         // let value_write = self.read_value.clone();
         let tx_clone = self.tx.clone();
+        let negotiated_mtu = self.negotiated_mtu.clone();
+        let preferred_mtu = self.config.preferred_mtu;
+        let notify_tx = self.notify_tx.clone();
+        let write_logger = self.logger.clone();
+        let notify_logger = self.logger.clone();
 
         Application {
             services: vec![
@@ -161,8 +325,18 @@ impl MidiBle {
                             write: Some(CharacteristicWrite {
                                 write: true,
                                 write_without_response: true,
-                                method: CharacteristicWriteMethod::Fun(Box::new(move |new_value, _req| {
+                                method: CharacteristicWriteMethod::Fun(Box::new(move |new_value, req| {
                                     println!("Write value: {:?}", new_value);
+                                    // Only report the MTU when it changes, so we don't spam the console on every note. BlueZ
+                                    // doesn't tell a GATT server when a central connects, so this is the first we hear of one
+                                    if negotiated_mtu.swap(req.mtu, Ordering::Relaxed) != req.mtu {
+                                        println!("Negotiated MTU with {}: {}", req.device_address, req.mtu);
+                                        let device = Some(req.device_address.to_string());
+                                        log_connection(&write_logger, format!("negotiated MTU {}", req.mtu), device);
+                                        if let Some(preferred) = preferred_mtu.filter(|preferred| req.mtu < *preferred) {
+                                            println!("Warning: negotiated MTU is below the preferred MTU of {}", preferred);
+                                        }
+                                    }
                                     let tx = tx_clone.clone();
                                     Box::pin(async move {
                                         if let Ok(command) = Command::try_from(&new_value) {
@@ -175,8 +349,31 @@ impl MidiBle {
                             }),
                             notify: Some(CharacteristicNotify {
                                 notify: true,
-                                method: CharacteristicNotifyMethod::Fun(Box::new(move | _notifier | {
-                                    Box::pin(async move {})
+                                method: CharacteristicNotifyMethod::Fun(Box::new(move |mut notifier| {
+                                    let mut packets = notify_tx.subscribe();
+                                    let logger = notify_logger.clone();
+                                    Box::pin(async move {
+                                        println!("Central subscribed to MIDI notifications");
+                                        log_connection(&logger, "subscribed to MIDI notifications".to_string(), None);
+                                        let stopped = notifier.stopped();
+                                        tokio::pin!(stopped);
+                                        loop {
+                                            tokio::select! {
+                                                packet = packets.recv() => match packet {
+                                                    Ok(packet) => if let Err(e) = notifier.notify(packet).await {
+                                                        eprintln!("Unable to send MIDI notification: {}", e);
+                                                        break;
+                                                    },
+                                                    // Packets that couldn't be sent in time are stale anyway
+                                                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                                    Err(broadcast::error::RecvError::Closed) => break,
+                                                },
+                                                _ = &mut stopped => break,
+                                            }
+                                        }
+                                        println!("Central unsubscribed from MIDI notifications");
+                                        log_connection(&logger, "unsubscribed from MIDI notifications".to_string(), None);
+                                    })
                                 })),
                                 ..Default::default()
                             }),
@@ -191,3 +388,8 @@ impl MidiBle {
     }
 
 }
+
+/// Log a connection event, with the address of the central it concerns if known
+fn log_connection(logger: &Mutex<Logger>, event: String, device: Option<String>) {
+    logger.lock().unwrap().log(LogEntry::Connection(ConnectionLogEntry { time: timestamp(), event, device }));
+}
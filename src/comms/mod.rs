@@ -1,2 +1,4 @@
 pub mod midi_ble;
 pub mod remote_command;
+pub mod console;
+pub mod cc_mapping;
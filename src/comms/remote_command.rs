@@ -1,8 +1,34 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::playback::articulation::Articulation;
+use crate::playback::recorder::DEFAULT_RECORDING_DIR;
+
 pub const MIDI_NOTE_ON_BYTE: u8 = 0x90;
 pub const MIDI_NOTE_OFF_BYTE: u8 = 0x80;
+pub const MIDI_CONTROL_CHANGE_BYTE: u8 = 0xB0;
+pub const MIDI_PROGRAM_CHANGE_BYTE: u8 = 0xC0;
 pub const READ_SYSTEM_CONSTANTS_COMMAND_BYTE: u8 = 0x00;
 pub const READ_CONFIG_COMMAND_BYTE: u8 = 0x01;
 pub const WRITE_CONFIG_COMMAND_BYTE: u8 = 0x02;
+pub const WRITE_BLE_CONFIG_COMMAND_BYTE: u8 = 0x03;
+pub const SWITCH_KIT_COMMAND_BYTE: u8 = 0x04;
+pub const ARTICULATION_COMMAND_BYTE: u8 = 0x05;
+pub const START_RECORDING_COMMAND_BYTE: u8 = 0x06;
+pub const STOP_RECORDING_COMMAND_BYTE: u8 = 0x07;
+
+/// Index of the command byte in a remote message (after the BLE MIDI header and timestamp bytes)
+const COMMAND_BYTE_INDEX: usize = 2;
+
+/// The JSON payload of an articulation command, e.g.
+/// `{"striker": "Snare", "velocity": 100, "articulation": {"type": "Flam", "grace_offset": 25}}`
+#[derive(Deserialize)]
+struct ArticulationRequest {
+    striker: String,
+    velocity: u8,
+    articulation: Articulation,
+}
 
 /// Represents a general command received from the remote
 #[derive(Debug, Clone)]
@@ -11,6 +37,44 @@ pub enum Command {
     ReadSystemConstants(Vec<u8>),
     ReadConfiguration(Vec<u8>),
     WriteConfiguration(Vec<u8>),
+    WriteBleConfiguration(Vec<u8>),
+    /// A hit on a trigger pad, to play on the strikers and send out over BLE MIDI
+    Trigger { note: u8, velocity: u8 },
+    /// Fire the named striker or modifier at the given velocity
+    Fire { name: String, velocity: u8 },
+    /// Fire the named striker a number of times as sixteenth notes at the given tempo
+    Roll { name: String, hits: u32, bpm: f64, velocity: u8 },
+    /// Play an articulation (flam, drag, roll...) on the named striker
+    Articulate { striker: String, articulation: Articulation, velocity: u8 },
+    /// Activate or deactivate the named modifier
+    SetModifier { name: String, active: bool },
+    /// Change a parameter (e.g. max_hit_duration) of the named striker
+    SetParameter { target: String, parameter: String, value: f64 },
+    /// Save the current configuration to the configuration file
+    SaveConfiguration,
+    /// Reload the configuration file, keeping the current configuration if the file has problems
+    ReloadConfiguration,
+    /// Restore the configuration file from a backup this many versions back (1 is the most recent) and apply it
+    RollbackConfiguration { steps: usize },
+    /// Switch to the named kit
+    SwitchKit { name: String },
+    /// Start recording the hits played to a MIDI file at the given path (or a new one in the recordings directory)
+    StartRecording { path: Option<String> },
+    /// Stop recording and save the MIDI file
+    StopRecording,
+    /// Report the state of every striker and modifier
+    Status,
+    /// Cancel everything scheduled and force every output off
+    Panic,
+    /// Shut down the application
+    Quit,
+}
+
+impl Command {
+    /// Get the data following the command byte of a raw remote message
+    pub fn payload(message: &[u8]) -> &[u8] {
+        message.get(COMMAND_BYTE_INDEX + 1..).unwrap_or_default()
+    }
 }
 
 impl TryFrom<&Vec<u8>> for Command {
@@ -20,11 +84,39 @@ impl TryFrom<&Vec<u8>> for Command {
         if message.len() < 3 {
             return Err("Message too short".to_string());
         }
-        match message.get(2).unwrap().clone() {
+        match message[COMMAND_BYTE_INDEX] {
             MIDI_NOTE_ON_BYTE | MIDI_NOTE_OFF_BYTE => Ok(Command::MIDI(message.clone())),
+            // Controllers can be mapped on any channel, and kits switched from any channel
+            status if status & 0xF0 == MIDI_CONTROL_CHANGE_BYTE => Ok(Command::MIDI(message.clone())),
+            status if status & 0xF0 == MIDI_PROGRAM_CHANGE_BYTE => Ok(Command::MIDI(message.clone())),
             READ_SYSTEM_CONSTANTS_COMMAND_BYTE => Ok(Command::ReadSystemConstants(message.clone())),
             READ_CONFIG_COMMAND_BYTE => Ok(Command::ReadConfiguration(message.clone())),
             WRITE_CONFIG_COMMAND_BYTE => Ok(Command::WriteConfiguration(message.clone())),
+            WRITE_BLE_CONFIG_COMMAND_BYTE => Ok(Command::WriteBleConfiguration(message.clone())),
+            SWITCH_KIT_COMMAND_BYTE => {
+                let name = String::from_utf8(Command::payload(message).to_vec()).map_err(|_| "Kit name is not valid UTF-8".to_string())?;
+                Ok(Command::SwitchKit { name })
+            },
+            ARTICULATION_COMMAND_BYTE => {
+                let request: ArticulationRequest = serde_json::from_slice(Command::payload(message))
+                    .map_err(|e| format!("Invalid articulation: {}", e))?;
+                if request.velocity > 127 {
+                    return Err(format!("Velocity must be between 0 and 127, got {}", request.velocity));
+                }
+                request.articulation.check()?;
+                Ok(Command::Articulate { striker: request.striker, articulation: request.articulation, velocity: request.velocity })
+            },
+            // Only a file name is taken from the remote, so it can't write anywhere but the recordings directory
+            START_RECORDING_COMMAND_BYTE => {
+                let name = String::from_utf8(Command::payload(message).to_vec()).map_err(|_| "Recording name is not valid UTF-8".to_string())?;
+                let path = match Path::new(name.trim()).file_name() {
+                    Some(file_name) => Some(Path::new(DEFAULT_RECORDING_DIR).join(file_name).to_string_lossy().into_owned()),
+                    None if name.trim().is_empty() => None,
+                    None => return Err(format!("Invalid recording name {}", name)),
+                };
+                Ok(Command::StartRecording { path })
+            },
+            STOP_RECORDING_COMMAND_BYTE => Ok(Command::StopRecording),
             _ => Err("Unknown command".to_string()),
         }
     }
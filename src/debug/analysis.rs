@@ -0,0 +1,456 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use crate::comms::midi_ble::BLE_MIDI_TIMESTAMP_PERIOD;
+use crate::debug::logger::{log_files, LogEntry, StrikeLogEntry};
+
+/// Name of the report that covers every striker together
+const ALL_STRIKERS: &str = "All strikers";
+/// Start of the names of the JSON hit logs saved on exit before logs were streamed
+const LEGACY_LOG_PREFIXES: [&str; 3] = ["hit_log_", "retrigger_log_", "verification_log_"];
+/// Columns of the per-hit CSV
+const CSV_HEADER: &str = "striker,note,time_s,event,velocity,planned_ms,actual_ms,duration_error_ms,inter_onset_ms,ble_inter_onset_ms,onset_error_ms,duty_cycle";
+
+/// How an analysis is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// A summary per striker, to read
+    Text,
+    /// The summary per striker as JSON
+    Json,
+    /// A row per hit (and per dropped, queued, retriggered or merged hit), to plot
+    Csv,
+}
+
+/// How the spread of a set of measurements looks, in milliseconds
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub mean: f64,
+    /// Standard deviation, i.e. the jitter
+    pub std_dev: f64,
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    /// Describe a set of measurements (None if there aren't any)
+    pub fn of(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / count as f64;
+        let variance = sorted.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / count as f64;
+        // Nearest rank, so every percentile is a value that was actually measured
+        let percentile = |p: f64| sorted[((p / 100.0 * count as f64).ceil() as usize).clamp(1, count) - 1];
+        Some(Self {
+            count,
+            mean,
+            std_dev: variance.sqrt(),
+            min: sorted[0],
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p99: percentile(99.0),
+            max: sorted[count - 1],
+        })
+    }
+}
+
+/// What happened to hits that came in while their striker was mid-hit
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct RetriggerCounts {
+    pub dropped: usize,
+    pub queued: usize,
+    pub retriggered: usize,
+    pub merged: usize,
+}
+
+/// Timing statistics for one striker, or for every striker together
+#[derive(Debug, Clone, Serialize)]
+pub struct StrikerReport {
+    pub name: String,
+    /// Note number of the striker (None for every striker together)
+    pub note: Option<u8>,
+    pub hits: usize,
+    /// How much longer than planned hits were held, in milliseconds
+    pub duration_error: Option<Distribution>,
+    /// Milliseconds between the starts of consecutive hits
+    pub inter_onset: Option<Distribution>,
+    /// How much longer the gap between consecutive hits was than the gap between their BLE MIDI timestamps, in
+    /// milliseconds. Only hits played straight from BLE MIDI have timestamps
+    pub onset_error: Option<Distribution>,
+    pub retriggers: RetriggerCounts,
+    /// Share of the logged time the striker was on
+    pub duty_cycle: f64,
+    /// Highest share of any window the striker was on, measured at the end of every hit
+    pub peak_duty_cycle: f64,
+}
+
+/// One row of the per-hit CSV
+#[derive(Debug, Clone)]
+struct HitRow {
+    striker: String,
+    note: u8,
+    /// Seconds since the start of the logs
+    time: f64,
+    /// "hit", or what was done with a hit that came in while the striker was mid-hit
+    event: String,
+    velocity: u8,
+    planned_ms: Option<f64>,
+    actual_ms: Option<f64>,
+    inter_onset_ms: Option<f64>,
+    ble_inter_onset_ms: Option<f64>,
+    duty_cycle: Option<f64>,
+}
+
+/// The analysis of a set of logs
+#[derive(Debug, Clone, Serialize)]
+pub struct LogReport {
+    /// Files the entries were read from
+    pub files: Vec<PathBuf>,
+    pub entries: usize,
+    /// Lines or entries that couldn't be read, e.g. the last line of a log cut short by a power cut
+    pub skipped: usize,
+    /// Seconds from the first entry to the end of the last hit
+    pub span: f64,
+    /// Length of the window peak duty cycle was measured over, in seconds (no longer than the span)
+    pub window: f64,
+    pub strikers: Vec<StrikerReport>,
+    pub all: StrikerReport,
+    #[serde(skip)]
+    rows: Vec<HitRow>,
+}
+
+/// Entries read from a set of logs
+struct LoadedLogs {
+    files: Vec<PathBuf>,
+    entries: Vec<LogEntry>,
+    skipped: usize,
+}
+
+/// Analyse the hits in the given log files, or every log in the given directories, with peak duty cycle measured
+/// over a window of the given number of seconds. Hits are logged when logging is enabled or running with --debug
+pub fn analyze_logs(paths: &[String], window: f64) -> Result<LogReport, String> {
+    if !(window > 0.0 && window.is_finite()) {
+        return Err(format!("The duty cycle window must be greater than 0, got {}", window));
+    }
+    let logs = load_logs(paths)?;
+    let mut hits: Vec<&StrikeLogEntry> = logs.entries.iter()
+        .filter_map(|entry| match entry { LogEntry::Strike(hit) => Some(hit), _ => None })
+        .collect();
+    if hits.is_empty() {
+        return Err(format!(
+            "No hits in {} (hits are logged when logging is enabled or running with --debug)",
+            if paths.is_empty() { "the logs".to_string() } else { paths.join(", ") },
+        ));
+    }
+    hits.sort_by_key(|hit| hit.time);
+    let start = logs.entries.iter().map(LogEntry::time).min().unwrap_or(hits[0].time) as f64;
+    let end = hits.iter().map(|hit| hit.time as f64 + on_ms(hit)).fold(start, f64::max);
+    let span_ms = (end - start).max(1.0);
+    // A window longer than the logs would understate the peak
+    let window_ms = (window * 1000.0).min(span_ms);
+
+    let strikers_hit: BTreeSet<(u8, String)> = hits.iter().map(|hit| (hit.note_num, hit.striker_name.clone())).collect();
+    let mut rows = vec![];
+    let mut strikers = vec![];
+    for (note, name) in strikers_hit {
+        let striker_hits: Vec<&StrikeLogEntry> = hits.iter().copied()
+            .filter(|hit| hit.note_num == note && hit.striker_name == name)
+            .collect();
+        let (report, striker_rows) = analyze_hits(&name, Some(note), &striker_hits, &logs.entries, start, span_ms, window_ms);
+        strikers.push(report);
+        rows.extend(striker_rows);
+    }
+    let (all, _) = analyze_hits(ALL_STRIKERS, None, &hits, &logs.entries, start, span_ms, window_ms);
+    rows.sort_by(|a, b| a.time.total_cmp(&b.time));
+    Ok(LogReport {
+        files: logs.files,
+        entries: logs.entries.len(),
+        skipped: logs.skipped,
+        span: span_ms / 1000.0,
+        window: window_ms / 1000.0,
+        strikers,
+        all,
+        rows,
+    })
+}
+
+/// Work out the statistics of a set of hits (sorted by time), counting the retriggers of the striker with the given
+/// name (or every striker if no note is given). Returns the report and a CSV row per hit and retrigger
+fn analyze_hits(
+    name: &str,
+    note: Option<u8>,
+    hits: &[&StrikeLogEntry],
+    entries: &[LogEntry],
+    start: f64,
+    span_ms: f64,
+    window_ms: f64,
+) -> (StrikerReport, Vec<HitRow>) {
+    let seconds = |time: u64| (time as f64 - start) / 1000.0;
+    let mut rows = vec![];
+    let mut retriggers = RetriggerCounts::default();
+    for entry in entries {
+        let LogEntry::Retrigger(retrigger) = entry else { continue };
+        if note.is_some_and(|note| retrigger.note_num != note || retrigger.striker_name != name) {
+            continue;
+        }
+        match retrigger.outcome.as_str() {
+            "dropped" => retriggers.dropped += 1,
+            "queued" => retriggers.queued += 1,
+            "retriggered" => retriggers.retriggered += 1,
+            "merged" => retriggers.merged += 1,
+            _ => {},
+        }
+        rows.push(HitRow {
+            striker: retrigger.striker_name.clone(),
+            note: retrigger.note_num,
+            time: seconds(retrigger.time),
+            event: retrigger.outcome.clone(),
+            velocity: retrigger.velocity,
+            planned_ms: None,
+            actual_ms: None,
+            inter_onset_ms: None,
+            ble_inter_onset_ms: None,
+            duty_cycle: None,
+        });
+    }
+
+    let actual_ms = |hit: &StrikeLogEntry| hit.actual_duration_ns.map(|ns| ns as f64 / 1e6);
+    let planned_ms = |hit: &StrikeLogEntry| hit.planned_duration_ns as f64 / 1e6;
+    let duration_errors: Vec<f64> = hits.iter().filter_map(|hit| Some(actual_ms(hit)? - planned_ms(hit))).collect();
+    let mut inter_onsets = vec![];
+    let mut onset_errors = vec![];
+    let mut peak_duty_cycle: f64 = 0.0;
+    let longest = hits.iter().map(|hit| on_ms(hit)).fold(0.0, f64::max);
+    for (i, hit) in hits.iter().enumerate() {
+        let previous = i.checked_sub(1).map(|j| hits[j]);
+        let inter_onset = previous.map(|previous| (hit.time - previous.time) as f64);
+        let ble_inter_onset = previous.zip(inter_onset).and_then(|(previous, inter_onset)| {
+            Some(ble_interval(previous.ble_timestamp?, hit.ble_timestamp?, inter_onset))
+        });
+        inter_onsets.extend(inter_onset);
+        if let Some((inter_onset, ble_inter_onset)) = inter_onset.zip(ble_inter_onset) {
+            onset_errors.push(inter_onset - ble_inter_onset);
+        }
+        // On time within the window that ends as this hit does
+        let hit_end = hit.time as f64 + on_ms(hit);
+        let window_start = hit_end - window_ms;
+        let on_ms: f64 = hits[..=i].iter().rev()
+            .take_while(|earlier| earlier.time as f64 + longest > window_start)
+            .map(|earlier| {
+                let earlier_start = earlier.time as f64;
+                (earlier_start + on_ms(earlier)).min(hit_end) - earlier_start.max(window_start)
+            })
+            .filter(|overlap| *overlap > 0.0)
+            .sum();
+        let duty_cycle = on_ms / window_ms;
+        peak_duty_cycle = peak_duty_cycle.max(duty_cycle);
+        rows.push(HitRow {
+            striker: hit.striker_name.clone(),
+            note: hit.note_num,
+            time: seconds(hit.time),
+            event: "hit".to_string(),
+            velocity: hit.velocity,
+            planned_ms: Some(planned_ms(hit)),
+            actual_ms: actual_ms(hit),
+            inter_onset_ms: inter_onset,
+            ble_inter_onset_ms: ble_inter_onset,
+            duty_cycle: Some(duty_cycle),
+        });
+    }
+    let total_on_ms: f64 = hits.iter().map(|hit| on_ms(hit)).sum();
+    let report = StrikerReport {
+        name: name.to_string(),
+        note,
+        hits: hits.len(),
+        duration_error: Distribution::of(&duration_errors),
+        inter_onset: Distribution::of(&inter_onsets),
+        onset_error: Distribution::of(&onset_errors),
+        retriggers,
+        duty_cycle: total_on_ms / span_ms,
+        peak_duty_cycle,
+    };
+    (report, rows)
+}
+
+/// Get how long a hit held its output on in milliseconds: as measured if it was, otherwise as planned
+fn on_ms(hit: &StrikeLogEntry) -> f64 {
+    hit.actual_duration_ns.unwrap_or(hit.planned_duration_ns) as f64 / 1e6
+}
+
+/// Get the milliseconds between two BLE MIDI timestamps. They wrap around every BLE_MIDI_TIMESTAMP_PERIOD, so the
+/// number of wraps is taken as whichever brings the gap closest to the gap measured when the hits were fired
+fn ble_interval(from: u16, to: u16, measured: f64) -> f64 {
+    let period = BLE_MIDI_TIMESTAMP_PERIOD as f64;
+    let gap = (to as f64 - from as f64).rem_euclid(period);
+    gap + ((measured - gap) / period).round() * period
+}
+
+/// Read every entry in the given log files, or every log in the given directories: NDJSON logs, and the JSON hit,
+/// retrigger and verification logs that used to be saved on exit
+fn load_logs(paths: &[String]) -> Result<LoadedLogs, String> {
+    let mut files = vec![];
+    for path in paths {
+        let path = Path::new(path);
+        if path.is_dir() {
+            let mut found = log_files(path, "ndjson").map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+            let legacy = std::fs::read_dir(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| {
+                    let name = file.file_name().unwrap_or_default().to_string_lossy();
+                    name.ends_with(".json") && LEGACY_LOG_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+                });
+            found.extend(legacy);
+            found.sort();
+            if found.is_empty() {
+                let csv_logs = log_files(path, "csv").map(|csv_logs| csv_logs.len()).unwrap_or(0);
+                return Err(match csv_logs {
+                    0 => format!("No logs in {}", path.display()),
+                    _ => format!(
+                        "{} only has CSV logs, which don't keep everything needed to analyse them. Set logging.format to Ndjson",
+                        path.display(),
+                    ),
+                });
+            }
+            files.extend(found);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    let mut logs = LoadedLogs { files: vec![], entries: vec![], skipped: 0 };
+    for file in files {
+        let text = std::fs::read_to_string(&file).map_err(|e| format!("Unable to read {}: {}", file.display(), e))?;
+        match file.extension().and_then(|extension| extension.to_str()) {
+            Some("ndjson") => {
+                for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                    match serde_json::from_str::<LogEntry>(line) {
+                        Ok(entry) => logs.entries.push(entry),
+                        Err(_) => logs.skipped += 1,
+                    }
+                }
+            },
+            Some("json") => {
+                let values: Vec<serde_json::Value> = serde_json::from_str(&text)
+                    .map_err(|e| format!("{} is not a hit log: {}", file.display(), e))?;
+                for value in values {
+                    match legacy_entry(value) {
+                        Some(entry) => logs.entries.push(entry),
+                        None => logs.skipped += 1,
+                    }
+                }
+            },
+            Some("csv") => return Err(format!(
+                "{} is a CSV log, which doesn't keep everything needed to analyse it. Set logging.format to Ndjson",
+                file.display(),
+            )),
+            _ => return Err(format!("{} is not a log (expected .ndjson or .json)", file.display())),
+        }
+        logs.files.push(file);
+    }
+    Ok(logs)
+}
+
+/// Read an entry of the JSON logs that used to be saved on exit, which weren't tagged with their type
+fn legacy_entry(value: serde_json::Value) -> Option<LogEntry> {
+    serde_json::from_value(value.clone()).map(LogEntry::Strike)
+        .or_else(|_| serde_json::from_value(value.clone()).map(LogEntry::Retrigger))
+        .or_else(|_| serde_json::from_value(value).map(LogEntry::Verification))
+        .ok()
+}
+
+impl LogReport {
+    /// Write the report in the given format
+    pub fn format(&self, format: ReportFormat) -> Result<String, String> {
+        match format {
+            ReportFormat::Text => Ok(self.to_text()),
+            ReportFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            ReportFormat::Csv => Ok(self.to_csv()),
+        }
+    }
+
+    /// Get a summary per striker, to read
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(
+            text,
+            "Analysed {} entries from {} files over {:.1}s{}",
+            self.entries, self.files.len(), self.span,
+            if self.skipped > 0 { format!(" ({} unreadable entries skipped)", self.skipped) } else { String::new() },
+        );
+        for report in self.strikers.iter().chain([&self.all]) {
+            let _ = match report.note {
+                Some(note) => writeln!(text, "{} (note {}): {} hits", report.name, note, report.hits),
+                None => writeln!(text, "{}: {} hits", report.name, report.hits),
+            };
+            if let Some(error) = report.duration_error {
+                let _ = writeln!(
+                    text,
+                    "  duration error: mean {:+.2}ms, jitter {:.2}ms, p50 {:+.2}ms, p90 {:+.2}ms, p99 {:+.2}ms ({:+.2} to {:+.2}ms)",
+                    error.mean, error.std_dev, error.p50, error.p90, error.p99, error.min, error.max,
+                );
+            }
+            match (report.inter_onset, report.onset_error) {
+                (Some(inter_onset), Some(error)) => {
+                    let _ = writeln!(
+                        text,
+                        "  inter-onset: mean {:.1}ms, min {:.1}ms; against BLE timestamps ({} intervals): mean {:+.2}ms, jitter {:.2}ms, p99 {:+.2}ms ({:+.1} to {:+.1}ms)",
+                        inter_onset.mean, inter_onset.min, error.count, error.mean, error.std_dev, error.p99, error.min, error.max,
+                    );
+                },
+                (Some(inter_onset), None) => {
+                    let _ = writeln!(
+                        text,
+                        "  inter-onset: mean {:.1}ms, min {:.1}ms, jitter {:.2}ms (no BLE timestamps to compare against)",
+                        inter_onset.mean, inter_onset.min, inter_onset.std_dev,
+                    );
+                },
+                _ => {},
+            }
+            let retriggers = report.retriggers;
+            let _ = writeln!(
+                text,
+                "  retriggers: {} dropped, {} queued, {} retriggered, {} merged",
+                retriggers.dropped, retriggers.queued, retriggers.retriggered, retriggers.merged,
+            );
+            let _ = writeln!(
+                text,
+                "  duty cycle: {:.2}% overall, peak {:.2}% over {:.1}s",
+                report.duty_cycle * 100.0, report.peak_duty_cycle * 100.0, self.window,
+            );
+        }
+        text
+    }
+
+    /// Get a row per hit and per retrigger, in time order, to plot
+    fn to_csv(&self) -> String {
+        let optional = |value: Option<f64>| value.map(|value| format!("{:.3}", value)).unwrap_or_default();
+        let mut csv = format!("{}\n", CSV_HEADER);
+        for row in &self.rows {
+            let duration_error = row.actual_ms.zip(row.planned_ms).map(|(actual, planned)| actual - planned);
+            let onset_error = row.inter_onset_ms.zip(row.ble_inter_onset_ms).map(|(measured, ble)| measured - ble);
+            let striker = if row.striker.contains([',', '"']) {
+                format!("\"{}\"", row.striker.replace('"', "\"\""))
+            } else {
+                row.striker.clone()
+            };
+            let _ = writeln!(
+                csv,
+                "{},{},{:.3},{},{},{},{},{},{},{},{},{}",
+                striker, row.note, row.time, row.event, row.velocity,
+                optional(row.planned_ms), optional(row.actual_ms), optional(duration_error),
+                optional(row.inter_onset_ms), optional(row.ble_inter_onset_ms), optional(onset_error),
+                row.duty_cycle.map(|duty_cycle| format!("{:.5}", duty_cycle)).unwrap_or_default(),
+            );
+        }
+        csv
+    }
+}
@@ -1,10 +1,16 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
 use serde::{Deserialize, Serialize};
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use crate::hardware::striker_hardware_util::StrikerHardwareKind;
+use crate::hardware::hit_sensor::HitStats;
+use crate::hardware::pin_address::PinAddress;
+use crate::hardware::striker::{RetriggerPolicy, RetriggerStats};
+use crate::hardware::striker_hardware_util::HardwareKind;
 
 /// A log entry representing a Striker fire
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StrikeLogEntry {
     /// UNIX timestamp but in milliseconds
     pub time: u64,
@@ -12,64 +18,446 @@ pub struct StrikeLogEntry {
     pub ms_since_last: u64,
     /// nanosecond duration calculated striker duration based on striker type and velocity
     pub planned_duration_ns: u64,
-    /// nanosecond duration between when the striker was triggered and when it was turned off
-    pub actual_duration_ns: u64,
+    /// nanosecond duration between when the striker was triggered and when it was turned off, only measured when
+    /// running with --debug (hits are logged as they're fired otherwise)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual_duration_ns: Option<u64>,
     /// The type of striker used
-    pub striker_kind: StrikerHardwareKind,
+    pub striker_kind: HardwareKind,
     /// The raw MIDI data that triggered the hit
     pub midi_data: (u8, u8, u8),
     /// The note number of the midi data
     pub note_num: u8,
     /// The velocity value of the midi data
     pub velocity: u8,
+    /// The velocity the striker was fired at, after the striker's gain and offset, the velocity scale and dynamics
+    pub processed_velocity: u8,
+    /// The name of the striker that was fired
+    pub striker_name: String,
+    /// The pin of the striker that was fired
+    pub target_pin: PinAddress,
+    /// The BLE MIDI timestamp of the note (milliseconds, wrapping every 8192), if it was played as soon as it
+    /// arrived over BLE
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ble_timestamp: Option<u16>,
+}
+
+/// A log entry representing a hit that came in while its striker was still mid-hit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetriggerLogEntry {
+    /// UNIX timestamp but in milliseconds
+    pub time: u64,
+    /// The name of the striker that was busy
+    pub striker_name: String,
+    /// The note number of the hit
+    pub note_num: u8,
+    /// The velocity of the hit
+    pub velocity: u8,
+    /// The striker's retrigger policy
+    pub policy: RetriggerPolicy,
+    /// What was done with the hit: dropped, queued, retriggered or merged
+    pub outcome: String,
+    /// milliseconds until the hit is played again, if it was queued
+    pub queued_for_ms: Option<u64>,
+    /// How many hits the striker has dropped and queued so far, including this one
+    pub totals: RetriggerStats,
+}
+
+/// A log entry representing a hit checked against its striker's sensor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerificationLogEntry {
+    /// UNIX timestamp but in milliseconds
+    pub time: u64,
     /// The name of the striker that was fired
     pub striker_name: String,
-    /// The raspberry pi pin number of the striker that was fired
-    pub target_pin: u8,
+    /// The note number of the hit
+    pub note_num: u8,
+    /// The velocity of the hit
+    pub velocity: u8,
+    /// Whether the sensor picked up the impact within its window
+    pub landed: bool,
+    /// milliseconds from the start of the hit to its impact, if it landed
+    pub latency_ms: Option<f64>,
+    /// How many of the striker's hits have been verified and missed so far, including this one
+    pub totals: HitStats,
 }
 
+/// A log entry representing a modifier being switched on or off
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModifierLogEntry {
+    /// UNIX timestamp but in milliseconds
+    pub time: u64,
+    /// The name of the modifier
+    pub modifier_name: String,
+    /// The note number of the modifier
+    pub note_num: u8,
+    /// Whether the modifier was switched on
+    pub active: bool,
+}
+
+/// A log entry representing a change to the running configuration (a reload, a kit switch, a parameter set...)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigurationLogEntry {
+    /// UNIX timestamp but in milliseconds
+    pub time: u64,
+    /// What changed
+    pub change: String,
+}
+
+/// A log entry representing an error that was reported and carried on from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorLogEntry {
+    /// UNIX timestamp but in milliseconds
+    pub time: u64,
+    pub message: String,
+}
+
+/// A log entry representing a BLE central connecting, subscribing or going away, or advertising starting or stopping
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionLogEntry {
+    /// UNIX timestamp but in milliseconds
+    pub time: u64,
+    /// What happened
+    pub event: String,
+    /// Address of the central, if known
+    pub device: Option<String>,
+}
+
+/// Anything that can be logged. Written as one JSON object per line, tagged with its type, or as one CSV row
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum LogEntry {
     /// represents a striker fire triggered by incoming MIDI data
     Strike(StrikeLogEntry),
+    /// represents a hit that came in while its striker was mid-hit
+    Retrigger(RetriggerLogEntry),
+    /// represents a hit checked against its striker's sensor
+    Verification(VerificationLogEntry),
+    /// represents a modifier being switched on or off
+    Modifier(ModifierLogEntry),
+    /// represents a change to the running configuration
+    Configuration(ConfigurationLogEntry),
+    /// represents an error that was carried on from
+    Error(ErrorLogEntry),
+    /// represents a BLE connection event
+    Connection(ConnectionLogEntry),
 }
 
+pub const DEFAULT_LOG_DIR: &str = "./logs";
+/// Start of the name of every log file, followed by when it was started
+const LOG_FILE_PREFIX: &str = "autodrum_";
+/// Columns of a CSV log. Every entry type fills in the ones that apply to it
+const CSV_HEADER: &str = "time,type,name,note,velocity,value,detail";
+
+/// How log files are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogFormat {
+    /// One JSON object per line, tagged with its type
+    #[default]
+    Ndjson,
+    /// One row per entry, with the same columns for every type
+    Csv,
+}
+
+impl LogFormat {
+    /// Get the extension of log files in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LogFormat::Ndjson => "ndjson",
+            LogFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Logging settings, loaded from the `logging` section of the configuration
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Always log every hit, modifier changes, configuration changes, errors, connections and hit sensor checks.
+    /// Running with --debug logs these whether or not this is set, and measures how long each hit actually lasted
+    pub enabled: bool,
+    /// Directory log files are written to (--log-dir overrides this)
+    pub directory: String,
+    pub format: LogFormat,
+    /// Start a new file once the current one reaches this many kilobytes
+    pub max_file_size_kb: u64,
+    /// Start a new file once the current one is this many minutes old (0 to only start one by size)
+    pub max_file_age_minutes: f64,
+    /// How many files to keep, the oldest being deleted as new ones are started (0 keeps every file)
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            directory: DEFAULT_LOG_DIR.to_string(),
+            format: LogFormat::Ndjson,
+            max_file_size_kb: 10 * 1024,
+            max_file_age_minutes: 60.0,
+            max_files: 20,
+        }
+    }
+}
+
+/// The file entries are currently written to
+struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    opened: Instant,
+    /// Bytes written so far
+    size: u64,
+}
+
+/// Writes log entries to disk as they come in, starting a new file when the current one gets too big or too old
 pub struct Logger {
-    /// A stack of all the hits that have been logged
-    hit_log: Vec<StrikeLogEntry>,
+    config: LoggingConfig,
+    /// Directory given on the command line, which takes the place of the configured one
+    directory_override: Option<PathBuf>,
+    /// Whether every hit is logged, not just the events
+    debug: bool,
+    file: Option<LogFile>,
+    /// Set once writing has failed, so the failure is only reported once until writing works again
+    failed: bool,
+    /// UNIX timestamp in milliseconds of the most recent hit
+    last_hit_time: Option<u64>,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new(LoggingConfig::default(), None, false)
+    }
 }
 
 impl Logger {
-    /// Create a new logger
-    pub fn new() -> Self {
+    /// Create a new logger with the given settings, writing to the given directory instead of the configured one if
+    /// there is one. With `debug` it logs even if the settings don't enable it
+    pub fn new(config: LoggingConfig, directory_override: Option<String>, debug: bool) -> Self {
         Self {
-            hit_log: vec![],
+            config,
+            directory_override: directory_override.map(PathBuf::from),
+            debug,
+            file: None,
+            failed: false,
+            last_hit_time: None,
         }
     }
 
-    /// Log a given entry to its respective collection
+    /// Get the logging settings in use
+    pub fn config(&self) -> &LoggingConfig {
+        &self.config
+    }
+
+    /// Change the logging settings, starting a new file if they changed
+    pub fn configure(&mut self, config: LoggingConfig) {
+        if config != self.config {
+            self.flush();
+            self.file = None;
+            self.config = config;
+        }
+    }
+
+    /// Check whether entries are written at all
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled || self.debug
+    }
+
+    /// Get the directory log files are written to
+    pub fn directory(&self) -> PathBuf {
+        self.directory_override.clone().unwrap_or_else(|| PathBuf::from(&self.config.directory))
+    }
+
+    /// Get the file entries are currently being written to, if one is open
+    pub fn current_file(&self) -> Option<&Path> {
+        self.file.as_ref().map(|file| file.path.as_path())
+    }
+
+    /// Write an entry to the current log file, if logging is enabled. A failure to write is reported rather than
+    /// returned, since losing the log mustn't stop the drum
     pub fn log(&mut self, entry: LogEntry) {
-        match entry {
-            LogEntry::Strike(hit) => self.hit_log.push(hit),
+        if !self.is_enabled() {
+            return;
+        }
+        match self.write(&entry) {
+            Ok(()) => self.failed = false,
+            Err(e) => {
+                if !self.failed {
+                    eprintln!("Unable to write to the log in {}: {}", self.directory().display(), e);
+                }
+                self.failed = true;
+                // Try a fresh file next time
+                self.file = None;
+            },
         }
     }
 
-    /// Save the log collections to their respective files
-    pub async fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.has_data() {
-            let mut file = File::create(format!("./logs/hit_log_{:?}.json", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap())).await?;
-            file.write_all(serde_json::to_string(&self.hit_log).unwrap().as_bytes()).await?;
-            println!("Hit log saved to file");
+    /// Write an entry as a line of its own, flushed straight away so nothing is lost if the process dies
+    fn write(&mut self, entry: &LogEntry) -> std::io::Result<()> {
+        let mut line = match self.config.format {
+            LogFormat::Ndjson => serde_json::to_string(entry)?,
+            LogFormat::Csv => entry.csv_row(),
+        };
+        line.push('\n');
+        if self.file.as_ref().is_some_and(|file| self.is_due_for_rotation(file)) {
+            self.file = None;
         }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(self.open()?),
+        };
+        file.writer.write_all(line.as_bytes())?;
+        file.writer.flush()?;
+        file.size += line.len() as u64;
         Ok(())
     }
 
-    /// Check if any of the collections have data
-    pub fn has_data(&self) -> bool {
-        !self.hit_log.is_empty()
+    /// Check whether a file is too big or too old to keep writing to
+    fn is_due_for_rotation(&self, file: &LogFile) -> bool {
+        let too_big = file.size >= self.config.max_file_size_kb * 1024;
+        let too_old = self.config.max_file_age_minutes > 0.0
+            && file.opened.elapsed().as_secs_f64() >= self.config.max_file_age_minutes * 60.0;
+        too_big || too_old
     }
 
-    /// Get the time of the last hit
-    pub fn last_hit_time(&self) -> Option<u64> {
-        self.hit_log.last().map(|hit| hit.time)
+    /// Start a new log file named after the time, deleting the oldest files beyond the number kept
+    fn open(&self) -> std::io::Result<LogFile> {
+        let directory = self.directory();
+        std::fs::create_dir_all(&directory)?;
+        let extension = self.config.format.extension();
+        let started = timestamp();
+        let mut path = directory.join(format!("{}{}.{}", LOG_FILE_PREFIX, started, extension));
+        // Files started within the same millisecond are numbered rather than written over
+        let mut number = 1;
+        while path.exists() {
+            path = directory.join(format!("{}{}_{}.{}", LOG_FILE_PREFIX, started, number, extension));
+            number += 1;
+        }
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut size = 0;
+        if self.config.format == LogFormat::Csv {
+            writeln!(writer, "{}", CSV_HEADER)?;
+            size += CSV_HEADER.len() as u64 + 1;
+        }
+        if self.config.max_files > 0 {
+            let mut files = log_files(&directory, extension)?;
+            // Named after when they were started, so sorting by name puts the oldest first
+            files.sort();
+            let excess = files.len().saturating_sub(self.config.max_files);
+            for old in &files[..excess] {
+                if let Err(e) = std::fs::remove_file(old) {
+                    eprintln!("Unable to delete old log file {}: {}", old.display(), e);
+                }
+            }
+        }
+        Ok(LogFile { path, writer, opened: Instant::now(), size })
+    }
+
+    /// Make sure everything logged so far is on disk
+    pub fn flush(&mut self) {
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = file.writer.flush() {
+                eprintln!("Unable to write to the log {}: {}", file.path.display(), e);
+            }
+        }
+    }
+
+    /// Record the time of a hit as it's fired (its entry is only logged once the hit finishes), returning the
+    /// milliseconds since the previous hit (0 if this is the first hit)
+    pub fn mark_hit(&mut self, time: u64) -> u64 {
+        let ms_since_last = self.last_hit_time.map(|last_hit_time| time.saturating_sub(last_hit_time)).unwrap_or(0);
+        self.last_hit_time = Some(time);
+        ms_since_last
+    }
+}
+
+impl LogEntry {
+    /// Get when the entry was logged, as a UNIX timestamp in milliseconds
+    pub fn time(&self) -> u64 {
+        match self {
+            LogEntry::Strike(entry) => entry.time,
+            LogEntry::Retrigger(entry) => entry.time,
+            LogEntry::Verification(entry) => entry.time,
+            LogEntry::Modifier(entry) => entry.time,
+            LogEntry::Configuration(entry) => entry.time,
+            LogEntry::Error(entry) => entry.time,
+            LogEntry::Connection(entry) => entry.time,
+        }
+    }
+
+    /// Get the entry as a CSV row: time, type, name, note, velocity, value and detail. The value is the entry's
+    /// main number (a hit's actual duration in milliseconds if it was measured, a queued hit's delay, a sensor's latency or whether a
+    /// modifier is on), and the detail describes the rest
+    fn csv_row(&self) -> String {
+        let time = self.time().to_string();
+        let (kind, name, note, velocity, value, detail) = match self {
+            LogEntry::Strike(entry) => (
+                "Strike", entry.striker_name.clone(), Some(entry.note_num), Some(entry.velocity),
+                entry.actual_duration_ns.map(|ns| ns as f64 / 1e6),
+                format!(
+                    "planned {}ms, processed velocity {}, {}ms since last, {} on {}{}",
+                    entry.planned_duration_ns as f64 / 1e6, entry.processed_velocity, entry.ms_since_last,
+                    entry.striker_kind, entry.target_pin,
+                    entry.ble_timestamp.map(|timestamp| format!(", BLE timestamp {}", timestamp)).unwrap_or_default(),
+                ),
+            ),
+            LogEntry::Retrigger(entry) => (
+                "Retrigger", entry.striker_name.clone(), Some(entry.note_num), Some(entry.velocity),
+                entry.queued_for_ms.map(|ms| ms as f64),
+                format!(
+                    "{} ({:?}), {} dropped, {} queued", entry.outcome, entry.policy, entry.totals.dropped, entry.totals.queued,
+                ),
+            ),
+            LogEntry::Verification(entry) => (
+                "Verification", entry.striker_name.clone(), Some(entry.note_num), Some(entry.velocity), entry.latency_ms,
+                format!(
+                    "{}, {} verified, {} missed",
+                    if entry.landed { "landed" } else { "missed" }, entry.totals.verified, entry.totals.missed,
+                ),
+            ),
+            LogEntry::Modifier(entry) => (
+                "Modifier", entry.modifier_name.clone(), Some(entry.note_num), None,
+                Some(if entry.active { 1.0 } else { 0.0 }), (if entry.active { "on" } else { "off" }).to_string(),
+            ),
+            LogEntry::Configuration(entry) => ("Configuration", String::new(), None, None, None, entry.change.clone()),
+            LogEntry::Error(entry) => ("Error", String::new(), None, None, None, entry.message.clone()),
+            LogEntry::Connection(entry) => (
+                "Connection", entry.device.clone().unwrap_or_default(), None, None, None, entry.event.clone(),
+            ),
+        };
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        [
+            time,
+            kind.to_string(),
+            csv_field(&name),
+            optional(note.map(|note| note.to_string())),
+            optional(velocity.map(|velocity| velocity.to_string())),
+            optional(value.map(|value| value.to_string())),
+            csv_field(&detail),
+        ].join(",")
+    }
+}
+
+/// Get the current time as a UNIX timestamp in milliseconds
+pub fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Get every log file in a directory with the given extension
+pub fn log_files(directory: &Path, extension: &str) -> std::io::Result<Vec<PathBuf>> {
+    Ok(std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == extension)
+                && path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(LOG_FILE_PREFIX))
+        })
+        .collect())
+}
+
+/// Quote a CSV field if it needs it
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
     }
 }
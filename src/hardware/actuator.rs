@@ -0,0 +1,171 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio_timerfd::Delay;
+
+use crate::hardware::output::Output;
+use crate::hardware::pin_address::PinAddress;
+
+/// Settings for a striker driven by a hobby servo (e.g. a cymbal damper), for kinds with the Servo driver.
+/// Each hit swings the servo from its rest angle towards its strike angle and back, then stops the pulses
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServoSettings {
+    /// Angle in degrees the servo rests at between hits
+    pub rest_angle: f64,
+    /// Angle in degrees the servo swings to on a full velocity hit. Softer hits swing proportionally less far
+    pub strike_angle: f64,
+    /// How long the servo takes to swing from rest_angle to strike_angle, in milliseconds
+    pub travel_time: f64,
+    /// Angle in degrees at max_pulse_width
+    pub max_angle: f64,
+    /// Pulse width at 0 degrees, in microseconds
+    pub min_pulse_width: f64,
+    /// Pulse width at max_angle, in microseconds
+    pub max_pulse_width: f64,
+    /// Pulse frequency in Hz (50 for most hobby servos)
+    pub frequency: f64,
+    /// Use the pin's hardware PWM channel (GPIO 12, 13, 18 or 19) instead of software PWM, for steadier pulses
+    pub hardware: bool,
+}
+
+impl Default for ServoSettings {
+    fn default() -> Self {
+        Self {
+            rest_angle: 0.0,
+            strike_angle: 30.0,
+            travel_time: 100.0,
+            max_angle: 180.0,
+            min_pulse_width: 1000.0,
+            max_pulse_width: 2000.0,
+            frequency: 50.0,
+            hardware: false,
+        }
+    }
+}
+
+impl ServoSettings {
+    /// Get the time between the start of one pulse and the next
+    pub fn period(&self) -> Duration {
+        Duration::try_from_secs_f64(1.0 / self.frequency).unwrap_or(Duration::MAX)
+    }
+
+    /// Get the pulse width that holds the servo at the given angle
+    pub fn pulse_width(&self, angle: f64) -> Duration {
+        let fraction = (angle / self.max_angle).clamp(0.0, 1.0);
+        Duration::from_micros((self.min_pulse_width + (self.max_pulse_width - self.min_pulse_width) * fraction) as u64)
+    }
+
+    /// Get the angle a hit at the given velocity swings to
+    pub fn target_angle(&self, velocity: u8) -> f64 {
+        self.rest_angle + (self.strike_angle - self.rest_angle) * velocity as f64 / 127.0
+    }
+
+    /// Get how long a hit drives the servo for: out to the target angle and back to rest
+    pub fn stroke_duration(&self) -> Duration {
+        Duration::from_micros((self.travel_time * 2000.0) as u64)
+    }
+}
+
+/// Settings for a striker driven by a step/dir stepper driver (e.g. a shaker motor), for kinds with the Stepper
+/// driver. The striker's pin is wired to the driver's STEP input
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StepperSettings {
+    /// Pin wired to the driver's DIR input
+    pub dir_pin: PinAddress,
+    /// Steps moved on a full velocity hit. Softer hits move proportionally fewer (at least one)
+    #[serde(default = "default_steps")]
+    pub steps: u32,
+    /// Steps per second
+    #[serde(default = "default_step_rate")]
+    pub step_rate: f64,
+    /// Step back to where the hit started once it's done
+    #[serde(default = "default_return_to_rest")]
+    pub return_to_rest: bool,
+    /// Drive DIR low instead of high to move forwards
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+fn default_steps() -> u32 {
+    50
+}
+
+fn default_step_rate() -> f64 {
+    1000.0
+}
+
+fn default_return_to_rest() -> bool {
+    true
+}
+
+impl StepperSettings {
+    /// Get the number of steps a hit at the given velocity moves
+    pub fn steps_for(&self, velocity: u8) -> u32 {
+        ((self.steps as f64 * velocity as f64 / 127.0).round() as u32).max(1)
+    }
+
+    /// Get the time between the start of one step and the next
+    pub fn step_period(&self) -> Duration {
+        Duration::try_from_secs_f64(1.0 / self.step_rate).unwrap_or(Duration::MAX)
+    }
+
+    /// Get how long the longest hit (at full velocity) takes, including stepping back to rest
+    pub fn stroke_duration(&self) -> Duration {
+        self.stroke_duration_for(127)
+    }
+
+    /// Get how long a hit at the given velocity takes, including stepping back to rest
+    pub fn stroke_duration_for(&self, velocity: u8) -> Duration {
+        let moves = if self.return_to_rest { 2 } else { 1 };
+        self.step_period().saturating_mul(self.steps_for(velocity).saturating_mul(moves))
+    }
+}
+
+/// Swing a servo out to the angle for the given velocity and back to rest, then stop its pulses so it doesn't hold
+/// (and heat up) between hits. Resolves to how long the servo was driven for
+pub async fn servo_stroke(pin: Output, servo: ServoSettings, velocity: u8) -> Duration {
+    let start = Instant::now();
+    let travel = servo.stroke_duration() / 2;
+    pin.set_pulse(servo.period(), servo.pulse_width(servo.target_angle(velocity)));
+    wait_until(start + travel).await;
+    pin.set_pulse(servo.period(), servo.pulse_width(servo.rest_angle));
+    wait_until(start + travel * 2).await;
+    pin.set_low();
+    start.elapsed()
+}
+
+/// Step a stepper forwards by the number of steps for the given velocity, then back again if it returns to rest.
+/// Resolves to how long the stepper was moving for
+pub async fn stepper_stroke(step_pin: Output, dir_pin: Output, stepper: StepperSettings, velocity: u8) -> Duration {
+    let start = Instant::now();
+    let steps = stepper.steps_for(velocity);
+    let directions: &[bool] = if stepper.return_to_rest { &[true, false] } else { &[true] };
+    let period = stepper.step_period();
+    let mut next = start;
+    for forwards in directions {
+        if *forwards != stepper.reverse {
+            dir_pin.set_high();
+        } else {
+            dir_pin.set_low();
+        }
+        for _ in 0..steps {
+            // A step is taken on the rising edge, with the pin high for half the step
+            step_pin.set_high();
+            wait_until(next + period / 2).await;
+            step_pin.set_low();
+            next += period;
+            wait_until(next).await;
+        }
+    }
+    dir_pin.set_low();
+    start.elapsed()
+}
+
+/// Wait until the given time. If the timer fails the wait is skipped, cutting the move short rather than leaving the
+/// output on
+async fn wait_until(deadline: Instant) {
+    if let Ok(delay) = Delay::new(deadline) {
+        let _ = delay.await;
+    }
+}
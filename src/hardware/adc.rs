@@ -0,0 +1,59 @@
+use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+use serde::{Deserialize, Serialize};
+
+/// SPI clock for the MCP3008, the fastest it allows at 3.3V
+const MCP3008_CLOCK_SPEED: u32 = 1_350_000;
+/// Number of input channels on an MCP3008
+pub const MCP3008_CHANNELS: u8 = 8;
+/// Highest reading of the MCP3008's 10-bit converter
+pub const MCP3008_MAX_READING: u16 = 1023;
+
+/// Where an MCP3008 analog to digital converter is wired on the SPI bus
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Mcp3008Address {
+    /// SPI bus (0 is the Pi's main SPI bus)
+    #[serde(default)]
+    pub bus: u8,
+    /// Chip select line the converter is on (0-2)
+    #[serde(default)]
+    pub chip_select: u8,
+}
+
+/// An MCP3008 8-channel 10-bit analog to digital converter, read over SPI (e.g. for piezo sensors on the drums)
+pub struct Mcp3008 {
+    spi: Spi,
+}
+
+impl Mcp3008 {
+    /// Open the SPI bus the converter is on
+    pub fn new(address: Mcp3008Address) -> Result<Self, String> {
+        let bus = match address.bus {
+            0 => Bus::Spi0,
+            1 => Bus::Spi1,
+            2 => Bus::Spi2,
+            bus => return Err(format!("No SPI bus {}", bus)),
+        };
+        let slave_select = match address.chip_select {
+            0 => SlaveSelect::Ss0,
+            1 => SlaveSelect::Ss1,
+            2 => SlaveSelect::Ss2,
+            chip_select => return Err(format!("No chip select {} on SPI bus {}", chip_select, address.bus)),
+        };
+        let spi = Spi::new(bus, slave_select, MCP3008_CLOCK_SPEED, Mode::Mode0)
+            .map_err(|e| format!("Failed to open SPI bus {} chip select {}: {}", address.bus, address.chip_select, e))?;
+        Ok(Self { spi })
+    }
+
+    /// Read one channel (0-7), giving 0 to MCP3008_MAX_READING
+    pub fn read(&self, channel: u8) -> Result<u16, String> {
+        if channel >= MCP3008_CHANNELS {
+            return Err(format!("The MCP3008 has no channel {}", channel));
+        }
+        // Start bit, then single-ended mode and the channel in the top nibble of the next byte. The reading comes
+        // back in the low 2 bits of the second byte and all of the third
+        let write = [0x01, (0x08 | channel) << 4, 0x00];
+        let mut read = [0u8; 3];
+        self.spi.transfer(&mut read, &write).map_err(|e| format!("Failed to read MCP3008 channel {}: {}", channel, e))?;
+        Ok((((read[1] & 0x03) as u16) << 8) | read[2] as u16)
+    }
+}
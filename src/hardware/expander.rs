@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use rppal::i2c::I2c;
+
+use crate::hardware::pin_address::{McpPort, PinAddress};
+
+/// PCA9685 registers
+const PCA9685_MODE1: u8 = 0x00;
+const PCA9685_LED0_ON_L: u8 = 0x06;
+const PCA9685_ALL_LED_OFF_H: u8 = 0xFD;
+const PCA9685_PRE_SCALE: u8 = 0xFE;
+/// MODE1 bits
+const PCA9685_RESTART: u8 = 0x80;
+const PCA9685_AUTO_INCREMENT: u8 = 0x20;
+const PCA9685_SLEEP: u8 = 0x10;
+/// The bit in a channel's ON_H or OFF_H register that turns it fully on or off
+const PCA9685_FULL: u8 = 0x10;
+/// Frequency of the PCA9685's internal oscillator in Hz
+const PCA9685_OSCILLATOR: f64 = 25_000_000.0;
+/// Steps in a PCA9685 PWM cycle
+const PCA9685_STEPS: f64 = 4096.0;
+/// Lowest and highest PWM frequencies a PCA9685 can run at, in Hz
+pub const PCA9685_MIN_FREQUENCY: f64 = 24.0;
+pub const PCA9685_MAX_FREQUENCY: f64 = 1526.0;
+/// How long the PCA9685's oscillator takes to start after waking
+const PCA9685_WAKE_TIME: Duration = Duration::from_micros(500);
+
+/// MCP23017 registers (with the default IOCON.BANK = 0), port A's. Port B's are the next register along
+const MCP23017_IODIRA: u8 = 0x00;
+const MCP23017_OLATA: u8 = 0x14;
+
+lazy_static! {
+    /// The I2C bus every expander board is on, opened when the first expander pin is claimed
+    static ref BUS: Mutex<Option<ExpanderBus>> = Mutex::new(None);
+}
+
+/// The I2C bus and what's known of the state of each board on it. Every board shares the bus, so every write goes
+/// through here
+struct ExpanderBus {
+    i2c: I2c,
+    /// The PWM frequency each PCA9685 that's been set up is running at
+    pca9685_frequencies: HashMap<u16, f64>,
+    /// The direction and output latch registers (port A then B) of each MCP23017 that's been set up
+    mcp23017_registers: HashMap<u16, Mcp23017Registers>,
+}
+
+#[derive(Clone, Copy)]
+struct Mcp23017Registers {
+    /// A set bit is an input
+    iodir: [u8; 2],
+    /// A set bit is driven high
+    olat: [u8; 2],
+}
+
+/// Lock the bus, ignoring poisoning. Like outputs, turning pins off matters more than a panic elsewhere
+fn lock_bus() -> MutexGuard<'static, Option<ExpanderBus>> {
+    BUS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Run some writes on the bus, opening it if it isn't already
+fn with_bus<T>(f: impl FnOnce(&mut ExpanderBus) -> Result<T, String>) -> Result<T, String> {
+    let mut bus = lock_bus();
+    if bus.is_none() {
+        let i2c = I2c::new().map_err(|e| format!("Unable to open the I2C bus: {}", e))?;
+        *bus = Some(ExpanderBus { i2c, pca9685_frequencies: HashMap::new(), mcp23017_registers: HashMap::new() });
+    }
+    f(bus.as_mut().unwrap())
+}
+
+impl ExpanderBus {
+    /// Write to consecutive registers of a board, starting at the given one
+    fn write(&mut self, address: u16, register: u8, values: &[u8]) -> Result<(), String> {
+        let mut buffer = Vec::with_capacity(values.len() + 1);
+        buffer.push(register);
+        buffer.extend_from_slice(values);
+        self.i2c.set_slave_address(address)
+            .and_then(|_| self.i2c.write(&buffer))
+            .map(|_| ())
+            .map_err(|e| format!("I2C write to {:#04x} failed: {}", address, e))
+    }
+
+    /// Set up a PCA9685 the first time one of its channels is claimed, with every channel off
+    fn init_pca9685(&mut self, address: u16) -> Result<(), String> {
+        if self.pca9685_frequencies.contains_key(&address) {
+            return Ok(());
+        }
+        self.write(address, PCA9685_ALL_LED_OFF_H, &[PCA9685_FULL])?;
+        // Nothing has asked for a frequency yet, so start at the highest
+        self.set_pca9685_frequency(address, PCA9685_MAX_FREQUENCY)
+    }
+
+    /// Change the PWM frequency of a PCA9685, which applies to all of its channels. The oscillator has to be stopped
+    /// to change it, so this is skipped if it's already running at that frequency
+    fn set_pca9685_frequency(&mut self, address: u16, frequency: f64) -> Result<(), String> {
+        if self.pca9685_frequencies.get(&address) == Some(&frequency) {
+            return Ok(());
+        }
+        let prescale = ((PCA9685_OSCILLATOR / (PCA9685_STEPS * frequency)).round() - 1.0).clamp(3.0, 255.0) as u8;
+        self.write(address, PCA9685_MODE1, &[PCA9685_AUTO_INCREMENT | PCA9685_SLEEP])?;
+        self.write(address, PCA9685_PRE_SCALE, &[prescale])?;
+        self.write(address, PCA9685_MODE1, &[PCA9685_AUTO_INCREMENT])?;
+        std::thread::sleep(PCA9685_WAKE_TIME);
+        self.write(address, PCA9685_MODE1, &[PCA9685_RESTART | PCA9685_AUTO_INCREMENT])?;
+        self.pca9685_frequencies.insert(address, frequency);
+        Ok(())
+    }
+
+    /// Set the ON and OFF registers of a PCA9685 channel
+    fn write_pca9685_channel(&mut self, address: u16, channel: u8, on: u16, off: u16) -> Result<(), String> {
+        let [on_l, on_h] = on.to_le_bytes();
+        let [off_l, off_h] = off.to_le_bytes();
+        self.write(address, PCA9685_LED0_ON_L + 4 * channel, &[on_l, on_h, off_l, off_h])
+    }
+
+    /// Set up an MCP23017 the first time one of its pins is claimed, with every pin an input (as at power on)
+    fn init_mcp23017(&mut self, address: u16) -> Result<Mcp23017Registers, String> {
+        if let Some(registers) = self.mcp23017_registers.get(&address) {
+            return Ok(*registers);
+        }
+        let registers = Mcp23017Registers { iodir: [0xFF; 2], olat: [0x00; 2] };
+        self.write(address, MCP23017_OLATA, &registers.olat)?;
+        self.write(address, MCP23017_IODIRA, &registers.iodir)?;
+        self.mcp23017_registers.insert(address, registers);
+        Ok(registers)
+    }
+
+    /// Update an MCP23017's registers with a function, writing back whichever changed
+    fn update_mcp23017(&mut self, address: u16, f: impl FnOnce(&mut Mcp23017Registers)) -> Result<(), String> {
+        let old = self.init_mcp23017(address)?;
+        let mut new = old;
+        f(&mut new);
+        if new.olat != old.olat {
+            self.write(address, MCP23017_OLATA, &new.olat)?;
+        }
+        if new.iodir != old.iodir {
+            self.write(address, MCP23017_IODIRA, &new.iodir)?;
+        }
+        self.mcp23017_registers.insert(address, new);
+        Ok(())
+    }
+}
+
+/// A pin of an I2C board, claimed as an output. Dropping it turns the pin off
+pub struct ExpanderPin {
+    address: PinAddress,
+}
+
+impl ExpanderPin {
+    /// Claim a pin of an I2C board as an output, setting up the board if it hasn't been yet
+    pub fn claim(address: &PinAddress) -> Result<Self, String> {
+        match address {
+            PinAddress::Gpio(_) => return Err(format!("GPIO pin {} is not on an I2C board", address)),
+            PinAddress::Pca9685 { address: board, .. } => with_bus(|bus| bus.init_pca9685(*board))?,
+            PinAddress::Mcp23017 { address: board, port, pin } => with_bus(|bus| bus.update_mcp23017(*board, |registers| {
+                registers.olat[port_index(*port)] &= !(1 << pin);
+                registers.iodir[port_index(*port)] &= !(1 << pin);
+            }))?,
+        }
+        let pin = Self { address: *address };
+        pin.set_low()?;
+        Ok(pin)
+    }
+
+    pub fn set_high(&self) -> Result<(), String> {
+        self.set_level(true)
+    }
+
+    pub fn set_low(&self) -> Result<(), String> {
+        self.set_level(false)
+    }
+
+    fn set_level(&self, high: bool) -> Result<(), String> {
+        match &self.address {
+            PinAddress::Gpio(_) => unreachable!("claim only accepts I2C boards"),
+            PinAddress::Pca9685 { address, channel } => with_bus(|bus| {
+                let (on, off) = if high { ((PCA9685_FULL as u16) << 8, 0) } else { (0, (PCA9685_FULL as u16) << 8) };
+                bus.write_pca9685_channel(*address, *channel, on, off)
+            }),
+            PinAddress::Mcp23017 { address, port, pin } => with_bus(|bus| bus.update_mcp23017(*address, |registers| {
+                if high {
+                    registers.olat[port_index(*port)] |= 1 << pin;
+                } else {
+                    registers.olat[port_index(*port)] &= !(1 << pin);
+                }
+            })),
+        }
+    }
+
+    /// Drive the pin at partial duty. Only PCA9685 channels can do this, and every channel of a board shares its
+    /// frequency
+    pub fn set_duty(&self, frequency: f64, duty: f64) -> Result<(), String> {
+        match &self.address {
+            PinAddress::Pca9685 { address, channel } => with_bus(|bus| {
+                bus.set_pca9685_frequency(*address, frequency)?;
+                let off = (duty.clamp(0.0, 1.0) * PCA9685_STEPS).round().clamp(1.0, PCA9685_STEPS - 1.0) as u16;
+                bus.write_pca9685_channel(*address, *channel, 0, off)
+            }),
+            _ => Err(format!("{} can only be switched on and off", self.address)),
+        }
+    }
+
+    /// Set the PWM frequency of the pin's board ahead of the first hit, since changing it briefly stops every channel
+    pub fn set_frequency(&self, frequency: f64) -> Result<(), String> {
+        match &self.address {
+            PinAddress::Pca9685 { address, .. } => with_bus(|bus| bus.set_pca9685_frequency(*address, frequency)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Drive the pin with pulses of a set width (e.g. for a servo)
+    pub fn set_pulse(&self, period: Duration, pulse_width: Duration) -> Result<(), String> {
+        self.set_duty(1.0 / period.as_secs_f64(), pulse_width.as_secs_f64() / period.as_secs_f64())
+    }
+}
+
+impl Drop for ExpanderPin {
+    fn drop(&mut self) {
+        let _ = self.set_low();
+    }
+}
+
+fn port_index(port: McpPort) -> usize {
+    match port {
+        McpPort::A => 0,
+        McpPort::B => 1,
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use rppal::gpio::{Gpio, InputPin, Trigger};
+use serde::{Deserialize, Serialize};
+
+use crate::hardware::adc::{Mcp3008, Mcp3008Address, MCP3008_MAX_READING};
+use crate::hardware::output;
+use crate::hardware::striker::StrikerData;
+
+/// Lowest and highest time to wait for an impact after a hit starts, in milliseconds
+pub const HIT_SENSOR_WINDOW_RANGE: (f64, f64) = (1.0, 1000.0);
+/// Lowest and highest rise of an ADC reading above its resting level that counts as an impact, as a share of full scale
+pub const HIT_SENSOR_THRESHOLD_RANGE: (f64, f64) = (0.01, 1.0);
+/// How many impacts are remembered per sensor, far more than can land in one window
+const IMPACT_HISTORY: usize = 32;
+/// How often sensors on an MCP3008 are read
+const ADC_POLL_INTERVAL: Duration = Duration::from_micros(250);
+/// How quickly an ADC sensor's resting level follows its readings between impacts
+const BASELINE_SMOOTHING: f32 = 0.001;
+/// How long to wait before reading an MCP3008 again after a read fails
+const ADC_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A sensor that confirms a striker's hits land, e.g. a piezo on the drum or a switch under the beater
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct HitSensorConfig {
+    #[serde(flatten)]
+    pub input: HitSensorInput,
+    /// How long after a hit starts the impact has to be sensed by, in milliseconds, before the hit counts as missed
+    #[serde(default = "default_window")]
+    pub window: f64,
+}
+
+impl HitSensorConfig {
+    /// Get the GPIO pin the sensor is on, if it's a GPIO sensor
+    pub fn gpio_pin(&self) -> Option<u8> {
+        match self.input {
+            HitSensorInput::Gpio { pin, .. } => Some(pin),
+            HitSensorInput::Mcp3008 { .. } => None,
+        }
+    }
+}
+
+fn default_window() -> f64 {
+    50.0
+}
+
+fn default_threshold() -> f64 {
+    0.2
+}
+
+/// How a hit sensor is read
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum HitSensorInput {
+    /// A GPIO input that changes level on impact (e.g. a piezo through a comparator). Inputs are pulled down and
+    /// sensed rising, or pulled up and sensed falling if `active_low`
+    Gpio {
+        pin: u8,
+        #[serde(default)]
+        active_low: bool,
+    },
+    /// A piezo on one channel of an MCP3008, sensed when its reading rises `threshold` (a share of full scale)
+    /// above where it rests
+    Mcp3008 {
+        #[serde(default)]
+        bus: u8,
+        #[serde(default)]
+        chip_select: u8,
+        channel: u8,
+        #[serde(default = "default_threshold")]
+        threshold: f64,
+    },
+}
+
+/// How a striker's hits have checked out against its sensor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct HitStats {
+    /// Hits the sensor picked up within the window
+    pub verified: u64,
+    /// Hits the sensor picked up nothing from
+    pub missed: u64,
+    /// Milliseconds from the start of the most recent verified hit to its impact
+    pub last_latency_ms: Option<f64>,
+    pub min_latency_ms: Option<f64>,
+    pub max_latency_ms: Option<f64>,
+    /// Sum of the latencies of every verified hit, for the mean
+    total_latency_ms: f64,
+}
+
+impl HitStats {
+    /// Count a hit, with the latency to its impact if it landed
+    fn record(&mut self, latency: Option<Duration>) {
+        let Some(latency) = latency else {
+            self.missed += 1;
+            return;
+        };
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        self.verified += 1;
+        self.total_latency_ms += latency_ms;
+        self.last_latency_ms = Some(latency_ms);
+        self.min_latency_ms = Some(self.min_latency_ms.map_or(latency_ms, |min| min.min(latency_ms)));
+        self.max_latency_ms = Some(self.max_latency_ms.map_or(latency_ms, |max| max.max(latency_ms)));
+    }
+
+    /// Get the mean milliseconds from the start of a hit to its impact, over every verified hit
+    pub fn mean_latency_ms(&self) -> Option<f64> {
+        (self.verified > 0).then(|| self.total_latency_ms / self.verified as f64)
+    }
+}
+
+/// When a sensor last picked up impacts, newest last
+#[derive(Debug, Clone, Default)]
+struct Impacts(Arc<Mutex<VecDeque<Instant>>>);
+
+impl Impacts {
+    fn record(&self, at: Instant) {
+        let mut impacts = self.0.lock().unwrap();
+        if impacts.len() == IMPACT_HISTORY {
+            impacts.pop_front();
+        }
+        impacts.push_back(at);
+    }
+
+    /// Get the first impact between two times
+    fn first_between(&self, from: Instant, to: Instant) -> Option<Instant> {
+        self.0.lock().unwrap().iter().copied().find(|at| (from..=to).contains(at))
+    }
+}
+
+/// Checks a striker's hits against its sensor
+#[derive(Debug, Clone)]
+pub struct HitCheck {
+    impacts: Impacts,
+    window: Duration,
+    stats: Arc<Mutex<HitStats>>,
+}
+
+impl HitCheck {
+    /// Wait out the window after a hit that started at the given time, then count the hit as verified or missed.
+    /// Returns the latency from the start of the hit to its impact (None if it was missed) and the striker's stats
+    /// including this hit
+    pub async fn verify(&self, start: Instant) -> (Option<Duration>, HitStats) {
+        let end = start + self.window;
+        tokio::time::sleep_until(end.into()).await;
+        let latency = self.impacts.first_between(start, end).map(|at| at - start);
+        let mut stats = self.stats.lock().unwrap();
+        stats.record(latency);
+        (latency, *stats)
+    }
+
+    /// Get how long after a hit starts its impact has to be sensed by
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+}
+
+/// One sensor on an MCP3008, and how far it is through an impact
+struct AdcSensor {
+    channel: u8,
+    threshold: f32,
+    impacts: Impacts,
+    /// Where the reading rests between impacts (None until the first reading)
+    baseline: Option<f32>,
+    /// Whether the reading has settled since the last impact, so the next can be sensed
+    armed: bool,
+}
+
+/// Reads the hit sensors of a kit's strikers in the background: GPIO sensors by interrupt, and piezos on MCP3008s
+/// on a thread per converter
+pub struct HitSensors {
+    /// Striker note numbers to the check on their hits
+    checks: HashMap<u8, HitCheck>,
+    /// Held so their interrupts stay set
+    gpio_inputs: Vec<InputPin>,
+    /// Set to stop the MCP3008 threads
+    stop: Arc<AtomicBool>,
+    adc_threads: Vec<JoinHandle<()>>,
+}
+
+impl HitSensors {
+    /// No sensors at all
+    pub fn none() -> Self {
+        Self { checks: HashMap::new(), gpio_inputs: vec![], stop: Arc::new(AtomicBool::new(false)), adc_threads: vec![] }
+    }
+
+    /// Start reading the sensors of the given strikers. In a dry run there's nothing to sense, so no hits are checked
+    pub fn start(strikers: &[StrikerData]) -> Result<Self, String> {
+        let mut sensors = Self::none();
+        let sensed: Vec<(&StrikerData, HitSensorConfig)> = strikers.iter()
+            .filter_map(|striker| striker.sensor.map(|sensor| (striker, sensor)))
+            .collect();
+        if sensed.is_empty() {
+            return Ok(sensors);
+        }
+        if output::is_dry_run() {
+            println!("Dry run: hit sensors aren't read, so hits won't be verified");
+            return Ok(sensors);
+        }
+        let mut gpio = None;
+        let mut adcs: HashMap<Mcp3008Address, Vec<AdcSensor>> = HashMap::new();
+        for (striker, sensor) in sensed {
+            let impacts = Impacts::default();
+            match sensor.input {
+                HitSensorInput::Gpio { pin, active_low } => {
+                    let gpio = match &gpio {
+                        Some(gpio) => gpio,
+                        None => gpio.insert(Gpio::new().map_err(|e| format!("Unable to access GPIO: {}", e))?),
+                    };
+                    let pin_error = |e| format!("Unable to claim GPIO pin {} for the sensor of {}: {}", pin, striker.name, e);
+                    let pin = gpio.get(pin).map_err(pin_error)?;
+                    let (mut input, trigger) = if active_low {
+                        (pin.into_input_pullup(), Trigger::FallingEdge)
+                    } else {
+                        (pin.into_input_pulldown(), Trigger::RisingEdge)
+                    };
+                    let recorder = impacts.clone();
+                    input.set_async_interrupt(trigger, move |_| recorder.record(Instant::now()))
+                        .map_err(|e| format!("Unable to watch the sensor of {}: {}", striker.name, e))?;
+                    sensors.gpio_inputs.push(input);
+                },
+                HitSensorInput::Mcp3008 { bus, chip_select, channel, threshold } => {
+                    adcs.entry(Mcp3008Address { bus, chip_select }).or_default().push(AdcSensor {
+                        channel,
+                        threshold: threshold as f32,
+                        impacts: impacts.clone(),
+                        baseline: None,
+                        armed: true,
+                    });
+                },
+            }
+            let window = Duration::from_secs_f64(sensor.window / 1000.0);
+            sensors.checks.insert(striker.note, HitCheck { impacts, window, stats: Arc::default() });
+        }
+        for (address, adc_sensors) in adcs {
+            let adc = Mcp3008::new(address)?;
+            let stop = sensors.stop.clone();
+            sensors.adc_threads.push(std::thread::spawn(move || poll_adc(adc, adc_sensors, &stop)));
+        }
+        Ok(sensors)
+    }
+
+    /// Get the check on the hits of the striker with the given note number, if it has a sensor
+    pub fn check(&self, note: u8) -> Option<&HitCheck> {
+        self.checks.get(&note)
+    }
+
+    /// Get how the hits of the striker with the given note number have checked out, if it has a sensor
+    pub fn stats(&self, note: u8) -> Option<HitStats> {
+        self.checks.get(&note).map(|check| *check.stats.lock().unwrap())
+    }
+}
+
+impl Drop for HitSensors {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.adc_threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Read every sensor on an MCP3008 in turn until told to stop, recording an impact whenever a reading jumps far
+/// enough from where it rests. A sensor has to settle back below half its threshold before the next impact counts,
+/// so one ringing hit isn't sensed twice
+fn poll_adc(adc: Mcp3008, mut sensors: Vec<AdcSensor>, stop: &AtomicBool) {
+    while !stop.load(Ordering::Relaxed) {
+        for sensor in sensors.iter_mut() {
+            let reading = match adc.read(sensor.channel) {
+                Ok(reading) => reading as f32 / MCP3008_MAX_READING as f32,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::thread::sleep(ADC_RETRY_INTERVAL);
+                    continue;
+                },
+            };
+            let baseline = sensor.baseline.get_or_insert(reading);
+            let rise = (reading - *baseline).abs();
+            if sensor.armed && rise >= sensor.threshold {
+                sensor.impacts.record(Instant::now());
+                sensor.armed = false;
+            } else if !sensor.armed && rise < sensor.threshold / 2.0 {
+                sensor.armed = true;
+            }
+            if sensor.armed {
+                *baseline += (reading - *baseline) * BASELINE_SMOOTHING;
+            }
+        }
+        std::thread::sleep(ADC_POLL_INTERVAL);
+    }
+}
@@ -1,3 +1,11 @@
 pub mod striker;
+pub mod actuator;
 pub mod modifier;
 pub mod striker_hardware_util;
+pub mod output;
+pub mod pin_address;
+pub mod expander;
+pub mod watchdog;
+pub mod adc;
+pub mod hit_sensor;
+pub mod trigger;
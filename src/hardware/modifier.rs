@@ -1,27 +1,48 @@
 use std::time::{Duration, Instant};
-use rppal::gpio::OutputPin;
+use serde::{Deserialize, Serialize};
 use tokio_timerfd::Delay;
 
-pub enum ModifierHardwareKind {
-    SolenoidBig
-}
+use crate::hardware::output::Output;
+use crate::hardware::pin_address::PinAddress;
+use crate::hardware::striker_hardware_util::{HardwareKind, StrikerHardwareUtil};
+use crate::system::system_constants::StrikerConstants;
 
 pub struct Modifier {
     pub name: String,
     pub note: u8,
-    pub pin: OutputPin,
-    pub hardware_kind: ModifierHardwareKind,
+    pub pin: Output,
+    pub hardware_kind: HardwareKind,
+    /// The constants of its kind, looked up when the modifier is created
+    kind_constants: StrikerConstants,
+}
+
+/// The configuration of a Modifier as stored in the configuration file
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ModifierData {
+    pub name: String,
+    pub pin: PinAddress,
+    pub kind: HardwareKind,
+    pub note: u8,
+    /// Name of the striker whose behavior this modifier changes
+    pub target: String,
 }
 
 impl Modifier {
-    pub fn new(name: &str, note: u8, pin: u8, hardware_kind: ModifierHardwareKind) -> Self {
-        let pin = rppal::gpio::Gpio::new().unwrap().get(pin).unwrap().into_output();
-        Self {
+    pub fn new(name: &str, note: u8, pin: PinAddress, hardware_kind: HardwareKind) -> Result<Self, String> {
+        Modifier::with_output(name, note, Output::new(pin)?, hardware_kind)
+    }
+
+    /// Create a Modifier driving an output that has already been claimed (e.g. one kept from the previous configuration)
+    pub fn with_output(name: &str, note: u8, pin: Output, hardware_kind: HardwareKind) -> Result<Self, String> {
+        let modifier = Self {
             name: name.to_string(),
             note,
             pin,
+            kind_constants: StrikerHardwareUtil::get_constants(&hardware_kind)?,
             hardware_kind,
-        }
+        };
+        modifier.pin.set_max_high_duration(Duration::from_millis(modifier.max_activation_duration() as u64));
+        Ok(modifier)
     }
 
     pub fn activate(&mut self) {
@@ -46,9 +67,17 @@ impl Modifier {
 
     /// Get the maximum duration that the modifier can be activated for in milliseconds
     pub fn max_activation_duration(&self) -> f64 {
-        match self.hardware_kind {
-            ModifierHardwareKind::SolenoidBig => 5000.0,
+        self.kind_constants.max_hold_duration
+    }
+
+    /// Export the modifier's configuration, given the name of the striker it targets
+    pub fn export_raw(&self, target: &str) -> ModifierData {
+        ModifierData {
+            name: self.name.clone(),
+            pin: self.pin.pin(),
+            kind: self.hardware_kind.clone(),
+            note: self.note,
+            target: target.to_string(),
         }
     }
 }
-
@@ -0,0 +1,324 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use rppal::gpio::{Gpio, OutputPin};
+use rppal::pwm::{Channel, Polarity, Pwm};
+
+use crate::hardware::expander::ExpanderPin;
+use crate::hardware::pin_address::PinAddress;
+
+/// When set, outputs are simulated instead of driving real GPIO pins (set by `--dry-run`)
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Every output that has been created, so they can all be forced off from anywhere (e.g. a shutdown timeout)
+    static ref OUTPUTS: Mutex<Vec<Weak<Mutex<OutputState>>>> = Mutex::new(vec![]);
+}
+
+/// Enable or disable dry-run mode. Must be called before any outputs are created
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+/// Check whether outputs are being simulated
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Force every output that still exists low, returning how many were high.
+/// Safe to call from any thread, and works even if a thread panicked while holding an output
+pub fn force_all_low() -> usize {
+    let mut outputs = lock(&OUTPUTS);
+    outputs.retain(|output| output.strong_count() > 0);
+    outputs.iter()
+        .filter_map(|output| output.upgrade())
+        .filter(|output| {
+            let mut state = lock(output);
+            let was_high = state.is_set_high();
+            state.set_low();
+            was_high
+        })
+        .count()
+}
+
+/// Force low every output that has been high for longer than its limit plus the given grace period, returning the
+/// pins that were forced off. Used by the watchdog to catch pins left on by a stalled or panicked task
+pub fn enforce_max_high_durations(grace: Duration) -> Vec<PinAddress> {
+    let now = Instant::now();
+    let mut forced = vec![];
+    for output in lock(&OUTPUTS).iter().filter_map(|output| output.upgrade()) {
+        let mut state = lock(&output);
+        if let (Some(high_since), Some(max_high_duration)) = (state.high_since, state.max_high_duration) {
+            if now.duration_since(high_since) > max_high_duration + grace {
+                state.set_low();
+                forced.push(state.pin);
+            }
+        }
+    }
+    forced
+}
+
+/// Lock a mutex, ignoring poisoning. An output's state is always valid, and turning it off matters more than the panic
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Get the hardware PWM channel a GPIO pin can be driven by, if any
+pub fn hardware_pwm_channel(pin: u8) -> Option<Channel> {
+    match pin {
+        12 | 18 => Some(Channel::Pwm0),
+        13 | 19 => Some(Channel::Pwm1),
+        _ => None,
+    }
+}
+
+/// How an Output drives its pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    /// A GPIO pin, switched on and off (or software PWM for partial duty), or a pin of an I2C board
+    Gpio,
+    /// One of the pi's hardware PWM channels, which needs the pin set up for PWM (e.g. with the pwm-2chan overlay)
+    HardwarePwm,
+}
+
+/// The hardware driving an Output
+enum OutputDriver {
+    /// A raspberry pi GPIO pin
+    Gpio(OutputPin),
+    /// A raspberry pi hardware PWM channel
+    HardwarePwm(Pwm),
+    /// A pin of a GPIO expander or PWM driver board on the I2C bus
+    Expander(ExpanderPin),
+    /// A stand-in for a GPIO pin that only tracks and prints its state, used for dry runs off the pi
+    Simulated,
+}
+
+/// The shared state of an Output
+struct OutputState {
+    pin: PinAddress,
+    driver: OutputDriver,
+    /// When the output last went high (fully or at partial duty), or None if it's low
+    high_since: Option<Instant>,
+    /// The longest the output may legitimately stay high, or None if it may stay high indefinitely
+    max_high_duration: Option<Duration>,
+}
+
+impl OutputState {
+    /// Claim the hardware for a pin in the given mode
+    fn claim(pin: &PinAddress, mode: OutputMode) -> Result<OutputDriver, String> {
+        if is_dry_run() {
+            return Ok(OutputDriver::Simulated);
+        }
+        let Some(pin) = pin.gpio() else {
+            if mode == OutputMode::HardwarePwm {
+                return Err(format!("{} has no hardware PWM channel", pin));
+            }
+            return Ok(OutputDriver::Expander(ExpanderPin::claim(pin)?));
+        };
+        match mode {
+            OutputMode::Gpio => {
+                let gpio = Gpio::new().map_err(|e| format!("Unable to access GPIO: {}", e))?;
+                let output_pin = gpio.get(pin).map_err(|e| format!("Unable to claim GPIO pin {}: {}", pin, e))?.into_output();
+                Ok(OutputDriver::Gpio(output_pin))
+            },
+            OutputMode::HardwarePwm => {
+                let channel = hardware_pwm_channel(pin).ok_or(format!("GPIO pin {} has no hardware PWM channel", pin))?;
+                let pwm = Pwm::with_frequency(channel, 1000.0, 0.0, Polarity::Normal, false)
+                    .map_err(|e| format!("Unable to claim hardware PWM on GPIO pin {}: {}", pin, e))?;
+                Ok(OutputDriver::HardwarePwm(pwm))
+            },
+        }
+    }
+
+    fn set_high(&mut self) {
+        match &mut self.driver {
+            OutputDriver::Gpio(output_pin) => {
+                let _ = output_pin.clear_pwm();
+                output_pin.set_high()
+            },
+            OutputDriver::HardwarePwm(pwm) => {
+                if let Err(e) = pwm.set_duty_cycle(1.0).and_then(|_| pwm.enable()) {
+                    eprintln!("Unable to drive PWM on pin {}: {}", self.pin, e);
+                }
+            },
+            OutputDriver::Expander(expander_pin) => {
+                if let Err(e) = expander_pin.set_high() {
+                    eprintln!("Unable to drive pin {}: {}", self.pin, e);
+                }
+            },
+            OutputDriver::Simulated => println!("[dry-run] pin {} high", self.pin),
+        }
+        self.high_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Drive the output at partial power
+    fn set_duty(&mut self, frequency: f64, duty: f64) {
+        let result = match &mut self.driver {
+            OutputDriver::Gpio(output_pin) => output_pin.set_pwm_frequency(frequency, duty).map_err(|e| e.to_string()),
+            OutputDriver::HardwarePwm(pwm) => pwm.set_frequency(frequency, duty).and_then(|_| pwm.enable()).map_err(|e| e.to_string()),
+            OutputDriver::Expander(expander_pin) => expander_pin.set_duty(frequency, duty),
+            OutputDriver::Simulated => {
+                println!("[dry-run] pin {} at {:.0}% duty, {}Hz", self.pin, duty * 100.0, frequency);
+                Ok(())
+            },
+        };
+        if let Err(e) = result {
+            // Better a full-power hit than a missing one
+            eprintln!("Unable to drive PWM on pin {}, driving it fully on instead: {}", self.pin, e);
+            self.set_high();
+        }
+        self.high_since.get_or_insert_with(Instant::now);
+    }
+
+    /// Drive the output with pulses of a set width (e.g. to hold a servo at an angle)
+    fn set_pulse(&mut self, period: Duration, pulse_width: Duration) {
+        let result = match &mut self.driver {
+            OutputDriver::Gpio(output_pin) => output_pin.set_pwm(period, pulse_width).map_err(|e| e.to_string()),
+            OutputDriver::HardwarePwm(pwm) => pwm.set_period(period)
+                .and_then(|_| pwm.set_pulse_width(pulse_width))
+                .and_then(|_| pwm.enable())
+                .map_err(|e| e.to_string()),
+            OutputDriver::Expander(expander_pin) => expander_pin.set_pulse(period, pulse_width),
+            OutputDriver::Simulated => {
+                println!("[dry-run] pin {} pulsing {}us every {}ms", self.pin, pulse_width.as_micros(), period.as_millis());
+                Ok(())
+            },
+        };
+        if let Err(e) = result {
+            // Unlike a duty, a pulse width has no safe fallback, so leave the output off
+            eprintln!("Unable to pulse pin {}: {}", self.pin, e);
+            self.set_low();
+            return;
+        }
+        self.high_since.get_or_insert_with(Instant::now);
+    }
+
+    fn set_low(&mut self) {
+        match &mut self.driver {
+            OutputDriver::Gpio(output_pin) => {
+                let _ = output_pin.clear_pwm();
+                output_pin.set_low()
+            },
+            OutputDriver::HardwarePwm(pwm) => {
+                // Zero the duty as well as disabling, in case disabling fails
+                let _ = pwm.set_duty_cycle(0.0);
+                let _ = pwm.disable();
+            },
+            OutputDriver::Expander(expander_pin) => {
+                if let Err(e) = expander_pin.set_low() {
+                    eprintln!("Unable to turn off pin {}: {}", self.pin, e);
+                }
+            },
+            OutputDriver::Simulated => {
+                if self.high_since.is_some() {
+                    println!("[dry-run] pin {} low", self.pin);
+                }
+            }
+        }
+        self.high_since = None;
+    }
+
+    fn is_set_high(&self) -> bool {
+        self.high_since.is_some()
+    }
+
+    fn mode(&self) -> OutputMode {
+        match self.driver {
+            OutputDriver::HardwarePwm(_) => OutputMode::HardwarePwm,
+            OutputDriver::Gpio(_) | OutputDriver::Expander(_) | OutputDriver::Simulated => OutputMode::Gpio,
+        }
+    }
+}
+
+/// A digital output that drives striker or modifier hardware (or one input of it, e.g. a stepper driver's DIR).
+/// Cloning gives another handle to the same pin, so a pulse can be ended from a spawned task
+#[derive(Clone)]
+pub struct Output {
+    pin: PinAddress,
+    state: Arc<Mutex<OutputState>>,
+}
+
+impl Output {
+    /// Claim the given GPIO pin or I2C board pin as an output (or a simulated one in dry-run mode)
+    pub fn new(pin: PinAddress) -> Result<Self, String> {
+        let driver = OutputState::claim(&pin, OutputMode::Gpio)?;
+        let state = Arc::new(Mutex::new(OutputState { pin, driver, high_since: None, max_high_duration: None }));
+        lock(&OUTPUTS).push(Arc::downgrade(&state));
+        Ok(Self { pin, state })
+    }
+
+    /// Get where the output is wired
+    pub fn pin(&self) -> PinAddress {
+        self.pin
+    }
+
+    pub fn set_high(&self) {
+        lock(&self.state).set_high();
+    }
+
+    pub fn set_low(&self) {
+        lock(&self.state).set_low();
+    }
+
+    /// Drive the output at partial power with PWM (software PWM on a GPIO pin, or the pin's hardware PWM channel)
+    pub fn set_duty(&self, frequency: f64, duty: f64) {
+        lock(&self.state).set_duty(frequency, duty);
+    }
+
+    /// Drive the output with pulses of a set width and period (software PWM on a GPIO pin, or the pin's hardware PWM
+    /// channel)
+    pub fn set_pulse(&self, period: Duration, pulse_width: Duration) {
+        lock(&self.state).set_pulse(period, pulse_width);
+    }
+
+    pub fn is_set_high(&self) -> bool {
+        lock(&self.state).is_set_high()
+    }
+
+    /// Get how the output drives its pin
+    pub fn mode(&self) -> OutputMode {
+        lock(&self.state).mode()
+    }
+
+    /// Switch how the output drives its pin (e.g. when a reloaded configuration uses it for a hardware PWM striker).
+    /// The output is turned off first
+    pub fn set_mode(&self, mode: OutputMode) -> Result<(), String> {
+        let mut state = lock(&self.state);
+        if state.mode() == mode {
+            return Ok(());
+        }
+        let previous_mode = state.mode();
+        state.set_low();
+        // Release the pin before claiming it again in the new mode
+        state.driver = OutputDriver::Simulated;
+        match OutputState::claim(&self.pin, mode) {
+            Ok(driver) => {
+                state.driver = driver;
+                Ok(())
+            },
+            Err(e) => {
+                if let Ok(driver) = OutputState::claim(&self.pin, previous_mode) {
+                    state.driver = driver;
+                }
+                Err(e)
+            },
+        }
+    }
+
+    /// Set the PWM frequency ahead of the first hit, for outputs on boards where every channel shares one frequency
+    /// and changing it briefly interrupts them all (a PCA9685). Does nothing for other outputs
+    pub fn set_frequency(&self, frequency: f64) {
+        if let OutputDriver::Expander(expander_pin) = &lock(&self.state).driver {
+            if let Err(e) = expander_pin.set_frequency(frequency) {
+                eprintln!("Unable to set the PWM frequency of {}: {}", self.pin, e);
+            }
+        }
+    }
+
+    /// Set the longest the output may stay high before the watchdog forces it low
+    pub fn set_max_high_duration(&self, max_high_duration: Duration) {
+        lock(&self.state).max_high_duration = Some(max_high_duration);
+    }
+}
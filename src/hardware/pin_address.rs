@@ -0,0 +1,132 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Lowest and highest 7-bit I2C addresses that aren't reserved
+const MIN_I2C_ADDRESS: u16 = 0x03;
+const MAX_I2C_ADDRESS: u16 = 0x77;
+/// Number of PWM channels on a PCA9685
+const PCA9685_CHANNELS: u8 = 16;
+/// Number of pins in each port of an MCP23017
+const MCP23017_PORT_PINS: u8 = 8;
+
+/// One of the two 8-pin ports of an MCP23017
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum McpPort {
+    A,
+    B,
+}
+
+/// Where an output is wired: a raspberry pi GPIO pin, or a pin of a board on the I2C bus. Written in the
+/// configuration as a BCM GPIO number (e.g. `12`), `pca9685@0x40:3` (PWM channel 3 of the PCA9685 at 0x40) or
+/// `mcp23017@0x20:B2` (pin 2 of port B of the MCP23017 at 0x20)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "PinSpec", into = "PinSpec")]
+pub enum PinAddress {
+    Gpio(u8),
+    Pca9685 { address: u16, channel: u8 },
+    Mcp23017 { address: u16, port: McpPort, pin: u8 },
+}
+
+/// A pin address as written in the configuration: GPIO pins can be given as plain numbers
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum PinSpec {
+    Number(u8),
+    Text(String),
+}
+
+impl PinAddress {
+    /// Get the BCM GPIO number, if the output is wired straight to the pi
+    pub fn gpio(&self) -> Option<u8> {
+        match self {
+            PinAddress::Gpio(pin) => Some(*pin),
+            _ => None,
+        }
+    }
+
+    /// Get the I2C address of the board the output is on, if any
+    pub fn i2c_address(&self) -> Option<u16> {
+        match self {
+            PinAddress::Gpio(_) => None,
+            PinAddress::Pca9685 { address, .. } | PinAddress::Mcp23017 { address, .. } => Some(*address),
+        }
+    }
+}
+
+impl From<u8> for PinAddress {
+    fn from(pin: u8) -> Self {
+        PinAddress::Gpio(pin)
+    }
+}
+
+impl FromStr for PinAddress {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        if let Ok(pin) = text.parse::<u8>() {
+            return Ok(PinAddress::Gpio(pin));
+        }
+        let invalid = || format!("{:?} is not a pin (expected a GPIO number, pca9685@0x40:3 or mcp23017@0x20:B2)", text);
+        let (chip, rest) = text.split_once('@').ok_or_else(invalid)?;
+        let (address, pin) = rest.split_once(':').ok_or_else(invalid)?;
+        let address = match address.strip_prefix("0x").or(address.strip_prefix("0X")) {
+            Some(hex) => u16::from_str_radix(hex, 16),
+            None => address.parse(),
+        }.map_err(|_| format!("{:?} is not an I2C address", address))?;
+        if !(MIN_I2C_ADDRESS..=MAX_I2C_ADDRESS).contains(&address) {
+            return Err(format!("I2C address {:#04x} is outside the usable range {:#04x}-{:#04x}", address, MIN_I2C_ADDRESS, MAX_I2C_ADDRESS));
+        }
+        match chip.to_lowercase().as_str() {
+            "pca9685" => {
+                let channel = pin.parse::<u8>().ok().filter(|channel| *channel < PCA9685_CHANNELS)
+                    .ok_or(format!("{:?} is not a PCA9685 channel (0-{})", pin, PCA9685_CHANNELS - 1))?;
+                Ok(PinAddress::Pca9685 { address, channel })
+            },
+            "mcp23017" => {
+                let mut chars = pin.chars();
+                let port = match chars.next().map(|port| port.to_ascii_uppercase()) {
+                    Some('A') => McpPort::A,
+                    Some('B') => McpPort::B,
+                    _ => return Err(format!("{:?} is not an MCP23017 pin (A0-A7 or B0-B7)", pin)),
+                };
+                let number = chars.as_str().parse::<u8>().ok().filter(|number| *number < MCP23017_PORT_PINS)
+                    .ok_or(format!("{:?} is not an MCP23017 pin (A0-A7 or B0-B7)", pin))?;
+                Ok(PinAddress::Mcp23017 { address, port, pin: number })
+            },
+            _ => Err(format!("unknown I2C board {:?} (expected pca9685 or mcp23017)", chip)),
+        }
+    }
+}
+
+impl fmt::Display for PinAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinAddress::Gpio(pin) => write!(f, "{}", pin),
+            PinAddress::Pca9685 { address, channel } => write!(f, "pca9685@{:#04x}:{}", address, channel),
+            PinAddress::Mcp23017 { address, port, pin } => write!(f, "mcp23017@{:#04x}:{:?}{}", address, port, pin),
+        }
+    }
+}
+
+impl TryFrom<PinSpec> for PinAddress {
+    type Error = String;
+
+    fn try_from(spec: PinSpec) -> Result<Self, Self::Error> {
+        match spec {
+            PinSpec::Number(pin) => Ok(PinAddress::Gpio(pin)),
+            PinSpec::Text(text) => text.parse(),
+        }
+    }
+}
+
+impl From<PinAddress> for PinSpec {
+    fn from(address: PinAddress) -> Self {
+        match address {
+            PinAddress::Gpio(pin) => PinSpec::Number(pin),
+            address => PinSpec::Text(address.to_string()),
+        }
+    }
+}
@@ -1,15 +1,23 @@
 use std::time::Duration;
 use std::time::Instant;
 
-use rppal::gpio::Gpio;
-use rppal::gpio::OutputPin;
 use serde::{Deserialize, Serialize};
+use tokio::task::{AbortHandle, JoinHandle};
 use tokio_timerfd::Delay;
 
-use crate::hardware::striker_hardware_util::{StrikerHardwareKind, StrikerHardwareUtil};
+use crate::hardware::actuator::{servo_stroke, stepper_stroke, ServoSettings, StepperSettings};
+use crate::hardware::hit_sensor::HitSensorConfig;
+use crate::hardware::output::{Output, OutputMode};
+use crate::hardware::pin_address::PinAddress;
+use crate::hardware::striker_hardware_util::{DriverType, HardwareKind, StrikerHardwareUtil};
+use crate::playback::dynamics::{VELOCITY_GAIN_RANGE, VELOCITY_OFFSET_RANGE};
+use crate::playback::humanize::HumanizeOverrides;
+use crate::system::system_constants::{StrikerConstants, StrikerConstantsOverrides};
 
 
 const MAX_HIT_DURATION_MS: f64 = 400.0;
+/// Shortest time a hit is queued for, so one that's retried while the striker is still running late can't spin
+const MIN_QUEUE_DELAY: Duration = Duration::from_millis(1);
 
 /// Represents a Striker that can be triggered, usually tied to a drum or other percussion target
 pub struct Striker {
@@ -17,54 +25,409 @@ pub struct Striker {
     pub name: String,
     /// MIDI note number that triggers this Striker
     pub note: u8,
-    /// GPIO pin that controls the Striker hardware
-    pin: OutputPin,
+    /// Pin that controls the Striker hardware
+    pin: Output,
     /// Type of striker hardware this Striker uses
-    kind: StrikerHardwareKind,
+    kind: HardwareKind,
+    /// The constants of its kind, looked up when the striker is created
+    kind_constants: StrikerConstants,
+    /// Changes to the constants of its kind for this particular unit
+    overrides: Option<StrikerConstantsOverrides>,
+    /// PWM settings, used if its kind has the Pwm driver
+    pwm: Option<PwmSettings>,
+    /// Servo settings, used if its kind has the Servo driver
+    servo: Option<ServoSettings>,
+    /// Stepper settings, used if its kind has the Stepper driver
+    stepper: Option<StepperSettings>,
+    /// Pin wired to the stepper driver's DIR input, for stepper strikers
+    dir_pin: Option<Output>,
     /// Minimum duration of the hit in milliseconds
     min_hit_duration: Option<f64>,
     /// Maximum duration of the hit in milliseconds
     max_hit_duration: Option<f64>,
+    /// How hit durations follow velocity (1 is a straight line)
+    velocity_curve: Option<f64>,
+    /// Incoming velocities are multiplied by this, to balance the striker against the others
+    velocity_gain: Option<f64>,
+    /// Added to incoming velocities after the gain
+    velocity_offset: Option<f64>,
+    /// Changes to the global humanize amounts for this striker
+    humanize: Option<HumanizeOverrides>,
+    /// Sensor that confirms the striker's hits land
+    sensor: Option<HitSensorConfig>,
+    /// Handle to the task running the current (or most recent) hit
+    pulse: Option<AbortHandle>,
+    /// What to do with a hit that comes in while the striker is mid-hit
+    retrigger: Option<RetriggerPolicy>,
+    /// When the current (or most recent) hit started, and its velocity
+    current_hit: Option<(Instant, u8)>,
+    /// When the striker is next free after the last hit that was queued
+    queued_until: Option<Instant>,
+    /// How many hits have been dropped or queued because the striker was mid-hit
+    retrigger_stats: RetriggerStats,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct StrikerData {
     pub name: String,
-    pub pin: u8,
-    pub kind: StrikerHardwareKind,
+    pub pin: PinAddress,
+    pub kind: HardwareKind,
     pub note: u8,
     pub min_hit_duration: Option<f64>,
-    pub max_hit_duration: Option<f64>
+    pub max_hit_duration: Option<f64>,
+    /// How hit durations follow velocity (0.2-5, 1 if not set): the velocity's share of its range is raised to this
+    /// power, so above 1 quiet hits get shorter and below 1 they get longer. Fitted by `calibrate`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity_curve: Option<f64>,
+    /// Incoming velocities are multiplied by this (0-2) before the global velocity processing (1 if not set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity_gain: Option<f64>,
+    /// Added to incoming velocities after the gain (-64 to 64, 0 if not set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity_offset: Option<f64>,
+    /// Changes to the constants of its kind for this particular unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overrides: Option<StrikerConstantsOverrides>,
+    /// PWM settings, for strikers whose kind has the Pwm driver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pwm: Option<PwmSettings>,
+    /// Servo settings, for strikers whose kind has the Servo driver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub servo: Option<ServoSettings>,
+    /// Stepper settings, for strikers whose kind has the Stepper driver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stepper: Option<StepperSettings>,
+    /// What to do with a hit that comes in while the striker is mid-hit (Drop if not set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retrigger: Option<RetriggerPolicy>,
+    /// Changes to the global humanize amounts for this striker, e.g. more timing jitter on a hi-hat than a kick
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humanize: Option<HumanizeOverrides>,
+    /// Sensor that confirms the striker's hits land, counting the ones it picks up nothing from as missed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor: Option<HitSensorConfig>,
+}
+
+/// What a striker does with a hit that comes in while it's still mid-hit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RetriggerPolicy {
+    /// Ignore the new hit
+    #[default]
+    Drop,
+    /// Play the new hit once the current one has finished and the striker has recovered
+    Queue,
+    /// Cut the current hit short and start the new one straight away
+    Retrigger,
+    /// Keep whichever of the two hits is louder, extending the current hit if the new one is louder
+    Merge,
+}
+
+/// What happened when a striker was asked to hit
+pub enum StrikeOutcome {
+    /// The hit started. The handle resolves to how long the striker was actually on (or moving) for
+    Started(JoinHandle<Duration>),
+    /// The striker was mid-hit, so the hit was ignored
+    Dropped,
+    /// The striker was mid-hit, so the hit should be played again at the given time
+    Queued(Instant),
+    /// The striker was mid-hit with a hit at least as loud, so the new hit was folded into it
+    Merged,
+}
+
+/// How many hits a striker has dropped or queued because it was mid-hit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetriggerStats {
+    pub dropped: u64,
+    pub queued: u64,
+}
+
+/// What a PWM striker's velocity controls
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VelocityMapping {
+    /// The length of the hit, like a solenoid striker
+    #[default]
+    Duration,
+    /// The hold duty, with every hit the full length
+    Duty,
+    /// Both the length of the hit and the hold duty
+    Both,
+}
+
+/// PWM settings for a striker whose kind has the Pwm driver
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PwmSettings {
+    /// PWM frequency in Hz
+    pub frequency: f64,
+    /// How long each hit is driven at the kind's max_duty before dropping to the hold duty, in milliseconds
+    pub kick_duration: f64,
+    /// Duty (0-1) for the rest of the hit, at full velocity
+    pub hold_duty: f64,
+    pub velocity_mapping: VelocityMapping,
+    /// Use the pin's hardware PWM channel (GPIO 12, 13, 18 or 19) instead of software PWM
+    pub hardware: bool,
+}
+
+impl Default for PwmSettings {
+    fn default() -> Self {
+        Self {
+            frequency: 1000.0,
+            kick_duration: 0.0,
+            hold_duty: 0.5,
+            velocity_mapping: VelocityMapping::Duration,
+            hardware: false,
+        }
+    }
+}
+
+/// How a single hit drives its output
+struct PulsePlan {
+    /// How long the output is on for
+    duration: Duration,
+    /// Duty at the start of the hit
+    kick_duty: f64,
+    /// When to drop from the kick duty to the hold duty, and the hold duty, if it changes during the hit
+    hold: Option<(Duration, f64)>,
+    /// PWM frequency for any duty below 1
+    frequency: f64,
+}
+
+impl StrikerData {
+    /// Fill in any unset hit durations with the defaults for the striker kind (and unit)
+    pub fn with_defaults(self) -> Result<Self, String> {
+        let constants = StrikerHardwareUtil::get_constants(&self.kind)?.with_overrides(self.overrides.as_ref());
+        Ok(Self {
+            min_hit_duration: self.min_hit_duration.or(Some(constants.default_min_hit_duration)),
+            max_hit_duration: self.max_hit_duration.or(Some(constants.max_max_hit_duration)),
+            ..self
+        })
+    }
+
+    /// Get the PWM frequency the striker drives its pin at, if its kind's driver uses PWM
+    pub fn pwm_frequency(&self, driver: DriverType) -> Option<f64> {
+        pwm_frequency(driver, self.pwm, self.servo)
+    }
+
+    /// Get a parameter that can be set live (see Striker::set_parameter), to write a value into without checking it
+    pub fn parameter_mut(&mut self, parameter: &str) -> Option<&mut Option<f64>> {
+        match parameter {
+            "min_hit_duration" => Some(&mut self.min_hit_duration),
+            "max_hit_duration" => Some(&mut self.max_hit_duration),
+            "velocity_gain" => Some(&mut self.velocity_gain),
+            "velocity_offset" => Some(&mut self.velocity_offset),
+            _ => None,
+        }
+    }
+
+    /// Get how the striker's output should drive its pin
+    pub fn output_mode(&self) -> OutputMode {
+        let hardware = self.pwm.is_some_and(|pwm| pwm.hardware) || self.servo.is_some_and(|servo| servo.hardware);
+        if hardware { OutputMode::HardwarePwm } else { OutputMode::Gpio }
+    }
 }
 
 impl Striker {
     /// Create a new Striker
-    pub fn new(note_num: u8, pin_num: u8, name: &str, kind: StrikerHardwareKind) -> Self {
-        let output_pin = Gpio::new().unwrap().get(pin_num).unwrap().into_output();
-        Self {
+    pub fn new(note_num: u8, pin_num: PinAddress, name: &str, kind: HardwareKind) -> Result<Self, String> {
+        let kind_constants = StrikerHardwareUtil::get_constants(&kind)?;
+        let output_pin = Output::new(pin_num)?;
+        output_pin.set_max_high_duration(Striker::longest_hit_duration(&kind_constants));
+        Ok(Self {
             name: name.to_string(),
             note: note_num,
             pin: output_pin,
             kind,
+            kind_constants,
+            overrides: None,
+            pwm: None,
+            servo: None,
+            stepper: None,
+            dir_pin: None,
             min_hit_duration: None,
             max_hit_duration: None,
+            velocity_curve: None,
+            velocity_gain: None,
+            velocity_offset: None,
+            humanize: None,
+            sensor: None,
+            pulse: None,
+            retrigger: None,
+            current_hit: None,
+            queued_until: None,
+            retrigger_stats: RetriggerStats::default(),
+        })
+    }
+
+    /// Set off the striker, triggering the striker for a given duration specified by the striker type and velocity.
+    /// The hit runs on its own task so other strikers can fire while it's in progress, and the outcome's handle
+    /// resolves to how long the striker was actually on (or moving) for. If the striker is already mid-hit, its
+    /// retrigger policy decides what happens instead. Servo and stepper strikers move out and back instead, by an
+    /// amount set by velocity
+    pub fn strike(&mut self, velocity: u8) -> Result<StrikeOutcome, std::io::Error> {
+        if self.is_active() {
+            return self.retrigger(velocity);
         }
+        self.start_hit(Instant::now(), velocity)
     }
 
-    /// Set off the striker, triggering the striker for a given duration specified by the striker type and velocity
-    pub async fn strike(&mut self, velocity: u8) -> Result<(), std::io::Error> {
-        if !self.pin.is_set_high() {
-            let duration = self.get_strike_duration(velocity);
+    /// Set off a solenoid or PWM striker for a set length of time, regardless of its hit durations and velocity
+    /// curve (used by calibration to sweep through hit lengths). PWM strikers kick and hold as they do at full
+    /// velocity
+    pub fn strike_for(&mut self, duration: Duration) -> Result<JoinHandle<Duration>, String> {
+        if !matches!(self.get_constants().driver, DriverType::Solenoid | DriverType::Pwm) {
+            return Err(format!("{} isn't a solenoid or PWM striker, so can't be struck for a set time", self.name));
+        }
+        self.abort();
+        let plan = self.plan_pulse(127);
+        let hold = plan.hold.filter(|(after, _)| *after < duration);
+        let start = Instant::now();
+        let pulse = self.drive_pulse(start, PulsePlan { duration, hold, ..plan }).map_err(|e| e.to_string())?;
+        self.pulse = Some(pulse.abort_handle());
+        self.current_hit = Some((start, 127));
+        Ok(pulse)
+    }
 
-            // Trigger the striker
-            self.pin.set_high();
+    /// Deal with a hit that comes in while the striker is mid-hit, according to its retrigger policy. Steppers can't
+    /// be cut short without losing their place, so they queue the hit instead of retriggering or merging it
+    fn retrigger(&mut self, velocity: u8) -> Result<StrikeOutcome, std::io::Error> {
+        let driver = self.get_constants().driver;
+        let policy = match self.get_retrigger_policy() {
+            RetriggerPolicy::Retrigger | RetriggerPolicy::Merge if driver == DriverType::Stepper => RetriggerPolicy::Queue,
+            policy => policy,
+        };
+        let now = Instant::now();
+        match policy {
+            RetriggerPolicy::Drop => {
+                self.retrigger_stats.dropped += 1;
+                Ok(StrikeOutcome::Dropped)
+            },
+            RetriggerPolicy::Queue => {
+                let free = self.current_hit
+                    .map(|(start, velocity)| start + self.min_stroke_interval(velocity))
+                    .unwrap_or(now);
+                // Wait behind any hits that are already queued
+                let at = free.max(self.queued_until.unwrap_or(now)).max(now + MIN_QUEUE_DELAY);
+                self.queued_until = Some(at + self.min_stroke_interval(velocity));
+                self.retrigger_stats.queued += 1;
+                Ok(StrikeOutcome::Queued(at))
+            },
+            RetriggerPolicy::Retrigger => {
+                self.abort();
+                self.start_hit(now, velocity)
+            },
+            RetriggerPolicy::Merge => {
+                let Some((start, _)) = self.current_hit.filter(|(_, current_velocity)| velocity > *current_velocity) else {
+                    return Ok(StrikeOutcome::Merged);
+                };
+                // Leave the output as it is and carry on to the end of the louder hit, timed from when the current
+                // one started. Servos just swing on to the louder hit's angle
+                if let Some(pulse) = &self.pulse {
+                    pulse.abort();
+                }
+                self.start_hit(if driver == DriverType::Servo { now } else { start }, velocity)
+            },
+        }
+    }
 
-            // Wait for the duration of the hit, then turn off the striker
-            let delay = Delay::new(Instant::now() + duration)?;
-            delay.await?;
-            self.pin.set_low();
-        } else { println!("Striker already activated, ignoring") }
-        Ok(())
+    /// Start a hit at the given velocity, timed from the given start (which is in the past when a louder hit is
+    /// merged into the one in progress)
+    fn start_hit(&mut self, start: Instant, velocity: u8) -> Result<StrikeOutcome, std::io::Error> {
+        let pulse = match self.get_constants().driver {
+            DriverType::Servo => tokio::spawn(servo_stroke(self.pin.clone(), self.servo.unwrap_or_default(), velocity)),
+            DriverType::Stepper => {
+                let (Some(stepper), Some(dir_pin)) = (self.stepper, self.dir_pin.clone()) else {
+                    eprintln!("{} has no stepper settings, ignoring", self.name);
+                    return Ok(StrikeOutcome::Dropped);
+                };
+                tokio::spawn(stepper_stroke(self.pin.clone(), dir_pin, stepper, velocity))
+            },
+            DriverType::Solenoid | DriverType::Pwm => self.drive_pulse(start, self.plan_pulse(velocity))?,
+        };
+        self.pulse = Some(pulse.abort_handle());
+        self.current_hit = Some((start, velocity));
+        Ok(StrikeOutcome::Started(pulse))
+    }
+
+    /// Drive the output for a planned pulse timed from the given start (at the hold duty if that's already past its
+    /// kick), on its own task that resolves to how long the pulse lasted
+    fn drive_pulse(&self, start: Instant, plan: PulsePlan) -> Result<JoinHandle<Duration>, std::io::Error> {
+        let pin = self.pin.clone();
+
+        // Trigger the striker, at the hold duty if a merged hit is already past its kick
+        let hold = plan.hold.filter(|(after, _)| start + *after > Instant::now());
+        let duty = match (plan.hold, hold) {
+            (Some((_, hold_duty)), None) => hold_duty,
+            _ => plan.kick_duty,
+        };
+        drive(&pin, duty, plan.frequency);
+
+        // Wait for the duration of the hit (dropping to the hold duty on the way for PWM), then turn off the striker
+        let delays = Delay::new(start + plan.duration).and_then(|delay| {
+            let hold = hold.map(|(after, duty)| Delay::new(start + after).map(|hold_delay| (hold_delay, duty))).transpose()?;
+            Ok((delay, hold))
+        });
+        let (delay, hold) = match delays {
+            Ok(delays) => delays,
+            Err(e) => {
+                pin.set_low();
+                return Err(e);
+            }
+        };
+        let frequency = plan.frequency;
+        Ok(tokio::spawn(async move {
+            if let Some((hold_delay, hold_duty)) = hold {
+                let _ = hold_delay.await;
+                drive(&pin, hold_duty, frequency);
+            }
+            // turn the striker off even if the timer fails, it'll just be a short hit
+            let _ = delay.await;
+            pin.set_low();
+            start.elapsed()
+        }))
+    }
+
+    /// Work out how a hit at the given velocity drives the output. Solenoids are fully on for a length set by
+    /// velocity, while PWM strikers kick at max_duty then hold at a duty and for a length set by their velocity mapping
+    fn plan_pulse(&self, velocity: u8) -> PulsePlan {
+        let constants = self.get_constants();
+        if constants.driver != DriverType::Pwm {
+            return PulsePlan { duration: self.get_strike_duration(velocity), kick_duty: 1.0, hold: None, frequency: 0.0 };
+        }
+        let pwm = self.pwm.unwrap_or_default();
+        let (maps_duration, maps_duty) = match pwm.velocity_mapping {
+            VelocityMapping::Duration => (true, false),
+            VelocityMapping::Duty => (false, true),
+            VelocityMapping::Both => (true, true),
+        };
+        let duration = self.get_strike_duration(if maps_duration { velocity } else { 127 });
+        let hold_duty = if maps_duty {
+            constants.min_duty + (pwm.hold_duty - constants.min_duty) * velocity as f64 / 127.0
+        } else {
+            pwm.hold_duty
+        }.clamp(constants.min_duty, constants.max_duty);
+        let kick = Duration::from_micros((pwm.kick_duration * 1000.0) as u64);
+        if kick.is_zero() {
+            PulsePlan { duration, kick_duty: hold_duty, hold: None, frequency: pwm.frequency }
+        } else {
+            let hold = (kick < duration).then_some((kick, hold_duty));
+            PulsePlan { duration, kick_duty: constants.max_duty, hold, frequency: pwm.frequency }
+        }
+    }
+
+    /// Get how long a hit at the given velocity keeps the striker busy (on, or moving for servos and steppers)
+    pub fn hit_length(&self, velocity: u8) -> Duration {
+        match self.get_constants().driver {
+            DriverType::Servo => self.servo.unwrap_or_default().stroke_duration(),
+            DriverType::Stepper => self.stepper.map(|stepper| stepper.stroke_duration_for(velocity)).unwrap_or_default(),
+            DriverType::Solenoid | DriverType::Pwm => self.plan_pulse(velocity).duration,
+        }
+    }
+
+    /// Get the shortest time from the start of a hit at the given velocity to the start of the next: the hit itself
+    /// plus the recovery time of the striker's kind
+    pub fn min_stroke_interval(&self, velocity: u8) -> Duration {
+        let recovery = Duration::from_micros((self.get_constants().min_recovery_time * 1000.0) as u64);
+        self.hit_length(velocity) + recovery
     }
 
     /// Get the duration of the hit based on striker type and velocity, clamping if necessary
@@ -72,7 +435,8 @@ impl Striker {
         // Get the duration of the hit, clamping if necessary
         let min_hit_duration = self.get_min_hit_duration();
         let max_hit_duration = self.get_max_hit_duration();
-        let mut duration = min_hit_duration + ((velocity as f64 * max_hit_duration) / 127.0);
+        let amount = (velocity as f64 / 127.0).powf(self.get_velocity_curve());
+        let mut duration = min_hit_duration + amount * max_hit_duration;
         if duration > MAX_HIT_DURATION_MS {
             duration = MAX_HIT_DURATION_MS;
             println!("Clamped hit duration to {}", duration)
@@ -80,6 +444,76 @@ impl Striker {
         Duration::from_micros((duration * 1000.0) as u64)
     }
 
+    /// Create a Striker from its configuration, driving an output that has already been claimed (e.g. one kept from
+    /// the previous configuration when reloading)
+    pub fn with_output(config: StrikerData, pin: Output, dir_pin: Option<Output>) -> Result<Self, String> {
+        let striker = Self {
+            name: config.name,
+            note: config.note,
+            pin,
+            kind_constants: StrikerHardwareUtil::get_constants(&config.kind)?,
+            kind: config.kind,
+            overrides: config.overrides,
+            pwm: config.pwm,
+            servo: config.servo,
+            stepper: config.stepper,
+            dir_pin,
+            min_hit_duration: config.min_hit_duration,
+            max_hit_duration: config.max_hit_duration,
+            velocity_curve: config.velocity_curve,
+            velocity_gain: config.velocity_gain,
+            velocity_offset: config.velocity_offset,
+            humanize: config.humanize,
+            sensor: config.sensor,
+            pulse: None,
+            retrigger: config.retrigger,
+            current_hit: None,
+            queued_until: None,
+            retrigger_stats: RetriggerStats::default(),
+        };
+        striker.pin.set_max_high_duration(striker.max_drive_duration());
+        if let Some(frequency) = pwm_frequency(striker.get_constants().driver, striker.pwm, striker.servo) {
+            striker.pin.set_frequency(frequency);
+        }
+        if let (Some(dir_pin), Some(stepper)) = (&striker.dir_pin, striker.stepper) {
+            dir_pin.set_max_high_duration(stepper.stroke_duration());
+        }
+        Ok(striker)
+    }
+
+    /// Get the output that drives the striker
+    pub fn get_output(&self) -> &Output {
+        &self.pin
+    }
+
+    /// Get every output the striker uses (e.g. a stepper's STEP and DIR pins)
+    pub fn get_outputs(&self) -> Vec<&Output> {
+        std::iter::once(&self.pin).chain(self.dir_pin.as_ref()).collect()
+    }
+
+    /// Get the constants for this striker: those of its kind, with any overrides for this unit applied
+    pub fn get_constants(&self) -> StrikerConstants {
+        self.kind_constants.with_overrides(self.overrides.as_ref())
+    }
+
+    /// Get the longest the striker's pin may be high for, which the watchdog uses as its limit. A stepper's STEP pin
+    /// is only high for half of each step
+    fn max_drive_duration(&self) -> Duration {
+        let constants = self.get_constants();
+        match constants.driver {
+            DriverType::Servo => self.servo.unwrap_or_default().stroke_duration(),
+            DriverType::Stepper => self.stepper.map(|stepper| stepper.step_period()).unwrap_or_default(),
+            DriverType::Solenoid | DriverType::Pwm => Striker::longest_hit_duration(&constants),
+        }
+    }
+
+    /// Get the longest hit a striker with the given constants can produce, which the watchdog uses as the limit for
+    /// its pin
+    pub fn longest_hit_duration(constants: &StrikerConstants) -> Duration {
+        let longest_ms = (constants.max_min_hit_duration + constants.max_max_hit_duration).min(MAX_HIT_DURATION_MS);
+        Duration::from_micros((longest_ms * 1000.0) as u64)
+    }
+
     /// Get the name of the Striker
     pub fn get_name(&self) -> String {
         self.name.clone()
@@ -91,42 +525,163 @@ impl Striker {
     }
 
     /// Get the type of striker used to hit the Striker
-    pub fn get_striker_kind(&self) -> StrikerHardwareKind {
-        self.kind
+    pub fn get_striker_kind(&self) -> HardwareKind {
+        self.kind.clone()
     }
 
-    /// Get the raspberry pi GPIO pin number that controls the striker for this Striker
-    pub fn get_pin_num(&self) -> u8 { self.pin.pin() }
+    /// Get the pin that controls the striker for this Striker
+    pub fn get_pin(&self) -> PinAddress { self.pin.pin() }
 
     /// Get the minimum duration of the hit in milliseconds
     pub fn get_min_hit_duration(&self) -> f64 {
         self.min_hit_duration.unwrap_or(
-            StrikerHardwareUtil::get_default_min_hit_duration(self.kind)
+            self.get_constants().default_min_hit_duration
         )
     }
 
     /// Get the maximum duration of the hit in milliseconds
     pub fn get_max_hit_duration(&self) -> f64 {
         self.max_hit_duration.unwrap_or(
-            StrikerHardwareUtil::get_max_hit_duration(self.kind)
+            self.get_constants().max_max_hit_duration
         )
     }
 
+    /// Set the minimum duration of the hit in milliseconds, within the bounds allowed for the striker kind
+    pub fn set_min_hit_duration(&mut self, duration: f64) -> Result<(), String> {
+        let constants = self.get_constants();
+        if !(constants.min_min_hit_duration..=constants.max_min_hit_duration).contains(&duration) {
+            return Err(format!(
+                "min_hit_duration for {} must be between {} and {}",
+                self.kind, constants.min_min_hit_duration, constants.max_min_hit_duration
+            ));
+        }
+        self.min_hit_duration = Some(duration);
+        Ok(())
+    }
+
+    /// Set the maximum duration of the hit in milliseconds, within the bounds allowed for the striker kind
+    pub fn set_max_hit_duration(&mut self, duration: f64) -> Result<(), String> {
+        let constants = self.get_constants();
+        if !(constants.min_max_hit_duration..=constants.max_max_hit_duration).contains(&duration) {
+            return Err(format!(
+                "max_hit_duration for {} must be between {} and {}",
+                self.kind, constants.min_max_hit_duration, constants.max_max_hit_duration
+            ));
+        }
+        self.max_hit_duration = Some(duration);
+        Ok(())
+    }
+
+    /// Get the exponent of the curve hit durations follow velocity along
+    pub fn get_velocity_curve(&self) -> f64 {
+        self.velocity_curve.unwrap_or(1.0)
+    }
+
+    /// Get the velocity gain, which incoming velocities are multiplied by
+    pub fn get_velocity_gain(&self) -> f64 {
+        self.velocity_gain.unwrap_or(1.0)
+    }
+
+    /// Get the velocity offset, which is added to incoming velocities after the gain
+    pub fn get_velocity_offset(&self) -> f64 {
+        self.velocity_offset.unwrap_or(0.0)
+    }
+
+    /// Apply the striker's gain and offset to an incoming velocity, before the global velocity processing
+    pub fn trim_velocity(&self, velocity: u8) -> f64 {
+        velocity as f64 * self.get_velocity_gain() + self.get_velocity_offset()
+    }
+
+    /// Get the value of a parameter that can be set live (by `set` or a controller), and the lowest and highest it
+    /// can be set to
+    pub fn get_parameter(&self, parameter: &str) -> Result<(f64, (f64, f64)), String> {
+        let constants = self.get_constants();
+        match parameter {
+            "min_hit_duration" => Ok((self.get_min_hit_duration(), (constants.min_min_hit_duration, constants.max_min_hit_duration))),
+            "max_hit_duration" => Ok((self.get_max_hit_duration(), (constants.min_max_hit_duration, constants.max_max_hit_duration))),
+            "velocity_gain" => Ok((self.get_velocity_gain(), VELOCITY_GAIN_RANGE)),
+            "velocity_offset" => Ok((self.get_velocity_offset(), VELOCITY_OFFSET_RANGE)),
+            _ => Err(format!("Unknown striker parameter {}", parameter)),
+        }
+    }
+
+    /// Set a parameter that can be set live: min_hit_duration, max_hit_duration, velocity_gain or velocity_offset
+    pub fn set_parameter(&mut self, parameter: &str, value: f64) -> Result<(), String> {
+        match parameter {
+            "min_hit_duration" => self.set_min_hit_duration(value),
+            "max_hit_duration" => self.set_max_hit_duration(value),
+            "velocity_gain" | "velocity_offset" => {
+                let (_, (lowest, highest)) = self.get_parameter(parameter)?;
+                if !(lowest..=highest).contains(&value) {
+                    return Err(format!("{} must be between {} and {}", parameter, lowest, highest));
+                }
+                if parameter == "velocity_gain" {
+                    self.velocity_gain = Some(value);
+                } else {
+                    self.velocity_offset = Some(value);
+                }
+                Ok(())
+            },
+            _ => Err(format!("Unknown striker parameter {}", parameter)),
+        }
+    }
+
+    /// Check whether the striker is currently mid-hit
+    pub fn is_active(&self) -> bool {
+        self.pin.is_set_high() || self.pulse.as_ref().is_some_and(|pulse| !pulse.is_finished())
+    }
+
     /// Export
     pub fn export_raw(&self) -> StrikerData {
         StrikerData {
             name: self.name.clone(),
             note: self.note,
             pin: self.pin.pin(),
-            kind: self.kind,
+            kind: self.kind.clone(),
             min_hit_duration: Some(self.get_min_hit_duration()),
             max_hit_duration: Some(self.get_max_hit_duration()),
+            velocity_curve: self.velocity_curve,
+            velocity_gain: self.velocity_gain,
+            velocity_offset: self.velocity_offset,
+            overrides: self.overrides,
+            pwm: self.pwm,
+            servo: self.servo,
+            stepper: self.stepper,
+            retrigger: self.retrigger,
+            humanize: self.humanize,
+            sensor: self.sensor,
         }
     }
 
+    /// Get the striker's changes to the global humanize amounts, if any
+    pub fn get_humanize_overrides(&self) -> Option<&HumanizeOverrides> {
+        self.humanize.as_ref()
+    }
+
+    /// Get when the current (or most recent) hit started
+    pub fn get_current_hit_start(&self) -> Option<Instant> {
+        self.current_hit.map(|(start, _)| start)
+    }
+
+    /// Get what the striker does with a hit that comes in while it's mid-hit
+    pub fn get_retrigger_policy(&self) -> RetriggerPolicy {
+        self.retrigger.unwrap_or_default()
+    }
+
+    /// Get how many hits the striker has dropped or queued because it was mid-hit
+    pub fn get_retrigger_stats(&self) -> RetriggerStats {
+        self.retrigger_stats
+    }
+
     /// Abort the current hit, turning off the striker early
     pub fn abort(&mut self) {
+        if let Some(pulse) = self.pulse.as_ref() {
+            pulse.abort();
+        }
         self.pin.set_low();
+        if let Some(dir_pin) = &self.dir_pin {
+            dir_pin.set_low();
+        }
     }
 }
 
@@ -134,14 +689,28 @@ impl TryFrom<StrikerData> for Striker {
     type Error = String;
 
     fn try_from(config: StrikerData) -> Result<Self, Self::Error> {
-        Ok(Self {
-            name: config.name,
-            note: config.note,
-            pin: Gpio::new().unwrap().get(config.pin).unwrap().into_output(),
-            kind: config.kind,
-            min_hit_duration: config.min_hit_duration,
-            max_hit_duration: config.max_hit_duration,
-        })
+        let pin = Output::new(config.pin)?;
+        pin.set_mode(config.output_mode())?;
+        let dir_pin = config.stepper.map(|stepper| Output::new(stepper.dir_pin)).transpose()?;
+        Striker::with_output(config, pin, dir_pin)
+    }
+}
+
+/// Get the PWM frequency a striker with the given driver and settings drives its pin at, if any
+fn pwm_frequency(driver: DriverType, pwm: Option<PwmSettings>, servo: Option<ServoSettings>) -> Option<f64> {
+    match driver {
+        DriverType::Pwm => Some(pwm.unwrap_or_default().frequency),
+        DriverType::Servo => Some(servo.unwrap_or_default().frequency),
+        DriverType::Solenoid | DriverType::Stepper => None,
+    }
+}
+
+/// Drive an output at the given duty, fully on if it's 1 or more
+fn drive(pin: &Output, duty: f64, frequency: f64) {
+    if duty >= 1.0 {
+        pin.set_high();
+    } else {
+        pin.set_duty(frequency, duty);
     }
 }
 
@@ -149,5 +718,8 @@ impl TryFrom<StrikerData> for Striker {
 impl Drop for Striker {
     fn drop(&mut self) {
         self.pin.set_low();
+        if let Some(dir_pin) = &self.dir_pin {
+            dir_pin.set_low();
+        }
     }
 }
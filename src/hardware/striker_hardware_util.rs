@@ -1,48 +1,67 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
-use crate::system::system_constants::{StrikerConstants, SYSTEM_CONSTANTS};
+use crate::system::system_constants::{get_striker_constants, StrikerConstants};
+
 
+/// Name of a kind of hardware (e.g. "SolenoidBig"). Kinds are defined in the `kinds` section of the system constants,
+/// so new ones can be added without recompiling
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct HardwareKind(String);
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub enum StrikerHardwareKind {
-    SolenoidBig,
-    SolenoidSmall,
+impl HardwareKind {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
 }
 
-impl TryFrom<&str> for StrikerHardwareKind {
-    type Error = String;
+impl From<&str> for HardwareKind {
+    fn from(name: &str) -> Self {
+        HardwareKind(name.to_string())
+    }
+}
 
-    fn try_from(kind: &str) -> Result<Self, Self::Error> {
-        match kind {
-            "solenoid_big" => Ok(StrikerHardwareKind::SolenoidBig),
-            "solenoid_small" => Ok(StrikerHardwareKind::SolenoidSmall),
-            _ => Err("Unknown striker hardware kind".to_string()),
-        }
+impl fmt::Display for HardwareKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
+/// How the hardware of a kind is driven
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DriverType {
+    /// Switched fully on for the length of the hit
+    #[default]
+    Solenoid,
+    /// Kicked at max_duty, then held at a lower duty with PWM for the rest of the hit
+    Pwm,
+    /// A hobby servo, swung out to an angle and back (configured by the striker's `servo` settings)
+    Servo,
+    /// A step/dir stepper driver, stepped forwards and back (configured by the striker's `stepper` settings)
+    Stepper,
+}
+
 pub struct StrikerHardwareUtil {}
 
 impl StrikerHardwareUtil {
-    fn get_constants(striker_kind: StrikerHardwareKind) -> &'static StrikerConstants {
-        match striker_kind {
-            StrikerHardwareKind::SolenoidBig => &SYSTEM_CONSTANTS.strikers.SolenoidBig,
-            StrikerHardwareKind::SolenoidSmall => &SYSTEM_CONSTANTS.strikers.SolenoidSmall,
-        }
+    pub fn get_constants(striker_kind: &HardwareKind) -> Result<StrikerConstants, String> {
+        get_striker_constants(striker_kind)
     }
 
-    pub fn get_min_hit_duration(striker_kind: StrikerHardwareKind) -> f64 {
-        StrikerHardwareUtil::get_constants(striker_kind).min_min_hit_duration
+    pub fn get_min_hit_duration(striker_kind: &HardwareKind) -> Result<f64, String> {
+        StrikerHardwareUtil::get_constants(striker_kind).map(|constants| constants.min_min_hit_duration)
     }
 
-    pub fn get_max_hit_duration(striker_kind: StrikerHardwareKind) -> f64 {
-        StrikerHardwareUtil::get_constants(striker_kind).max_max_hit_duration
+    pub fn get_max_hit_duration(striker_kind: &HardwareKind) -> Result<f64, String> {
+        StrikerHardwareUtil::get_constants(striker_kind).map(|constants| constants.max_max_hit_duration)
     }
 
-    pub fn get_default_min_hit_duration(striker_kind: StrikerHardwareKind) -> f64 {
-        StrikerHardwareUtil::get_constants(striker_kind).default_min_hit_duration
+    pub fn get_default_min_hit_duration(striker_kind: &HardwareKind) -> Result<f64, String> {
+        StrikerHardwareUtil::get_constants(striker_kind).map(|constants| constants.default_min_hit_duration)
     }
 
-    pub fn get_default_max_hit_duration(striker_kind: StrikerHardwareKind) -> f64 {
-        StrikerHardwareUtil::get_constants(striker_kind).default_max_hit_duration
+    pub fn get_default_max_hit_duration(striker_kind: &HardwareKind) -> Result<f64, String> {
+        StrikerHardwareUtil::get_constants(striker_kind).map(|constants| constants.default_max_hit_duration)
     }
 }
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::comms::remote_command::Command;
+use crate::hardware::adc::{Mcp3008, Mcp3008Address, MCP3008_MAX_READING};
+use crate::hardware::output;
+
+/// Lowest and highest jump of a pad's reading that starts a hit, as a share of full scale
+pub const TRIGGER_THRESHOLD_RANGE: (f64, f64) = (0.01, 1.0);
+/// How often every pad is read
+const TRIGGER_SAMPLE_RATE: u32 = 4_000;
+/// How quickly a pad's resting level follows its readings between hits
+const BASELINE_SMOOTHING: f32 = 0.001;
+/// How long to wait before reading the pads again after a read fails
+const READ_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Piezo trigger pads read through MCP3008s, whose hits are played as notes. Loaded from the `triggers` section of
+/// the configuration, and used by every kit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriggerConfig {
+    /// Play pad hits on the strikers, for layering or doubling
+    pub play_locally: bool,
+    /// Send pad hits out as BLE MIDI notifications, to anything subscribed to the MIDI characteristic
+    pub send_ble: bool,
+    /// MIDI channel pad hits are sent on (1-16)
+    pub channel: u8,
+    /// How long apart two pads' hits can be, in milliseconds, for the quieter to be taken as crosstalk from the louder
+    pub crosstalk_window: f64,
+    /// A hit within the crosstalk window of a louder one on another pad is ignored if its peak is below this share
+    /// of the louder one's (0 turns crosstalk cancellation off)
+    pub crosstalk_ratio: f64,
+    pub pads: Vec<TriggerPad>,
+}
+
+impl Default for TriggerConfig {
+    fn default() -> Self {
+        Self {
+            play_locally: true,
+            send_ble: true,
+            channel: 10,
+            crosstalk_window: 10.0,
+            crosstalk_ratio: 0.5,
+            pads: vec![],
+        }
+    }
+}
+
+/// One piezo trigger on a channel of an MCP3008. Readings are measured as how far they jump from where the pad
+/// rests, as a share of full scale
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TriggerPad {
+    pub name: String,
+    /// MIDI note number the pad plays
+    pub note: u8,
+    #[serde(flatten)]
+    pub adc: Mcp3008Address,
+    /// MCP3008 channel the piezo is on (0-7)
+    pub channel: u8,
+    /// How far a reading has to jump for a hit to start (0-1)
+    #[serde(default = "default_threshold")]
+    pub threshold: f64,
+    /// Jump that gives full velocity (threshold-1), so a softer piezo can still reach 127
+    #[serde(default = "default_sensitivity")]
+    pub sensitivity: f64,
+    /// How long after a hit starts to look for its peak, in milliseconds
+    #[serde(default = "default_scan_time")]
+    pub scan_time: f64,
+    /// How long after a hit starts before the pad can be hit again, in milliseconds, so one hit ringing on doesn't
+    /// sound twice
+    #[serde(default = "default_mask_time")]
+    pub mask_time: f64,
+    /// How velocity follows the peak (0.2-5, 1 is a straight line): the peak's share of the range from threshold to
+    /// sensitivity is raised to this power, so above 1 soft hits get quieter
+    #[serde(default = "default_velocity_curve")]
+    pub velocity_curve: f64,
+}
+
+fn default_threshold() -> f64 {
+    0.05
+}
+
+fn default_sensitivity() -> f64 {
+    0.8
+}
+
+fn default_scan_time() -> f64 {
+    2.0
+}
+
+fn default_mask_time() -> f64 {
+    30.0
+}
+
+fn default_velocity_curve() -> f64 {
+    1.0
+}
+
+impl TriggerPad {
+    /// Get the velocity of a hit with the given peak
+    pub fn velocity(&self, peak: f64) -> u8 {
+        let share = ((peak - self.threshold) / (self.sensitivity - self.threshold)).clamp(0.0, 1.0);
+        (1.0 + share.powf(self.velocity_curve) * 126.0).round() as u8
+    }
+}
+
+/// Where a pad is in a hit
+#[derive(Debug, Clone, Copy)]
+enum PadState {
+    /// Waiting for a hit
+    Idle,
+    /// Looking for the peak of a hit that started at the given time
+    Scanning { started: Instant, peak: f32 },
+    /// Ignoring the pad until the given time, and until it has settled below its threshold
+    Masked { until: Instant },
+}
+
+/// A pad being read, and how far it is through a hit
+struct Pad {
+    config: TriggerPad,
+    /// Index into the converters being read
+    adc: usize,
+    /// Where the reading rests between hits (None until the first reading)
+    baseline: Option<f32>,
+    state: PadState,
+    /// When the pad's last hit started and its peak, for crosstalk cancellation
+    last_hit: Option<(Instant, f32)>,
+}
+
+/// Reads the trigger pads on a thread of their own, sending their hits to the AutoDrum instance as Trigger commands
+pub struct TriggerReader {
+    /// Set to stop the thread
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    /// How many pads are being read
+    pads: usize,
+}
+
+impl TriggerReader {
+    /// No pads at all
+    pub fn none() -> Self {
+        Self { stop: Arc::new(AtomicBool::new(false)), thread: None, pads: 0 }
+    }
+
+    /// Start reading the configured pads. In a dry run there are no pads to read
+    pub fn start(config: &TriggerConfig, tx: broadcast::Sender<Command>) -> Result<Self, String> {
+        let mut reader = Self::none();
+        if config.pads.is_empty() {
+            return Ok(reader);
+        }
+        if output::is_dry_run() {
+            println!("Dry run: trigger pads aren't read");
+            return Ok(reader);
+        }
+        let mut addresses: HashMap<Mcp3008Address, usize> = HashMap::new();
+        let mut adcs = vec![];
+        let mut pads = vec![];
+        for pad in &config.pads {
+            let adc = match addresses.get(&pad.adc) {
+                Some(adc) => *adc,
+                None => {
+                    adcs.push(Mcp3008::new(pad.adc)?);
+                    addresses.insert(pad.adc, adcs.len() - 1);
+                    adcs.len() - 1
+                },
+            };
+            pads.push(Pad { config: pad.clone(), adc, baseline: None, state: PadState::Idle, last_hit: None });
+        }
+        reader.pads = pads.len();
+        let crosstalk = (Duration::from_secs_f64(config.crosstalk_window.max(0.0) / 1000.0), config.crosstalk_ratio as f32);
+        let stop = reader.stop.clone();
+        reader.thread = Some(std::thread::spawn(move || read_pads(adcs, pads, crosstalk, &stop, tx)));
+        println!("Reading {} trigger pads", reader.pads);
+        Ok(reader)
+    }
+
+    /// Get how many pads are being read
+    pub fn pads(&self) -> usize {
+        self.pads
+    }
+}
+
+impl Drop for TriggerReader {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Read every pad at a steady rate until told to stop, sending a Trigger command for each hit. A hit starts when a
+/// pad's reading jumps past its threshold, and is sent with the velocity of its peak once its scan time is up. A hit
+/// is taken as crosstalk, and ignored, if another pad's hit within the crosstalk window (finished, or still being
+/// scanned) peaked high enough above it
+fn read_pads(adcs: Vec<Mcp3008>, mut pads: Vec<Pad>, (crosstalk_window, crosstalk_ratio): (Duration, f32), stop: &AtomicBool, tx: broadcast::Sender<Command>) {
+    let period = Duration::from_secs(1) / TRIGGER_SAMPLE_RATE;
+    let mut next = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        for i in 0..pads.len() {
+            let pad = &mut pads[i];
+            let reading = match adcs[pad.adc].read(pad.config.channel) {
+                Ok(reading) => reading as f32 / MCP3008_MAX_READING as f32,
+                Err(e) => {
+                    eprintln!("Trigger pad {}: {}", pad.config.name, e);
+                    std::thread::sleep(READ_RETRY_INTERVAL);
+                    next = Instant::now();
+                    continue;
+                },
+            };
+            let now = Instant::now();
+            let baseline = pad.baseline.get_or_insert(reading);
+            let level = (reading - *baseline).abs();
+            let threshold = pad.config.threshold as f32;
+            let finished = match pad.state {
+                PadState::Idle if level >= threshold => {
+                    pad.state = PadState::Scanning { started: now, peak: level };
+                    None
+                },
+                PadState::Idle => {
+                    *baseline += (reading - *baseline) * BASELINE_SMOOTHING;
+                    None
+                },
+                PadState::Scanning { started, peak } => {
+                    let peak = peak.max(level);
+                    if now.duration_since(started).as_secs_f64() * 1000.0 >= pad.config.scan_time {
+                        let mask = Duration::from_secs_f64(pad.config.mask_time.max(0.0) / 1000.0);
+                        pad.state = PadState::Masked { until: started + mask };
+                        pad.last_hit = Some((started, peak));
+                        Some((started, peak))
+                    } else {
+                        pad.state = PadState::Scanning { started, peak };
+                        None
+                    }
+                },
+                PadState::Masked { until } => {
+                    if now >= until && level < threshold {
+                        pad.state = PadState::Idle;
+                    }
+                    None
+                },
+            };
+            let Some((started, peak)) = finished else { continue };
+            let within_window = |at: Instant| at.max(started) - at.min(started) <= crosstalk_window;
+            let crosstalk = pads.iter().enumerate().filter(|(j, _)| *j != i).any(|(_, other)| {
+                let other_peak = match other.state {
+                    PadState::Scanning { started, peak } => Some((started, peak)),
+                    _ => other.last_hit,
+                };
+                other_peak.is_some_and(|(at, other_peak)| within_window(at) && peak < other_peak * crosstalk_ratio)
+            });
+            if !crosstalk {
+                let pad = &pads[i].config;
+                // Nothing is listening if this fails, e.g. while shutting down
+                let _ = tx.send(Command::Trigger { note: pad.note, velocity: pad.velocity(peak as f64) });
+            }
+        }
+        // Keep to the sample rate overall, even if a read runs late
+        next += period;
+        match next.checked_duration_since(Instant::now()) {
+            Some(wait) => std::thread::sleep(wait),
+            None => next = Instant::now(),
+        }
+    }
+}
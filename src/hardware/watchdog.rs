@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hardware::output;
+
+/// Writing this to the Linux watchdog device before closing it disarms it ("magic close")
+const WATCHDOG_MAGIC_CLOSE: &[u8] = b"V";
+/// How often the hardware watchdog is fed while the control loop is healthy, well within the shortest timeout boards
+/// use (the Pi's is 15 seconds at most)
+const HARDWARE_WATCHDOG_FEED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Watchdog settings, loaded from the `watchdog` section of the configuration
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// Whether to run the watchdog at all
+    pub enabled: bool,
+    /// How often to check the outputs, in milliseconds
+    pub check_interval_ms: f64,
+    /// How far past its limit an output may stay high before it's forced low, in milliseconds
+    pub grace_ms: f64,
+    /// How long the control loop may go without checking in before every output is forced low, in milliseconds
+    pub stall_timeout_ms: f64,
+    /// Path of the Linux hardware watchdog device (e.g. /dev/watchdog) to feed once a second while the control loop
+    /// is healthy, so the board resets if the process hangs. Not used if unset
+    pub hardware_watchdog: Option<String>,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            check_interval_ms: 1.0,
+            grace_ms: 5.0,
+            stall_timeout_ms: 2000.0,
+            hardware_watchdog: None,
+        }
+    }
+}
+
+/// Runs on its own OS thread, independent of the async runtime, forcing low any output that's been held high for
+/// longer than it should (e.g. because the task ending a hit panicked or the control loop deadlocked)
+pub struct Watchdog {
+    /// Cleared to stop the watchdog thread
+    running: Arc<AtomicBool>,
+    /// Milliseconds since `started` at which the control loop last checked in
+    last_heartbeat_ms: Arc<AtomicU64>,
+    /// Reference point for heartbeat times
+    started: Instant,
+    /// Handle to the watchdog thread
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// Start the watchdog thread
+    pub fn start(config: WatchdogConfig) -> Result<Self, std::io::Error> {
+        let running = Arc::new(AtomicBool::new(true));
+        let last_heartbeat_ms = Arc::new(AtomicU64::new(0));
+        let started = Instant::now();
+        // Open the hardware watchdog up front so a missing device is reported at startup
+        let mut hardware_watchdog = config.hardware_watchdog.as_ref().map(File::create).transpose()?;
+
+        let handle = {
+            let running = running.clone();
+            let last_heartbeat_ms = last_heartbeat_ms.clone();
+            let check_interval = Duration::from_secs_f64(config.check_interval_ms / 1000.0);
+            let grace = Duration::from_secs_f64(config.grace_ms / 1000.0);
+            let stall_timeout_ms = config.stall_timeout_ms as u64;
+            std::thread::Builder::new().name("watchdog".to_string()).spawn(move || {
+                let mut stalled = false;
+                let mut last_fed: Option<Instant> = None;
+                // Set once feeding fails, so the failure is only reported once until it works again
+                let mut feed_failed = false;
+                while running.load(Ordering::Relaxed) {
+                    for pin in output::enforce_max_high_durations(grace) {
+                        eprintln!("Watchdog: pin {} was held high past its limit, forced it low", pin);
+                    }
+
+                    let since_heartbeat_ms = (started.elapsed().as_millis() as u64).saturating_sub(last_heartbeat_ms.load(Ordering::Relaxed));
+                    if since_heartbeat_ms > stall_timeout_ms {
+                        if !stalled {
+                            let forced = output::force_all_low();
+                            eprintln!("Watchdog: control loop stalled for {}ms, forced {} outputs low", since_heartbeat_ms, forced);
+                            stalled = true;
+                        }
+                    } else {
+                        stalled = false;
+                        // Only feed the hardware watchdog while the control loop is healthy, so a hang resets the board
+                        let feed_due = last_fed.map_or(true, |fed| fed.elapsed() >= HARDWARE_WATCHDOG_FEED_INTERVAL);
+                        if let Some(device) = hardware_watchdog.as_mut().filter(|_| feed_due) {
+                            last_fed = Some(Instant::now());
+                            match device.write_all(b"\0") {
+                                Ok(()) => feed_failed = false,
+                                Err(e) => {
+                                    if !feed_failed {
+                                        eprintln!("Watchdog: unable to feed hardware watchdog: {}", e);
+                                    }
+                                    feed_failed = true;
+                                },
+                            }
+                        }
+                    }
+                    std::thread::sleep(check_interval);
+                }
+                if let Some(mut device) = hardware_watchdog {
+                    let _ = device.write_all(WATCHDOG_MAGIC_CLOSE);
+                }
+            })?
+        };
+
+        let watchdog = Self { running, last_heartbeat_ms, started, handle: Some(handle) };
+        watchdog.heartbeat();
+        Ok(watchdog)
+    }
+
+    /// Let the watchdog know the control loop is still alive. Must be called more often than `stall_timeout_ms`
+    pub fn heartbeat(&self) {
+        self.last_heartbeat_ms.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Stop the watchdog thread, disarming the hardware watchdog
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
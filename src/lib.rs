@@ -3,3 +3,5 @@ pub mod comms;
 pub mod hardware;
 pub mod system;
 pub mod debug;
+pub mod playback;
+pub mod calibration;
@@ -1,15 +1,21 @@
-use std::error::Error;
-use auto_drum::autodrum::AutoDrum;
+use clap::Parser;
+use auto_drum::system::cli::Cli;
+use auto_drum::system::signals::signal_exit_code;
 
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let mut app = AutoDrum::new().await;
-
+async fn main() {
     // Note number reference here: https://computermusicresource.com/midikeys.html
 
-    app.run().await?;
-    app.stop();
-
-    Ok(())
+    // Print errors with Display rather than Debug so configuration problems come out as a readable report
+    let result = Cli::parse().execute().await;
+    if let Err(e) = &result {
+        eprintln!("{}", e);
+    }
+    // Shutting down because of a signal is reported with the signal's code, even when the shutdown went cleanly
+    match (signal_exit_code(), result) {
+        (Some(code), _) => std::process::exit(code),
+        (None, Err(_)) => std::process::exit(1),
+        (None, Ok(())) => {},
+    }
 }
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Most strokes an articulation may expand into
+pub const MAX_ARTICULATION_STROKES: u32 = 1000;
+/// Longest an articulation may take to play, in milliseconds
+pub const MAX_ARTICULATION_LENGTH_MS: f64 = 30_000.0;
+
+/// A pattern of strokes played on a single striker from one trigger, expanded on the device so the strokes are
+/// timed precisely instead of depending on how evenly notes arrive
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Articulation {
+    /// A quieter grace stroke just before the main stroke
+    Flam {
+        /// Time from the grace stroke to the main stroke in milliseconds
+        #[serde(default = "default_grace_offset")]
+        grace_offset: f64,
+        /// Velocity of the grace stroke as a fraction of the main stroke's
+        #[serde(default = "default_grace_velocity_ratio")]
+        grace_velocity_ratio: f64,
+    },
+    /// Two grace strokes, as close together as the striker allows, before the main stroke
+    Drag {
+        /// Time from the second grace stroke to the main stroke in milliseconds
+        #[serde(default = "default_grace_offset")]
+        grace_offset: f64,
+        /// Velocity of the grace strokes as a fraction of the main stroke's
+        #[serde(default = "default_grace_velocity_ratio")]
+        grace_velocity_ratio: f64,
+    },
+    /// Strokes as fast as the striker can recover from each, for a length of time in milliseconds
+    BuzzRoll { duration: f64 },
+    /// A number of evenly spaced strokes at a rate in strokes per second
+    Roll { strokes: u32, rate: f64 },
+}
+
+fn default_grace_offset() -> f64 {
+    30.0
+}
+
+fn default_grace_velocity_ratio() -> f64 {
+    0.5
+}
+
+/// A single stroke of an expanded articulation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stroke {
+    /// When the stroke is played, relative to the first
+    pub offset: Duration,
+    pub velocity: u8,
+}
+
+/// An articulation mapped to a striker in the configuration, so it can be triggered by a note, a control change or
+/// its name
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ArticulationData {
+    pub name: String,
+    /// Name of the striker that plays it
+    pub striker: String,
+    /// MIDI note that triggers it, at the note's velocity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<u8>,
+    /// MIDI controller number that triggers it, with the controller value as the velocity (0 is ignored)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cc: Option<u8>,
+    pub articulation: Articulation,
+}
+
+impl Articulation {
+    /// Get every problem with the articulation's settings, as the setting and what's wrong with it. Besides making
+    /// sense, the settings have to keep the articulation within MAX_ARTICULATION_STROKES and MAX_ARTICULATION_LENGTH_MS
+    pub fn problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = vec![];
+        match *self {
+            Articulation::Flam { grace_offset, grace_velocity_ratio } | Articulation::Drag { grace_offset, grace_velocity_ratio } => {
+                if !(0.0..=MAX_ARTICULATION_LENGTH_MS).contains(&grace_offset) {
+                    problems.push(("grace_offset", format!("must be between 0 and {}, got {}", MAX_ARTICULATION_LENGTH_MS, grace_offset)));
+                }
+                if !(grace_velocity_ratio > 0.0 && grace_velocity_ratio <= 1.0) {
+                    problems.push(("grace_velocity_ratio", format!("must be greater than 0 and at most 1, got {}", grace_velocity_ratio)));
+                }
+            },
+            Articulation::BuzzRoll { duration } => {
+                if !(duration > 0.0 && duration <= MAX_ARTICULATION_LENGTH_MS) {
+                    problems.push(("duration", format!("must be greater than 0 and at most {}, got {}", MAX_ARTICULATION_LENGTH_MS, duration)));
+                }
+            },
+            Articulation::Roll { strokes, rate } => {
+                if !(1..=MAX_ARTICULATION_STROKES).contains(&strokes) {
+                    problems.push(("strokes", format!("must be between 1 and {}, got {}", MAX_ARTICULATION_STROKES, strokes)));
+                }
+                if !(rate > 0.0 && rate.is_finite()) {
+                    problems.push(("rate", format!("must be greater than 0, got {}", rate)));
+                } else if strokes > 0 && (strokes - 1) as f64 / rate * 1000.0 > MAX_ARTICULATION_LENGTH_MS {
+                    problems.push(("rate", format!(
+                        "{} strokes at {} per second would take longer than {}ms", strokes, rate, MAX_ARTICULATION_LENGTH_MS,
+                    )));
+                }
+            },
+        }
+        problems
+    }
+
+    /// Check the articulation's settings before playing it (see `problems`)
+    pub fn check(&self) -> Result<(), String> {
+        let problems: Vec<String> = self.problems().into_iter().map(|(setting, problem)| format!("{} {}", setting, problem)).collect();
+        if problems.is_empty() { Ok(()) } else { Err(format!("Invalid articulation: {}", problems.join(", "))) }
+    }
+
+    /// Expand into the strokes for a trigger at the given velocity. `min_interval` gives the shortest time a striker
+    /// needs from the start of a stroke at a velocity to the start of the next (the hit plus its recovery time), and
+    /// strokes are pushed back as needed to keep to it
+    pub fn strokes(&self, velocity: u8, min_interval: impl Fn(u8) -> Duration) -> Vec<Stroke> {
+        let grace_velocity = |ratio: f64| (velocity as f64 * ratio).round().clamp(1.0, 127.0) as u8;
+        let millis = |ms: f64| Duration::from_micros((ms * 1000.0) as u64);
+        let wanted: Vec<(Duration, u8)> = match *self {
+            Articulation::Flam { grace_offset, grace_velocity_ratio } => {
+                vec![(Duration::ZERO, grace_velocity(grace_velocity_ratio)), (millis(grace_offset), velocity)]
+            },
+            Articulation::Drag { grace_offset, grace_velocity_ratio } => {
+                let grace = grace_velocity(grace_velocity_ratio);
+                // The second grace stroke comes as soon as the striker allows, and the main stroke is timed from it
+                let second_grace = min_interval(grace);
+                vec![(Duration::ZERO, grace), (second_grace, grace), (second_grace.saturating_add(millis(grace_offset)), velocity)]
+            },
+            Articulation::BuzzRoll { duration } => {
+                let spacing = min_interval(velocity).max(Duration::from_millis(1));
+                let strokes = (millis(duration).as_nanos() / spacing.as_nanos()).min(MAX_ARTICULATION_STROKES as u128 - 1) as u32 + 1;
+                (0..strokes).map(|i| (spacing.saturating_mul(i), velocity)).collect()
+            },
+            Articulation::Roll { strokes, rate } => {
+                let spacing = Duration::try_from_secs_f64(1.0 / rate).unwrap_or(Duration::MAX);
+                (0..strokes.min(MAX_ARTICULATION_STROKES)).map(|i| (spacing.saturating_mul(i), velocity)).collect()
+            },
+        };
+        let mut strokes: Vec<Stroke> = vec![];
+        for (offset, velocity) in wanted {
+            let earliest = strokes.last().map(|last| last.offset.saturating_add(min_interval(last.velocity))).unwrap_or_default();
+            strokes.push(Stroke { offset: offset.max(earliest), velocity });
+        }
+        strokes
+    }
+}
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+
+/// Lowest and highest the global velocity scale (the master dynamics knob) can be set to
+pub const VELOCITY_SCALE_RANGE: (f64, f64) = (0.0, 2.0);
+/// Lowest and highest a striker's velocity gain can be set to
+pub const VELOCITY_GAIN_RANGE: (f64, f64) = (0.0, 2.0);
+/// Lowest and highest a striker's velocity offset can be set to
+pub const VELOCITY_OFFSET_RANGE: (f64, f64) = (-64.0, 64.0);
+/// Lowest and highest a striker's velocity curve can be set to
+pub const VELOCITY_CURVE_RANGE: (f64, f64) = (0.2, 5.0);
+/// Lowest and highest the compression threshold and the ceiling can be set to
+pub const VELOCITY_RANGE: (f64, f64) = (1.0, 127.0);
+/// Lowest and highest the compression ratio can be set to
+pub const RATIO_RANGE: (f64, f64) = (1.0, 20.0);
+
+/// Compression and limiting applied to every note-on velocity, after each striker's gain and offset and the global
+/// velocity scale, so senders with different velocity ranges and drums that overpower the others can be evened out
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DynamicsConfig {
+    /// Velocities above this are compressed (127 leaves them alone)
+    pub threshold: f64,
+    /// How much velocities above the threshold are compressed, e.g. 2 makes them rise half as fast (1 leaves them
+    /// alone)
+    pub ratio: f64,
+    /// No velocity is played louder than this
+    pub ceiling: f64,
+}
+
+impl Default for DynamicsConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 127.0,
+            ratio: 1.0,
+            ceiling: 127.0,
+        }
+    }
+}
+
+impl DynamicsConfig {
+    /// Compress and limit a velocity that has already been scaled, giving the velocity to play (1-127)
+    pub fn apply(&self, velocity: f64) -> u8 {
+        let compressed = if velocity > self.threshold {
+            self.threshold + (velocity - self.threshold) / self.ratio.max(1.0)
+        } else {
+            velocity
+        };
+        compressed.min(self.ceiling).round().clamp(1.0, 127.0) as u8
+    }
+}
@@ -0,0 +1,244 @@
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+/// Lowest and highest the overall humanize amount can be set to
+pub const HUMANIZE_AMOUNT_RANGE: (f64, f64) = (0.0, 2.0);
+
+/// Humanization of note timing and velocity, so hits don't land with machine precision. It's applied to notes that
+/// are played later (MIDI file playback, rolls and articulations), and to live input only if `live_input` is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HumanizeConfig {
+    pub enabled: bool,
+    /// Scales every amount (0-2), so one fader can bring humanization in and out
+    pub amount: f64,
+    /// The amounts for every striker, which strikers can change with their own `humanize` settings
+    #[serde(flatten)]
+    pub amounts: HumanizeAmounts,
+    /// How long the drift takes to wander from one extreme to the other, in milliseconds
+    pub drift_period: f64,
+    /// Timing offset in milliseconds for each sixteenth note position, repeating (e.g. `[0, 15]` swings every other
+    /// sixteenth 15ms late). Only used where a note's position in the bar is known (MIDI file playback)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groove: Vec<f64>,
+    /// Also humanize notes from live input. Live notes can't be played early, so only the late half of the timing
+    /// changes applies to them
+    pub live_input: bool,
+    /// Seed for the random numbers, so a run can be repeated exactly (a different one each run if not set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+}
+
+impl Default for HumanizeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            amount: 1.0,
+            amounts: HumanizeAmounts::default(),
+            drift_period: 4000.0,
+            groove: vec![],
+            live_input: false,
+            seed: None,
+        }
+    }
+}
+
+/// How much timing and velocity vary, globally or for a single striker
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HumanizeAmounts {
+    /// Each note is moved at random by up to this many milliseconds either way
+    pub timing_jitter: f64,
+    /// Notes drift smoothly early and late over time, by up to this many milliseconds either way, like a player
+    /// pushing and pulling the beat
+    pub timing_drift: f64,
+    /// Each note's velocity is changed at random by up to this much either way
+    pub velocity_variation: f64,
+}
+
+/// Changes to the global humanize amounts for a single striker. Anything left out uses the global amount
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HumanizeOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing_jitter: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing_drift: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub velocity_variation: Option<f64>,
+}
+
+impl HumanizeAmounts {
+    /// Apply a single striker's overrides on top of the global amounts
+    pub fn with_overrides(self, overrides: Option<&HumanizeOverrides>) -> Self {
+        let Some(o) = overrides else { return self };
+        Self {
+            timing_jitter: o.timing_jitter.unwrap_or(self.timing_jitter),
+            timing_drift: o.timing_drift.unwrap_or(self.timing_drift),
+            velocity_variation: o.velocity_variation.unwrap_or(self.velocity_variation),
+        }
+    }
+}
+
+/// Generates the timing and velocity changes for humanized notes
+pub struct Humanizer {
+    config: HumanizeConfig,
+    rng: StdRng,
+    /// Seed of the drift's noise, so the drift is the same shape on every run with the same seed
+    noise_seed: u64,
+    /// When it was created, the zero of the drift's clock for notes that aren't timed from the start of a file
+    started: Instant,
+}
+
+impl Humanizer {
+    pub fn new(config: HumanizeConfig) -> Self {
+        let mut rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let noise_seed = rng.gen();
+        Self { config, rng, noise_seed, started: Instant::now() }
+    }
+
+    pub fn config(&self) -> &HumanizeConfig {
+        &self.config
+    }
+
+    /// Set the overall amount, which scales every other amount
+    pub fn amount_mut(&mut self) -> &mut f64 {
+        &mut self.config.amount
+    }
+
+    /// Get how long ago it was created in milliseconds, the time to humanize notes at that aren't timed from the
+    /// start of a file
+    pub fn elapsed_ms(&self) -> f64 {
+        self.started.elapsed().as_secs_f64() * 1000.0
+    }
+
+    /// Check whether notes from live input should be humanized
+    pub fn applies_to_live_input(&self) -> bool {
+        self.config.enabled && self.config.live_input
+    }
+
+    /// Humanize a note, given the amounts for its striker, when it's due (in milliseconds on any steady clock, for
+    /// the drift) and its sixteenth note position in the bar if known. Returns how many milliseconds to move the
+    /// note by (negative is earlier) and its new velocity
+    pub fn humanize(&mut self, amounts: HumanizeAmounts, time_ms: f64, position: Option<u32>, velocity: u8) -> (f64, u8) {
+        if !self.config.enabled {
+            return (0.0, velocity);
+        }
+        let amount = self.config.amount;
+        let jitter = amounts.timing_jitter * amount * self.triangular();
+        let drift = amounts.timing_drift * amount * perlin(self.noise_seed, time_ms / self.config.drift_period.max(1.0));
+        let groove = match (position, self.config.groove.len()) {
+            (Some(position), len) if len > 0 => self.config.groove[position as usize % len] * amount,
+            _ => 0.0,
+        };
+        let variation = amounts.velocity_variation * amount * self.triangular();
+        let velocity = (velocity as f64 + variation).round().clamp(1.0, 127.0) as u8;
+        (jitter + drift + groove, velocity)
+    }
+
+    /// Get a random number between -1 and 1, more likely near 0 than the ends (the difference of two uniform
+    /// numbers), which sounds more natural than an even spread
+    fn triangular(&mut self) -> f64 {
+        self.rng.gen::<f64>() - self.rng.gen::<f64>()
+    }
+}
+
+/// Move a time by a humanize offset in milliseconds, no earlier than zero (and no later than Duration can hold)
+pub fn shift(time: Duration, offset_ms: f64) -> Duration {
+    Duration::try_from_secs_f64((time.as_secs_f64() + offset_ms / 1000.0).max(0.0)).unwrap_or(Duration::MAX)
+}
+
+/// One dimensional Perlin noise: a smooth curve between about -1 and 1 that crosses 0 at every whole number, with a
+/// random slope there
+fn perlin(seed: u64, x: f64) -> f64 {
+    let cell = x.floor();
+    let t = x - cell;
+    let gradient = |i: f64| {
+        let hash = splitmix64(seed ^ (i as i64 as u64));
+        (hash >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    };
+    let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let from = gradient(cell) * t;
+    let to = gradient(cell + 1.0) * (t - 1.0);
+    // A single cell peaks at half of the gradient, so scale it back up towards -1 to 1
+    (from + (to - from) * fade) * 2.0
+}
+
+/// Mix the bits of a number, giving a well spread hash of it
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(seed: Option<u64>, groove: Vec<f64>) -> HumanizeConfig {
+        HumanizeConfig {
+            enabled: true,
+            amounts: HumanizeAmounts { timing_jitter: 10.0, timing_drift: 20.0, velocity_variation: 15.0 },
+            drift_period: 1000.0,
+            groove,
+            seed,
+            ..HumanizeConfig::default()
+        }
+    }
+
+    /// Humanize a bar of sixteenths at 120bpm
+    fn humanize_bar(humanizer: &mut Humanizer) -> Vec<(f64, u8)> {
+        let amounts = humanizer.config().amounts;
+        (0..16).map(|i| humanizer.humanize(amounts, i as f64 * 125.0, Some(i), 100)).collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_jitter_drift_and_groove() {
+        let first = humanize_bar(&mut Humanizer::new(config(Some(42), vec![0.0, 15.0])));
+        let second = humanize_bar(&mut Humanizer::new(config(Some(42), vec![0.0, 15.0])));
+        assert_eq!(first, second);
+        // Something actually moved
+        assert!(first.iter().any(|(offset, velocity)| *offset != 0.0 && *velocity != 100));
+    }
+
+    #[test]
+    fn different_seeds_give_different_offsets() {
+        let first = humanize_bar(&mut Humanizer::new(config(Some(1), vec![])));
+        let second = humanize_bar(&mut Humanizer::new(config(Some(2), vec![])));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn groove_repeats_by_sixteenth_position_and_scales_with_amount() {
+        let mut humanizer = Humanizer::new(HumanizeConfig {
+            enabled: true,
+            amount: 0.5,
+            groove: vec![0.0, 12.0, -4.0],
+            ..HumanizeConfig::default()
+        });
+        let amounts = HumanizeAmounts::default();
+        let offsets: Vec<f64> = (0..7).map(|position| humanizer.humanize(amounts, 0.0, Some(position), 100).0).collect();
+        assert_eq!(offsets, vec![0.0, 6.0, -2.0, 0.0, 6.0, -2.0, 0.0]);
+        // Without a position (live input, rolls) there's no groove
+        assert_eq!(humanizer.humanize(amounts, 0.0, None, 100), (0.0, 100));
+    }
+
+    #[test]
+    fn disabled_leaves_notes_alone() {
+        let mut humanizer = Humanizer::new(HumanizeConfig { enabled: false, ..config(Some(42), vec![10.0]) });
+        assert_eq!(humanizer.humanize(humanizer.config().amounts, 500.0, Some(1), 90), (0.0, 90));
+    }
+
+    #[test]
+    fn shift_stops_at_zero() {
+        assert_eq!(shift(Duration::from_millis(5), -10.0), Duration::ZERO);
+        assert_eq!(shift(Duration::from_millis(5), 2.5), Duration::from_micros(7500));
+    }
+}
@@ -0,0 +1,108 @@
+use std::error::Error;
+use std::time::Duration;
+
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+use crate::comms::remote_command::{MIDI_NOTE_OFF_BYTE, MIDI_NOTE_ON_BYTE};
+
+/// Tempo assumed by the MIDI spec until a file sets its own (120bpm)
+const DEFAULT_MICROS_PER_BEAT: u32 = 500_000;
+
+/// A note event read from a MIDI file, with its time relative to the start of the file
+#[derive(Debug, Clone, Copy)]
+pub struct TimedNote {
+    /// Time since the start of the file
+    pub time: Duration,
+    /// Status byte, note number and velocity, the same shape AutoDrum::handle_note takes
+    pub midi_data: (u8, u8, u8),
+    /// Which sixteenth note of the file it falls nearest to, counting from 0 at the start, if the file counts time in
+    /// beats
+    pub sixteenth: Option<u32>,
+}
+
+/// Read the note-on and note-off events of a Standard MIDI File, merged across tracks and sorted by time
+pub fn read_notes(path: &str) -> Result<Vec<TimedNote>, Box<dyn Error>> {
+    let bytes = std::fs::read(path)?;
+    let smf = Smf::parse(&bytes)?;
+
+    // Flatten every track into (absolute tick, event) pairs so tempo changes on one track apply to all
+    let mut events = vec![];
+    for track in smf.tracks.iter() {
+        let mut tick: u64 = 0;
+        for event in track.iter() {
+            tick += event.delta.as_int() as u64;
+            events.push((tick, event.kind));
+        }
+    }
+    // stable sort keeps the in-track order of simultaneous events
+    events.sort_by_key(|(tick, _)| *tick);
+
+    let mut notes = vec![];
+    let mut micros_per_beat = DEFAULT_MICROS_PER_BEAT;
+    let mut last_tick: u64 = 0;
+    let mut elapsed_micros: f64 = 0.0;
+    for (tick, kind) in events {
+        elapsed_micros += (tick - last_tick) as f64 * micros_per_tick(smf.header.timing, micros_per_beat);
+        last_tick = tick;
+        match kind {
+            TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => micros_per_beat = tempo.as_int(),
+            TrackEventKind::Midi { message, .. } => {
+                let midi_data = match message {
+                    // a note-on with velocity 0 is a note-off by convention
+                    MidiMessage::NoteOn { key, vel } if vel.as_int() == 0 => (MIDI_NOTE_OFF_BYTE, key.as_int(), 0),
+                    MidiMessage::NoteOn { key, vel } => (MIDI_NOTE_ON_BYTE, key.as_int(), vel.as_int()),
+                    MidiMessage::NoteOff { key, vel } => (MIDI_NOTE_OFF_BYTE, key.as_int(), vel.as_int()),
+                    _ => continue,
+                };
+                notes.push(TimedNote {
+                    time: Duration::from_micros(elapsed_micros as u64),
+                    midi_data,
+                    sixteenth: sixteenth(smf.header.timing, tick),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(notes)
+}
+
+/// Get the sixteenth note nearest to a tick, for files that count time in beats
+fn sixteenth(timing: Timing, tick: u64) -> Option<u32> {
+    match timing {
+        Timing::Metrical(ticks_per_beat) => {
+            let ticks_per_sixteenth = (ticks_per_beat.as_int() as f64 / 4.0).max(1.0);
+            Some((tick as f64 / ticks_per_sixteenth).round() as u32)
+        }
+        Timing::Timecode(..) => None,
+    }
+}
+
+/// Get the length of a tick in microseconds for the given timing and tempo
+fn micros_per_tick(timing: Timing, micros_per_beat: u32) -> f64 {
+    match timing {
+        Timing::Metrical(ticks_per_beat) => micros_per_beat as f64 / ticks_per_beat.as_int() as f64,
+        Timing::Timecode(fps, subframe) => 1_000_000.0 / (fps.as_f32() as f64 * subframe as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use midly::num::u15;
+
+    use super::*;
+
+    #[test]
+    fn sixteenth_rounds_to_the_nearest_position() {
+        let timing = Timing::Metrical(u15::new(480));
+        assert_eq!(sixteenth(timing, 0), Some(0));
+        assert_eq!(sixteenth(timing, 120), Some(1));
+        // A note pushed a little late still counts as on its position
+        assert_eq!(sixteenth(timing, 250), Some(2));
+        assert_eq!(sixteenth(timing, 1900), Some(16));
+    }
+
+    #[test]
+    fn sixteenth_is_unknown_for_timecode_files() {
+        assert_eq!(sixteenth(Timing::Timecode(midly::Fps::Fps25, 40), 1000), None);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod articulation;
+pub mod dynamics;
+pub mod humanize;
+pub mod midi_file;
+pub mod recorder;
+pub mod scheduler;
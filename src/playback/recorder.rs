@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+use crate::debug::logger::timestamp;
+
+/// Directory recordings are saved to when no path is given
+pub const DEFAULT_RECORDING_DIR: &str = "./recordings";
+/// Resolution of recordings. At 120bpm a tick is about half a millisecond
+const TICKS_PER_BEAT: u16 = 960;
+const MICROS_PER_BEAT: u32 = 500_000;
+/// Channel 10, where General MIDI puts drums
+const DRUM_CHANNEL: u8 = 9;
+/// Name of the first track, which only holds the tempo
+const CONDUCTOR_TRACK_NAME: &str = "AutoDrum";
+
+/// A hit as the striker played it
+#[derive(Debug, Clone, Copy)]
+struct RecordedHit {
+    /// Time since recording started that the striker fired
+    start: Duration,
+    /// How long the striker was to be held on for
+    duration: Duration,
+    velocity: u8,
+}
+
+/// A modifier being switched on or off
+#[derive(Debug, Clone, Copy)]
+struct RecordedModifierChange {
+    /// Time since recording started
+    at: Duration,
+    note: u8,
+    active: bool,
+}
+
+/// Everything recorded for one striker
+#[derive(Debug, Clone, Default)]
+struct RecordedTrack {
+    hits: Vec<RecordedHit>,
+    modifier_changes: Vec<RecordedModifierChange>,
+}
+
+/// Records what the strikers actually played (after modifier mapping, humanizing and velocity processing, at the
+/// time each striker fired) and every modifier change, to save as a type 1 Standard MIDI File with a track per striker
+pub struct Recorder {
+    path: PathBuf,
+    started: Instant,
+    /// Striker note numbers and names to what they played
+    tracks: BTreeMap<(u8, String), RecordedTrack>,
+    hits: usize,
+}
+
+impl Recorder {
+    /// Start recording, to save to the given path, or to a file named after the time in DEFAULT_RECORDING_DIR
+    pub fn start(path: Option<&str>) -> Self {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => Path::new(DEFAULT_RECORDING_DIR).join(format!("recording_{}.mid", timestamp())),
+        };
+        Self { path, started: Instant::now(), tracks: BTreeMap::new(), hits: 0 }
+    }
+
+    /// Get the path the recording will be saved to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get how many hits have been recorded
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Get how long it's been recording
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Record a hit on a striker that fired at the given time, at the given velocity, for the given duration
+    pub fn record_hit(&mut self, striker: &str, striker_note: u8, velocity: u8, fired: Instant, duration: Duration) {
+        let start = fired.saturating_duration_since(self.started);
+        self.track(striker, striker_note).hits.push(RecordedHit { start, duration, velocity });
+        self.hits += 1;
+    }
+
+    /// Record a striker's modifier being switched on or off at the given time
+    pub fn record_modifier(&mut self, striker: &str, striker_note: u8, modifier_note: u8, active: bool, at: Instant) {
+        let at = at.saturating_duration_since(self.started);
+        self.track(striker, striker_note).modifier_changes.push(RecordedModifierChange { at, note: modifier_note, active });
+    }
+
+    fn track(&mut self, striker: &str, striker_note: u8) -> &mut RecordedTrack {
+        self.tracks.entry((striker_note, striker.to_string())).or_default()
+    }
+
+    /// Save the recording as a type 1 Standard MIDI File: a tempo track, then a track per striker (in note order)
+    /// named after it, with its hits as notes and its modifiers as notes of their own held for as long as they were
+    /// on. A hit is cut short if the striker fired again before it ended, and modifiers still on are switched off at
+    /// the end
+    pub fn save(&self) -> Result<(), String> {
+        let end = self.elapsed();
+        let names: Vec<String> = self.tracks.keys().map(|(_, name)| name.clone()).collect();
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(u15::new(TICKS_PER_BEAT))));
+        smf.tracks.push(to_track_events(CONDUCTOR_TRACK_NAME.as_bytes(), vec![(0, TrackEventKind::Meta(MetaMessage::Tempo(u24::new(MICROS_PER_BEAT))))]));
+        for ((&(note, _), track), name) in self.tracks.iter().zip(&names) {
+            let mut events = vec![];
+            let mut hits = track.hits.clone();
+            hits.sort_by_key(|hit| hit.start);
+            let next_starts = hits.iter().skip(1).map(|hit| hit.start).map(Some).chain([None]);
+            for (hit, next_start) in hits.iter().zip(next_starts) {
+                let hit_end = next_start.map_or(hit.start + hit.duration, |next_start| (hit.start + hit.duration).min(next_start));
+                events.push((to_ticks(hit.start), note_event(note, Some(hit.velocity))));
+                events.push((to_ticks(hit_end), note_event(note, None)));
+            }
+            let mut active: Vec<u8> = vec![];
+            for change in &track.modifier_changes {
+                let is_active = active.contains(&change.note);
+                if change.active && !is_active {
+                    active.push(change.note);
+                    events.push((to_ticks(change.at), note_event(change.note, Some(127))));
+                } else if !change.active && is_active {
+                    active.retain(|note| *note != change.note);
+                    events.push((to_ticks(change.at), note_event(change.note, None)));
+                }
+            }
+            events.extend(active.into_iter().map(|note| (to_ticks(end), note_event(note, None))));
+            // Note-offs first, so a note ending as another starts doesn't cut the new one off
+            events.sort_by_key(|(tick, kind)| (*tick, !matches!(kind, TrackEventKind::Midi { message: MidiMessage::NoteOff { .. }, .. })));
+            smf.tracks.push(to_track_events(name.as_bytes(), events));
+        }
+        if let Some(directory) = self.path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            std::fs::create_dir_all(directory).map_err(|e| format!("Unable to create {}: {}", directory.display(), e))?;
+        }
+        smf.save(&self.path).map_err(|e| format!("Unable to save the recording to {}: {}", self.path.display(), e))
+    }
+}
+
+/// Convert a time since recording started to ticks
+fn to_ticks(time: Duration) -> u64 {
+    time.as_micros() as u64 * TICKS_PER_BEAT as u64 / MICROS_PER_BEAT as u64
+}
+
+/// Get a note-on at the given velocity on the drum channel, or a note-off if there's no velocity
+fn note_event(note: u8, velocity: Option<u8>) -> TrackEventKind<'static> {
+    let key = u7::new(note.min(127));
+    let message = match velocity {
+        Some(velocity) => MidiMessage::NoteOn { key, vel: u7::new(velocity.clamp(1, 127)) },
+        None => MidiMessage::NoteOff { key, vel: u7::new(0) },
+    };
+    TrackEventKind::Midi { channel: u4::new(DRUM_CHANNEL), message }
+}
+
+/// Build a named track from events at absolute ticks (sorted), ending it after the last
+fn to_track_events<'a>(name: &'a [u8], events: Vec<(u64, TrackEventKind<'a>)>) -> Vec<TrackEvent<'a>> {
+    let mut track = vec![TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::TrackName(name)) }];
+    let mut last_tick = 0;
+    for (tick, kind) in events {
+        let delta = tick.saturating_sub(last_tick).min(u28::max_value().as_int() as u64) as u32;
+        track.push(TrackEvent { delta: u28::new(delta), kind });
+        last_tick = tick.max(last_tick);
+    }
+    track.push(TrackEvent { delta: u28::new(0), kind: TrackEventKind::Meta(MetaMessage::EndOfTrack) });
+    track
+}
@@ -0,0 +1,97 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use tokio::time::{Duration, Instant};
+
+/// Lowest and highest the sequencer tempo can be set to, as a multiple of the tempo MIDI files are written at
+pub const TEMPO_SCALE_RANGE: (f64, f64) = (0.25, 4.0);
+
+/// A note event waiting to be played at a given time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ScheduledNote {
+    /// When the note should be played
+    pub at: Instant,
+    /// Order the note was scheduled in, so notes scheduled for the same instant play in order
+    sequence: u64,
+    /// Whether the note is part of a sequence (a MIDI file), so it follows tempo changes
+    sequenced: bool,
+    /// Status byte, note number and velocity
+    pub midi_data: (u8, u8, u8),
+}
+
+/// A queue of notes to be played in the future (rolls, MIDI file playback, etc.), polled by the AutoDrum run loop
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<ScheduledNote>>,
+    next_sequence: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a note to be played at the given time
+    pub fn schedule(&mut self, at: Instant, midi_data: (u8, u8, u8)) {
+        self.push(at, false, midi_data);
+    }
+
+    /// Queue a note of a sequence (a MIDI file) to be played at the given time. Unlike rolls and articulations, whose
+    /// strokes are spaced for the striker to recover, sequenced notes are moved by tempo changes
+    pub fn schedule_sequenced(&mut self, at: Instant, midi_data: (u8, u8, u8)) {
+        self.push(at, true, midi_data);
+    }
+
+    fn push(&mut self, at: Instant, sequenced: bool, midi_data: (u8, u8, u8)) {
+        self.queue.push(Reverse(ScheduledNote { at, sequence: self.next_sequence, sequenced, midi_data }));
+        self.next_sequence += 1;
+    }
+
+    /// Stretch the time from now until every sequenced note by a factor (e.g. 0.5 to play what's left twice as fast)
+    pub fn retime(&mut self, factor: f64, now: Instant) {
+        let notes = std::mem::take(&mut self.queue).into_vec();
+        self.queue = notes.into_iter()
+            .map(|Reverse(mut note)| {
+                if note.sequenced && note.at > now {
+                    note.at = now + (note.at - now).mul_f64(factor);
+                }
+                Reverse(note)
+            })
+            .collect();
+    }
+
+    /// Queue a note to be played after the given delay
+    pub fn schedule_in(&mut self, delay: Duration, midi_data: (u8, u8, u8)) {
+        self.schedule(Instant::now() + delay, midi_data);
+    }
+
+    /// Get the time of the next note due, if any are waiting
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.queue.peek().map(|Reverse(note)| note.at)
+    }
+
+    /// Remove and return every note that is due at the given time, earliest first
+    pub fn pop_due(&mut self, now: Instant) -> Vec<ScheduledNote> {
+        let mut due = vec![];
+        while self.next_deadline().is_some_and(|at| at <= now) {
+            due.push(self.queue.pop().unwrap().0);
+        }
+        due
+    }
+
+    /// Drop every waiting note, returning how many were cancelled
+    pub fn clear(&mut self) -> usize {
+        let cancelled = self.queue.len();
+        self.queue.clear();
+        cancelled
+    }
+
+    /// Get the number of notes waiting to be played
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
@@ -0,0 +1,319 @@
+use std::error::Error;
+use std::path::Path;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::autodrum::{AutoDrum, AutoDrumOptions, Transport};
+use crate::calibration::analysis::AnalysisOptions;
+use crate::calibration::calibrate::{calibrate, CalibrateOptions, SweepSource};
+use crate::calibration::input::SensorInput;
+use crate::calibration::plan::SweepOptions;
+use crate::debug::analysis::{analyze_logs, ReportFormat};
+use crate::debug::logger::LoggingConfig;
+use crate::hardware::adc::Mcp3008Address;
+use crate::hardware::striker::StrikerData;
+use crate::system::configuration::{Configuration, DEFAULT_CONFIGURATION_PATH};
+use crate::system::persistence::{find_backup, list_backups, restore_backup, PersistenceConfig};
+use crate::system::system_constants::{set_system_constants, SystemConstants, DEFAULT_SYSTEM_CONSTANTS_PATH};
+
+/// Drive a robotic drum kit from BLE MIDI
+#[derive(Debug, Parser)]
+#[command(name = "auto_drum", version)]
+pub struct Cli {
+    /// Path of the configuration file
+    #[arg(long, global = true, default_value = DEFAULT_CONFIGURATION_PATH)]
+    pub config: String,
+    /// Path of the system constants file
+    #[arg(long, global = true, default_value = DEFAULT_SYSTEM_CONSTANTS_PATH)]
+    pub constants: String,
+    /// Name of the kit to use (defaults to the configuration's default kit)
+    #[arg(long, global = true)]
+    pub kit: Option<String>,
+    /// What to do (defaults to `run`)
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Run the drum, receiving MIDI and commands over the selected transport
+    Run(RunArgs),
+    /// Check the configuration and system constants files, reporting any problems
+    ValidateConfig,
+    /// Print the strikers of a kit
+    ListStrikers,
+    /// Fire a single striker then exit
+    Fire {
+        /// Name of the striker to fire (case-insensitive)
+        name: String,
+        /// MIDI velocity of the hit
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=127))]
+        velocity: u8,
+        /// Simulate outputs instead of driving GPIO pins
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Play a Standard MIDI File on the strikers then exit
+    Play {
+        /// Path of the .mid file to play
+        file: String,
+        /// Simulate outputs instead of driving GPIO pins
+        #[arg(long)]
+        dry_run: bool,
+        /// Log every hit, not just the events the configuration's logging section logs
+        #[arg(long)]
+        debug: bool,
+        /// Directory that logs are written to, instead of the one in the configuration
+        #[arg(long)]
+        log_dir: Option<String>,
+        /// Record the hits as played (after humanizing and velocity processing) to this MIDI file
+        #[arg(long)]
+        record: Option<String>,
+        /// How MIDI is received while playing, e.g. from a controller mapped to the Tempo
+        #[arg(long, value_enum, default_value_t = Transport::None)]
+        transport: Transport,
+    },
+    /// Find a striker's hit durations and velocity curve by sweeping its hit length while listening to the drum
+    Calibrate(CalibrateArgs),
+    /// Restore the configuration file from one of its backups
+    Rollback {
+        /// How many versions back to go (1 is the most recent backup)
+        #[arg(default_value_t = 1)]
+        steps: usize,
+        /// List the backups instead of restoring one
+        #[arg(long)]
+        list: bool,
+    },
+    /// Report timing statistics per striker from hit logs (durations are only measured when running with --debug)
+    AnalyzeLog(AnalyzeLogArgs),
+    /// Print the configuration with every default filled in, as YAML
+    ExportConfig {
+        /// Write to this file instead of stdout
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// Directory that logs are written to, instead of the one in the configuration
+    #[arg(long)]
+    pub log_dir: Option<String>,
+    /// How remote commands are received
+    #[arg(long, value_enum, default_value_t = Transport::Ble)]
+    pub transport: Transport,
+    /// Simulate outputs instead of driving GPIO pins
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Log every hit, not just the events the configuration's logging section logs
+    #[arg(long)]
+    pub debug: bool,
+    /// Record every hit played and modifier change to this MIDI file, saved on shutdown
+    #[arg(long)]
+    pub record: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct AnalyzeLogArgs {
+    /// Log files to analyse, or directories to analyse every log in (defaults to the configured log directory)
+    pub paths: Vec<String>,
+    /// How to write the analysis
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+    /// Length of the window peak duty cycle is measured over, in seconds
+    #[arg(long, default_value_t = 10.0)]
+    pub window: f64,
+    /// Write to this file instead of stdout
+    #[arg(long, short)]
+    pub output: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CalibrateArgs {
+    /// Name of the striker to calibrate (case-insensitive)
+    pub striker: String,
+    /// ALSA capture device of the microphone to listen with
+    #[arg(long, default_value = "default")]
+    pub audio_device: String,
+    /// Listen with a piezo on this MCP3008 channel (0-7) instead of a microphone
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=7))]
+    pub piezo: Option<u8>,
+    /// SPI bus of the MCP3008
+    #[arg(long, default_value_t = 0)]
+    pub spi_bus: u8,
+    /// SPI chip select of the MCP3008
+    #[arg(long, default_value_t = 0)]
+    pub chip_select: u8,
+    /// Analyse a recording of an earlier sweep instead of firing the striker
+    #[arg(long, conflicts_with_all = ["piezo", "record", "dry_run"])]
+    pub wav: Option<String>,
+    /// Save the recording of the sweep as a WAV file, with its sweep plan alongside, to analyse again with --wav
+    #[arg(long)]
+    pub record: Option<String>,
+    /// How many hit lengths to try, from the shortest to the longest the striker's kind allows
+    #[arg(long, default_value_t = 16)]
+    pub steps: usize,
+    /// How many times to hit at each length
+    #[arg(long, default_value_t = 3)]
+    pub repeats: usize,
+    /// Milliseconds from the start of one hit to the start of the next, long enough for the drum to ring out
+    #[arg(long, default_value_t = 600.0)]
+    pub interval: f64,
+    /// How far above the noise floor a hit has to be to count as sounding, in dB
+    #[arg(long, default_value_t = 10.0)]
+    pub threshold_db: f64,
+    /// How close to the loudest a hit has to be to count as saturated, in dB
+    #[arg(long, default_value_t = 1.0)]
+    pub saturation_db: f64,
+    /// Print the results without saving them to the configuration
+    #[arg(long)]
+    pub no_save: bool,
+    /// Simulate outputs instead of driving GPIO pins
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Default for RunArgs {
+    fn default() -> Self {
+        Self {
+            log_dir: None,
+            transport: Transport::Ble,
+            dry_run: false,
+            debug: false,
+            record: None,
+        }
+    }
+}
+
+impl Cli {
+    /// Carry out the parsed command
+    pub async fn execute(self) -> Result<(), Box<dyn Error>> {
+        let options = AutoDrumOptions {
+            config_path: self.config.clone(),
+            constants_path: self.constants.clone(),
+            kit: self.kit.clone(),
+            ..Default::default()
+        };
+        match self.command.unwrap_or(CliCommand::Run(RunArgs::default())) {
+            CliCommand::Run(args) => {
+                let mut app = AutoDrum::new(AutoDrumOptions {
+                    log_dir: args.log_dir,
+                    transport: args.transport,
+                    dry_run: args.dry_run,
+                    debug: args.debug,
+                    record: args.record,
+                    ..options
+                }).await?;
+                app.run().await?;
+            },
+            CliCommand::ValidateConfig => {
+                let constants = SystemConstants::load(&options.constants_path)?;
+                Configuration::load(&options.config_path, &constants)?;
+                println!("{} and {} are valid", options.constants_path, options.config_path);
+            },
+            CliCommand::ListStrikers => {
+                let constants = SystemConstants::load(&options.constants_path)?;
+                let config = Configuration::load(&options.config_path, &constants)?;
+                set_system_constants(constants);
+                let kit = config.get_kit(options.kit.as_deref())?;
+                let mut strikers: Vec<StrikerData> = kit
+                    .strikers.into_iter().map(StrikerData::with_defaults).collect::<Result<_, _>>()?;
+                strikers.sort_by_key(|striker| striker.note);
+                println!("Kit: {}", kit.name);
+                println!("{:<16} {:>4}  {:<16} {:<14} {:>10} {:>10}", "NAME", "NOTE", "PIN", "KIND", "MIN (ms)", "MAX (ms)");
+                for striker in strikers {
+                    println!(
+                        "{:<16} {:>4}  {:<16} {:<14} {:>10} {:>10}",
+                        striker.name, striker.note, striker.pin.to_string(), striker.kind.to_string(),
+                        striker.min_hit_duration.unwrap(), striker.max_hit_duration.unwrap(),
+                    );
+                }
+            },
+            CliCommand::Fire { name, velocity, dry_run } => {
+                let mut app = AutoDrum::new(AutoDrumOptions { transport: Transport::None, dry_run, ..options }).await?;
+                app.fire(&name, velocity).await?;
+                // Articulations schedule the rest of their strokes
+                app.play_scheduled().await?;
+                app.wait_for_pulses(Duration::from_secs(1)).await;
+                app.shutdown().await?;
+            },
+            CliCommand::Play { file, dry_run, debug, log_dir, record, transport } => {
+                let mut app = AutoDrum::new(AutoDrumOptions { transport, dry_run, debug, log_dir, record, ..options }).await?;
+                app.play(&file).await?;
+            },
+            CliCommand::Calibrate(args) => {
+                let source = match args.wav {
+                    Some(path) => SweepSource::Wav(path),
+                    None => {
+                        let input = match args.piezo {
+                            Some(channel) => SensorInput::Piezo {
+                                adc: Mcp3008Address { bus: args.spi_bus, chip_select: args.chip_select },
+                                channel,
+                            },
+                            None => SensorInput::Audio { device: args.audio_device },
+                        };
+                        SweepSource::Live { input, dry_run: args.dry_run, record: args.record }
+                    },
+                };
+                calibrate(&options.config_path, &options.constants_path, options.kit.as_deref(), CalibrateOptions {
+                    striker: args.striker,
+                    source,
+                    sweep: SweepOptions { steps: args.steps, repeats: args.repeats, interval: args.interval },
+                    analysis: AnalysisOptions { threshold_db: args.threshold_db, saturation_db: args.saturation_db },
+                    save: !args.no_save,
+                }).await?;
+            },
+            CliCommand::Rollback { steps, list } => {
+                let constants = SystemConstants::load(&options.constants_path)?;
+                let path = Path::new(&options.config_path);
+                // The backup settings come from the current file, if it can still be loaded
+                let persistence = Configuration::load(&options.config_path, &constants)
+                    .map(|config| config.persistence)
+                    .unwrap_or_else(|_| PersistenceConfig::default());
+                if list {
+                    for (i, backup_path) in list_backups(path, &persistence)?.iter().enumerate() {
+                        println!("{:>3}  {}", i + 1, backup_path.display());
+                    }
+                    return Ok(());
+                }
+                let backup_path = find_backup(path, steps, &persistence)?;
+                Configuration::load(&backup_path.to_string_lossy(), &constants)?;
+                restore_backup(path, &backup_path, &persistence)?;
+                println!("Restored {} from {}", options.config_path, backup_path.display());
+            },
+            CliCommand::AnalyzeLog(args) => {
+                let paths = if args.paths.is_empty() {
+                    // The log directory comes from the current configuration, if it can be loaded
+                    let logging = SystemConstants::load(&options.constants_path)
+                        .and_then(|constants| Configuration::load(&options.config_path, &constants))
+                        .map(|config| config.logging)
+                        .unwrap_or_else(|_| LoggingConfig::default());
+                    vec![logging.directory]
+                } else {
+                    args.paths
+                };
+                let report = analyze_logs(&paths, args.window)?.format(args.format)?;
+                match args.output {
+                    Some(path) => std::fs::write(path, report)?,
+                    None => print!("{}", report),
+                }
+            },
+            CliCommand::ExportConfig { output } => {
+                let constants = SystemConstants::load(&options.constants_path)?;
+                let mut config = Configuration::load(&options.config_path, &constants)?;
+                set_system_constants(constants);
+                config.strikers = config.strikers.into_iter().map(StrikerData::with_defaults).collect::<Result<_, _>>()?;
+                for kit in config.kits.iter_mut() {
+                    kit.strikers = std::mem::take(&mut kit.strikers).into_iter().map(StrikerData::with_defaults).collect::<Result<_, _>>()?;
+                }
+                let yaml = serde_yaml::to_string(&config)?;
+                match output {
+                    Some(path) => std::fs::write(path, yaml)?,
+                    None => print!("{}", yaml),
+                }
+            },
+        }
+        Ok(())
+    }
+}
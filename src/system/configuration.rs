@@ -1,20 +1,155 @@
-use config::Config;
+use std::path::Path;
+
 use serde::{Deserialize, Serialize};
+use crate::comms::cc_mapping::CcMapping;
+use crate::comms::midi_ble::BleConfig;
+use crate::debug::logger::LoggingConfig;
+use crate::hardware::modifier::ModifierData;
 use crate::hardware::striker::StrikerData;
+use crate::hardware::trigger::TriggerConfig;
+use crate::hardware::watchdog::WatchdogConfig;
+use crate::playback::articulation::ArticulationData;
+use crate::playback::dynamics::DynamicsConfig;
+use crate::playback::humanize::HumanizeConfig;
+use crate::system::file_watcher::HotReloadConfig;
+use crate::system::migration::{migrate_text, write_migrated, Schema, CONFIGURATION_VERSION};
+use crate::system::persistence::PersistenceConfig;
+use crate::system::system_constants::SystemConstants;
+use crate::system::validation::{parse_configuration, ValidationReport};
+
+/// A named set of strikers and modifiers for one physical setup
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KitData {
+    pub name: String,
+    #[serde(default)]
+    pub strikers: Vec<StrikerData>,
+    #[serde(default)]
+    pub modifiers: Vec<ModifierData>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub articulations: Vec<ArticulationData>,
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Configuration {
-    pub strikers: Vec<StrikerData>
+    /// Schema version of the file, which older files are migrated up to when loaded
+    #[serde(default = "current_version")]
+    pub version: u32,
+    /// Strikers of the only kit, when no named kits are defined
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strikers: Vec<StrikerData>,
+    /// Modifiers of the only kit, when no named kits are defined
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modifiers: Vec<ModifierData>,
+    /// Articulations of the only kit, when no named kits are defined
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub articulations: Vec<ArticulationData>,
+    /// Named kits that can be switched between at runtime
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub kits: Vec<KitData>,
+    /// Name of the kit loaded at startup (the first kit if not given)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_kit: Option<String>,
+    /// Name of the kit currently loaded. Only filled in when reporting the configuration, and ignored when loading
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_kit: Option<String>,
+    /// Every note-on velocity is multiplied by this (0-2) before it's played, the master dynamics knob
+    #[serde(default = "default_velocity_scale")]
+    pub velocity_scale: f64,
+    /// Compression and limiting applied to every note-on velocity after scaling
+    #[serde(default)]
+    pub dynamics: DynamicsConfig,
+    /// MIDI files are played at this multiple (0.25-4) of the tempo they're written at, the sequencer tempo
+    #[serde(default = "default_tempo_scale")]
+    pub tempo_scale: f64,
+    /// Humanization of note timing and velocity
+    #[serde(default)]
+    pub humanize: HumanizeConfig,
+    /// MIDI controllers mapped to live parameters, in every kit
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cc_mappings: Vec<CcMapping>,
+    /// Piezo trigger pads played as notes, in every kit
+    #[serde(default)]
+    pub triggers: TriggerConfig,
+    #[serde(default)]
+    pub ble: BleConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub hot_reload: HotReloadConfig,
+    #[serde(default)]
+    pub persistence: PersistenceConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+}
+
+pub const DEFAULT_CONFIGURATION_PATH: &str = "configuration.yaml";
+
+fn current_version() -> u32 {
+    CONFIGURATION_VERSION
+}
+
+fn default_velocity_scale() -> f64 {
+    1.0
 }
 
+fn default_tempo_scale() -> f64 {
+    1.0
+}
+/// Name of the kit made up of the top-level strikers and modifiers, in a configuration without named kits
+pub const DEFAULT_KIT_NAME: &str = "default";
+
 impl Configuration {
-    pub fn load() -> Self {
-        // load system-constants.yaml and parse it into a Configuration struct
-        Config::builder()
-            .add_source(config::File::with_name("configuration.yaml").required(true))
-            .build()
-            .unwrap()
-            .try_deserialize()
-            .unwrap()
+    /// Load a configuration file and parse it into a Configuration struct, reporting every problem with it. A file
+    /// from an older schema version is migrated, and saved back (after backing it up) if the result is valid
+    pub fn load(path: &str, constants: &SystemConstants) -> Result<Self, ValidationReport> {
+        let text = std::fs::read_to_string(path).map_err(|e| ValidationReport::with_issue(path, "", format!("unable to read file: {}", e)))?;
+        let migrated = migrate_text(&text, Schema::Configuration).map_err(|e| ValidationReport::with_issue(path, "", e))?;
+        let config = parse_configuration(path, &migrated.text, constants)?;
+        if let Some(from_version) = migrated.from_version {
+            // Still usable if it can't be saved (e.g. a read-only filesystem), it'll just be migrated again next time
+            match write_migrated(Path::new(path), &migrated, &config.persistence) {
+                Ok(backup_path) => println!(
+                    "Migrated {} from schema version {} to {} (previous version backed up to {})",
+                    path, from_version, CONFIGURATION_VERSION, backup_path.unwrap_or_default().display(),
+                ),
+                Err(e) => eprintln!("Migrated {} from schema version {} but unable to save it: {}", path, from_version, e),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Get every kit in the configuration. Without named kits, the top-level strikers, modifiers and articulations
+    /// make up a single kit called "default"
+    pub fn all_kits(&self) -> Vec<KitData> {
+        if self.kits.is_empty() {
+            vec![KitData {
+                name: DEFAULT_KIT_NAME.to_string(),
+                strikers: self.strikers.clone(),
+                modifiers: self.modifiers.clone(),
+                articulations: self.articulations.clone(),
+            }]
+        } else {
+            self.kits.clone()
+        }
+    }
+
+    /// Get the strikers of the kit with the given name (case-insensitive), to change them in place
+    pub fn kit_strikers_mut(&mut self, name: &str) -> Option<&mut Vec<StrikerData>> {
+        if self.kits.is_empty() {
+            return name.eq_ignore_ascii_case(DEFAULT_KIT_NAME).then_some(&mut self.strikers);
+        }
+        self.kits.iter_mut().find(|kit| kit.name.eq_ignore_ascii_case(name)).map(|kit| &mut kit.strikers)
+    }
+
+    /// Get the kit with the given name (case-insensitive), or the default kit if no name is given
+    pub fn get_kit(&self, name: Option<&str>) -> Result<KitData, String> {
+        let kits = self.all_kits();
+        let name = name.map(str::to_string).or_else(|| self.default_kit.clone());
+        match name {
+            Some(name) => kits.into_iter()
+                .find(|kit| kit.name.eq_ignore_ascii_case(&name))
+                .ok_or(format!("No kit with name {} exists", name)),
+            None => Ok(kits.into_iter().next().unwrap()),
+        }
     }
 }
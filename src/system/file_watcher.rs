@@ -0,0 +1,67 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+use crate::comms::remote_command::Command;
+use crate::system::persistence::is_own_write;
+
+/// Hot-reload settings, loaded from the `hot_reload` section of the configuration
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HotReloadConfig {
+    /// Whether to reload the configuration file when it changes
+    pub enabled: bool,
+    /// Whether to also reload when the system constants file changes
+    pub watch_system_constants: bool,
+    /// How often to check the files for changes, in milliseconds
+    pub poll_interval_ms: u64,
+}
+
+impl Default for HotReloadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            watch_system_constants: false,
+            poll_interval_ms: 500,
+        }
+    }
+}
+
+/// Start a task that polls the given files' modification times, sending a ReloadConfiguration command once a change
+/// has settled (so a half-written file isn't picked up while an editor is still saving it). Files this process wrote
+/// itself (saving, rolling back, migrating) have already been applied, so changes that are only those don't reload
+pub fn spawn_file_watcher(paths: Vec<String>, poll_interval: Duration, tx: broadcast::Sender<Command>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut last_seen: Vec<Option<SystemTime>> = paths.iter().map(|path| modified_time(path)).collect();
+        // Modification times as of the last reload (or start), to find which files a settled change touched
+        let mut applied = last_seen.clone();
+        let mut pending = false;
+        let mut interval = tokio::time::interval(poll_interval);
+        loop {
+            interval.tick().await;
+            let current: Vec<Option<SystemTime>> = paths.iter().map(|path| modified_time(path)).collect();
+            if current != last_seen {
+                // Changed since the last check, wait for it to settle
+                last_seen = current;
+                pending = true;
+            } else if pending {
+                pending = false;
+                let edited = paths.iter().zip(&current).zip(&applied).any(|((path, current), applied)| {
+                    current != applied && !current.is_some_and(|modified| is_own_write(Path::new(path), modified))
+                });
+                applied = current;
+                if edited && tx.send(Command::ReloadConfiguration).is_err() {
+                    break;
+                }
+            }
+        }
+    })
+}
+
+/// Get the modification time of a file, or None if it can't be read (e.g. mid-save)
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,202 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use crate::system::persistence::{backup_file, write_atomic, PersistenceConfig};
+
+/// Current schema version of the configuration file
+pub const CONFIGURATION_VERSION: u32 = 2;
+/// Current schema version of the system constants file
+pub const SYSTEM_CONSTANTS_VERSION: u32 = 3;
+/// Version of a file written before versions were added
+const UNVERSIONED: u32 = 1;
+
+/// Upgrades a file from one schema version to the next
+type Migration = fn(&mut Mapping) -> Result<(), String>;
+
+/// Configuration migrations, in order. The first upgrades version 1 to 2, the next 2 to 3 and so on
+const CONFIGURATION_MIGRATIONS: [Migration; 1] = [configuration_v1_to_v2];
+/// System constants migrations, in order. The first upgrades version 1 to 2, the next 2 to 3 and so on
+const SYSTEM_CONSTANTS_MIGRATIONS: [Migration; 2] = [system_constants_v1_to_v2, system_constants_v2_to_v3];
+
+/// The kinds of versioned file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Schema {
+    Configuration,
+    SystemConstants,
+}
+
+impl Schema {
+    pub fn current_version(&self) -> u32 {
+        match self {
+            Schema::Configuration => CONFIGURATION_VERSION,
+            Schema::SystemConstants => SYSTEM_CONSTANTS_VERSION,
+        }
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        match self {
+            Schema::Configuration => &CONFIGURATION_MIGRATIONS,
+            Schema::SystemConstants => &SYSTEM_CONSTANTS_MIGRATIONS,
+        }
+    }
+}
+
+/// The result of migrating a file's text
+pub struct Migrated {
+    /// The text at the current schema version (unchanged if it already was)
+    pub text: String,
+    /// The version the file was at before migrating, if it had to be migrated
+    pub from_version: Option<u32>,
+}
+
+/// Upgrade a parsed file to the current schema version in place, returning the version it was at. Files from a newer
+/// version than this build understands are refused rather than guessed at
+pub fn migrate(value: &mut Value, schema: Schema) -> Result<u32, String> {
+    let mapping = value.as_mapping_mut().ok_or("expected a mapping at the top level".to_string())?;
+    let version = match mapping.get("version") {
+        None => UNVERSIONED,
+        Some(version) => version.as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version >= UNVERSIONED)
+            .ok_or(format!("version must be a whole number of at least {}, got {:?}", UNVERSIONED, version))?,
+    };
+    let current = schema.current_version();
+    if version > current {
+        return Err(format!("schema version {} is newer than this build supports (up to {})", version, current));
+    }
+    for (from, migration) in schema.migrations().iter().enumerate().skip((version - UNVERSIONED) as usize) {
+        migration(mapping).map_err(|e| format!("unable to migrate from version {}: {}", from as u32 + UNVERSIONED, e))?;
+        mapping.insert("version".into(), (from as u32 + UNVERSIONED + 1).into());
+    }
+    if version < current {
+        // Keep the version at the top of the file, where people look for it
+        let rest = std::mem::take(mapping);
+        mapping.insert("version".into(), current.into());
+        mapping.extend(rest.into_iter().filter(|(key, _)| key.as_str() != Some("version")));
+    }
+    Ok(version)
+}
+
+/// Upgrade a file's YAML text to the current schema version
+pub fn migrate_text(text: &str, schema: Schema) -> Result<Migrated, String> {
+    let mut value: Value = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+    let version = migrate(&mut value, schema)?;
+    if version == schema.current_version() {
+        return Ok(Migrated { text: text.to_string(), from_version: None });
+    }
+    Ok(Migrated {
+        text: serde_yaml::to_string(&value).map_err(|e| e.to_string())?,
+        from_version: Some(version),
+    })
+}
+
+/// Replace a file with its migrated text, backing up the old version first. Returns the path of the backup
+pub fn write_migrated(path: &Path, migrated: &Migrated, persistence: &PersistenceConfig) -> io::Result<Option<PathBuf>> {
+    // Keep a copy of the old version even if regular backups are turned off
+    let persistence = PersistenceConfig { backup_count: persistence.backup_count.max(1), ..persistence.clone() };
+    let backup_path = backup_file(path, &persistence)?;
+    write_atomic(path, migrated.text.as_bytes())?;
+    Ok(backup_path)
+}
+
+/// Version 2 added the version field. Everything else added since version 1 has a default, so there's nothing else
+/// to change
+fn configuration_v1_to_v2(_config: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+/// Version 2 added the version field. reserved_pins (also added since version 1) has a default
+fn system_constants_v1_to_v2(_constants: &mut Mapping) -> Result<(), String> {
+    Ok(())
+}
+
+/// Version 3 replaced the fixed `strikers` map (one entry per built-in kind) with `kinds`, which can define any kind.
+/// The existing kinds are all solenoids
+fn system_constants_v2_to_v3(constants: &mut Mapping) -> Result<(), String> {
+    let kinds = constants.get_mut("strikers").and_then(Value::as_mapping_mut).ok_or("missing strikers".to_string())?;
+    for (_, kind) in kinds.iter_mut() {
+        if let Some(kind) = kind.as_mapping_mut() {
+            kind.entry("driver".into()).or_insert("Solenoid".into());
+        }
+    }
+    // Rename in place so the file keeps its order
+    *constants = std::mem::take(constants).into_iter()
+        .map(|(key, value)| if key.as_str() == Some("strikers") { ("kinds".into(), value) } else { (key, value) })
+        .collect();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V2_CONSTANTS: &str = "\
+version: 2
+strikers:
+  SolenoidBig:
+    min_min_hit_duration: 8.0
+    max_min_hit_duration: 50.0
+  SolenoidSmall:
+    min_min_hit_duration: 0.1
+    max_min_hit_duration: 0.5
+reserved_pins: [0, 1]
+";
+
+    #[test]
+    fn v2_constants_rename_strikers_to_kinds_and_become_solenoids() {
+        let migrated = migrate_text(V2_CONSTANTS, Schema::SystemConstants).unwrap();
+        assert_eq!(migrated.from_version, Some(2));
+        let value: Value = serde_yaml::from_str(&migrated.text).unwrap();
+        let keys: Vec<&str> = value.as_mapping().unwrap().keys().filter_map(Value::as_str).collect();
+        assert_eq!(keys, vec!["version", "kinds", "reserved_pins"]);
+        assert_eq!(value["version"], Value::from(3));
+        for kind in ["SolenoidBig", "SolenoidSmall"] {
+            assert_eq!(value["kinds"][kind]["driver"], Value::from("Solenoid"));
+        }
+        assert_eq!(value["kinds"]["SolenoidBig"]["min_min_hit_duration"], Value::from(8.0));
+    }
+
+    #[test]
+    fn unversioned_constants_are_migrated_from_version_1() {
+        let unversioned = V2_CONSTANTS.replace("version: 2\n", "");
+        let mut value: Value = serde_yaml::from_str(&unversioned).unwrap();
+        assert_eq!(migrate(&mut value, Schema::SystemConstants), Ok(1));
+        assert_eq!(value["version"], Value::from(3));
+        assert!(value.get("kinds").is_some() && value.get("strikers").is_none());
+    }
+
+    #[test]
+    fn a_current_file_passes_through_unchanged() {
+        let text = "version: 2\n# A comment that reserializing would lose\nstrikers: []\n";
+        let migrated = migrate_text(text, Schema::Configuration).unwrap();
+        assert_eq!(migrated.from_version, None);
+        assert_eq!(migrated.text, text);
+        let constants = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/system-constants.yaml")).unwrap();
+        let migrated = migrate_text(&constants, Schema::SystemConstants).unwrap();
+        assert_eq!(migrated.from_version, None);
+        assert_eq!(migrated.text, constants);
+    }
+
+    #[test]
+    fn a_newer_version_is_refused() {
+        let error = migrate_text("version: 3\nstrikers: []\n", Schema::Configuration).err().unwrap();
+        assert_eq!(error, "schema version 3 is newer than this build supports (up to 2)");
+        let mut value: Value = serde_yaml::from_str("version: 4\nkinds: {}\n").unwrap();
+        assert!(migrate(&mut value, Schema::SystemConstants).is_err());
+    }
+
+    #[test]
+    fn a_bad_version_is_refused() {
+        for text in ["version: 0\n", "version: two\n", "version: -1\n"] {
+            assert!(migrate_text(text, Schema::Configuration).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn v2_constants_without_strikers_are_refused() {
+        let error = migrate_text("version: 2\nreserved_pins: []\n", Schema::SystemConstants).err().unwrap();
+        assert_eq!(error, "unable to migrate from version 2: missing strikers");
+    }
+}
@@ -1,2 +1,8 @@
 pub mod system_constants;
 pub mod configuration;
+pub mod cli;
+pub mod signals;
+pub mod validation;
+pub mod file_watcher;
+pub mod persistence;
+pub mod migration;
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+lazy_static! {
+    /// Modification times of the files this process last wrote, so the file watcher can tell its own writes (saves,
+    /// rollbacks, migrations) from edits made by someone else
+    static ref OWN_WRITES: Mutex<HashMap<PathBuf, SystemTime>> = Mutex::new(HashMap::new());
+}
+
+/// Backup settings, loaded from the `persistence` section of the configuration
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PersistenceConfig {
+    /// How many previous versions of the configuration file to keep (0 to keep none)
+    pub backup_count: usize,
+    /// Directory backups are kept in (a `backups` directory next to the configuration file if not given)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_dir: Option<String>,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            backup_count: 5,
+            backup_dir: None,
+        }
+    }
+}
+
+impl PersistenceConfig {
+    /// Get the directory backups of the given file are kept in
+    pub fn backup_dir_for(&self, path: &Path) -> PathBuf {
+        match &self.backup_dir {
+            Some(dir) => PathBuf::from(dir),
+            None => path.parent().unwrap_or(Path::new("")).join("backups"),
+        }
+    }
+}
+
+/// Replace a file's contents so that a crash or power cut leaves either the old or the new version, never a mix:
+/// the new contents are written to a temporary file next to it, flushed to disk, then renamed over it
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path.file_name().ok_or(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    if let Err(e) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    if let Ok(modified) = fs::metadata(path).and_then(|metadata| metadata.modified()) {
+        OWN_WRITES.lock().unwrap().insert(own_write_key(path), modified);
+    }
+    // Make sure the rename itself has reached the disk
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    File::open(dir)?.sync_all()
+}
+
+/// Check whether a file's modification time is the one it was given when this process last wrote it, i.e. nobody
+/// else has changed it since
+pub fn is_own_write(path: &Path, modified: SystemTime) -> bool {
+    OWN_WRITES.lock().unwrap().get(&own_write_key(path)) == Some(&modified)
+}
+
+/// The same file can be reached by different paths, so writes are remembered by their canonical path
+fn own_write_key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Copy a file into the backup directory under a timestamped name (numbered if another backup was made in the same
+/// millisecond), then delete the oldest backups beyond the configured count. Returns the path of the new backup, or None if the file doesn't exist or backups are disabled
+pub fn backup_file(path: &Path, config: &PersistenceConfig) -> io::Result<Option<PathBuf>> {
+    if config.backup_count == 0 || !path.exists() {
+        return Ok(None);
+    }
+    let dir = config.backup_dir_for(path);
+    fs::create_dir_all(&dir)?;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+    let mut backup_path = dir.join(format!("{}-{}.{}", file_stem(path), time, file_extension(path)));
+    // Backups made within the same millisecond are numbered rather than written over
+    let mut number = 1;
+    while backup_path.exists() {
+        backup_path = dir.join(format!("{}-{}_{}.{}", file_stem(path), time, number, file_extension(path)));
+        number += 1;
+    }
+    write_atomic(&backup_path, &fs::read(path)?)?;
+    for old_backup in list_backups(path, config)?.into_iter().skip(config.backup_count) {
+        fs::remove_file(old_backup)?;
+    }
+    Ok(Some(backup_path))
+}
+
+/// List the backups of a file, newest first
+pub fn list_backups(path: &Path, config: &PersistenceConfig) -> io::Result<Vec<PathBuf>> {
+    let dir = config.backup_dir_for(path);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let prefix = format!("{}-", file_stem(path));
+    let suffix = format!(".{}", file_extension(path));
+    // Sort on the parsed timestamp and number, so backups sort by time even if the timestamps have different numbers
+    // of digits
+    let mut backups: Vec<((u128, u32), PathBuf)> = vec![];
+    for entry in fs::read_dir(dir)? {
+        let backup_path = entry?.path();
+        let name = backup_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let order = name.strip_prefix(&prefix).and_then(|rest| rest.strip_suffix(&suffix)).and_then(backup_order);
+        if let Some(order) = order {
+            backups.push((order, backup_path));
+        }
+    }
+    backups.sort_by_key(|(order, _)| std::cmp::Reverse(*order));
+    Ok(backups.into_iter().map(|(_, backup_path)| backup_path).collect())
+}
+
+/// Back up a file then atomically replace its contents
+pub fn save_with_backup(path: &Path, contents: &[u8], config: &PersistenceConfig) -> io::Result<()> {
+    backup_file(path, config)?;
+    write_atomic(path, contents)
+}
+
+/// Find the backup `steps` versions back (1 is the most recent backup)
+pub fn find_backup(path: &Path, steps: usize, config: &PersistenceConfig) -> Result<PathBuf, String> {
+    let backups = list_backups(path, config).map_err(|e| format!("Unable to list backups: {}", e))?;
+    match steps.checked_sub(1).and_then(|index| backups.get(index)) {
+        Some(backup_path) => Ok(backup_path.clone()),
+        None if backups.is_empty() => Err(format!("No backups of {} to roll back to", path.display())),
+        None => Err(format!("Rollback steps must be between 1 and {} (the number of backups)", backups.len())),
+    }
+}
+
+/// Restore a backup over a file. The current version is backed up first, so a rollback can itself be rolled back
+pub fn restore_backup(path: &Path, backup_path: &Path, config: &PersistenceConfig) -> io::Result<()> {
+    let contents = fs::read(backup_path)?;
+    save_with_backup(path, &contents, config)
+}
+
+/// Parse the timestamp and number of a backup from its name between the file's stem and extension, e.g. `1700000000000`
+/// or `1700000000000_1` for the second backup made in that millisecond
+fn backup_order(name: &str) -> Option<(u128, u32)> {
+    match name.split_once('_') {
+        Some((time, number)) => Some((time.parse().ok()?, number.parse().ok()?)),
+        None => Some((name.parse().ok()?, 0)),
+    }
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().unwrap_or_default().to_string_lossy().to_string()
+}
+
+fn file_extension(path: &Path) -> String {
+    path.extension().unwrap_or_default().to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A configuration file in a directory of its own, which is emptied first
+    fn config_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("auto_drum_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("configuration.yaml")
+    }
+
+    #[test]
+    fn backups_are_rotated_and_found_by_steps_back() {
+        let path = config_file("rotation");
+        let config = PersistenceConfig { backup_count: 3, backup_dir: None };
+        fs::write(&path, "v0").unwrap();
+        // Quick enough that several backups land in the same millisecond
+        for version in 1..=5 {
+            save_with_backup(&path, format!("v{}", version).as_bytes(), &config).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v5");
+        assert_eq!(list_backups(&path, &config).unwrap().len(), 3);
+        for (steps, expected) in [(1, "v4"), (2, "v3"), (3, "v2")] {
+            let backup_path = find_backup(&path, steps, &config).unwrap();
+            assert_eq!(fs::read_to_string(backup_path).unwrap(), expected, "{} steps back", steps);
+        }
+        assert_eq!(find_backup(&path, 0, &config), Err("Rollback steps must be between 1 and 3 (the number of backups)".to_string()));
+        assert!(find_backup(&path, 4, &config).is_err());
+
+        // A rollback backs up the current version first, so it can be undone
+        restore_backup(&path, &find_backup(&path, 2, &config).unwrap(), &config).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "v3");
+        assert_eq!(fs::read_to_string(find_backup(&path, 1, &config).unwrap()).unwrap(), "v5");
+    }
+
+    #[test]
+    fn backups_in_the_same_millisecond_sort_by_number() {
+        let path = config_file("same_millisecond");
+        let config = PersistenceConfig::default();
+        let dir = config.backup_dir_for(&path);
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["configuration-999.yaml", "configuration-1000.yaml", "configuration-1000_2.yaml", "configuration-1000_1.yaml", "other-2000.yaml"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let names: Vec<String> = list_backups(&path, &config).unwrap().iter()
+            .map(|backup_path| backup_path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["configuration-1000_2.yaml", "configuration-1000_1.yaml", "configuration-1000.yaml", "configuration-999.yaml"]);
+    }
+
+    #[test]
+    fn no_backups_is_an_error() {
+        let path = config_file("no_backups");
+        assert_eq!(
+            find_backup(&path, 1, &PersistenceConfig::default()),
+            Err(format!("No backups of {} to roll back to", path.display())),
+        );
+    }
+}
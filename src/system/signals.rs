@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
+
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::hardware::output;
+
+/// How long a graceful shutdown may take before outputs are forced off and the process is killed
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+/// Exit code used when a shutdown doesn't finish within SHUTDOWN_TIMEOUT
+pub const EXIT_CODE_SHUTDOWN_TIMEOUT: i32 = 124;
+/// Exit codes after a shutdown signal follow the shell convention of 128 plus the signal number (130 for SIGINT,
+/// 143 for SIGTERM)
+const EXIT_CODE_SIGNAL_BASE: i32 = 128;
+
+/// Number of the last shutdown signal received (0 if none has been)
+static RECEIVED_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// Listens for the signals that ask the application to shut down (SIGINT from Ctrl-C, SIGTERM from systemd)
+pub struct ShutdownSignals {
+    interrupt: tokio::signal::unix::Signal,
+    terminate: tokio::signal::unix::Signal,
+}
+
+impl ShutdownSignals {
+    /// Start listening for shutdown signals. Once this is called the signals no longer kill the process on their own
+    pub fn new() -> std::io::Result<Self> {
+        Ok(Self {
+            interrupt: signal(SignalKind::interrupt())?,
+            terminate: signal(SignalKind::terminate())?,
+        })
+    }
+
+    /// Wait for a shutdown signal, returning its name. The signal is remembered so the process can exit with its
+    /// code once shut down
+    pub async fn recv(&mut self) -> &'static str {
+        let (name, kind) = tokio::select! {
+            _ = self.interrupt.recv() => ("SIGINT", SignalKind::interrupt()),
+            _ = self.terminate.recv() => ("SIGTERM", SignalKind::terminate()),
+        };
+        RECEIVED_SIGNAL.store(kind.as_raw_value(), Ordering::Relaxed);
+        name
+    }
+}
+
+/// Get the code to exit with after shutting down because of a signal (None if no shutdown signal was received)
+pub fn signal_exit_code() -> Option<i32> {
+    match RECEIVED_SIGNAL.load(Ordering::Relaxed) {
+        0 => None,
+        signal => Some(EXIT_CODE_SIGNAL_BASE + signal),
+    }
+}
+
+/// Start a timer on a separate OS thread (so it still fires if the async runtime is stuck) that forces every output
+/// off and exits the process if the shutdown hasn't finished by the time it runs out
+pub fn arm_shutdown_timeout(timeout: Duration) {
+    std::thread::spawn(move || {
+        std::thread::sleep(timeout);
+        let forced = output::force_all_low();
+        eprintln!("Shutdown timed out after {:?}, forced {} outputs off", timeout, forced);
+        std::process::exit(EXIT_CODE_SHUTDOWN_TIMEOUT);
+    });
+}
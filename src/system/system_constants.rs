@@ -1,9 +1,20 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::RwLock;
+
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use config::{Config, File, FileFormat};
+
+use crate::hardware::striker_hardware_util::{DriverType, HardwareKind};
+use crate::system::migration::{migrate_text, write_migrated, Schema, SYSTEM_CONSTANTS_VERSION};
+use crate::system::persistence::PersistenceConfig;
+use crate::system::validation::{validate_system_constants, ValidationReport};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct StrikerConstants {
+    // How the hardware is driven
+    #[serde(default)]
+    pub driver: DriverType,
     // The minimum that the min_hit_duration can be set to
     pub min_min_hit_duration: f64,
     // The maximum that the min_hit_duration can be set to
@@ -18,31 +29,172 @@ pub struct StrikerConstants {
     pub default_max_hit_duration: f64,
     // The step size for changing these controls
     pub increment: f64,
+    // The lowest duty cycle (0-1) the hardware may be driven at while on
+    #[serde(default)]
+    pub min_duty: f64,
+    // The highest duty cycle (0-1) the hardware may be driven at while on
+    #[serde(default = "default_max_duty")]
+    pub max_duty: f64,
+    // The longest the hardware may be held on in milliseconds (e.g. a modifier held for a sustained note)
+    #[serde(default = "default_max_hold_duration")]
+    pub max_hold_duration: f64,
+    // How long the hardware needs after a hit ends before it can hit again in milliseconds (e.g. for a solenoid to
+    // return), which rolls and other articulations leave between strokes
+    #[serde(default = "default_min_recovery_time")]
+    pub min_recovery_time: f64,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct StrikerConstantsMap {
-    #[allow(non_snake_case)] // just to match the enum variants
-    pub SolenoidBig: StrikerConstants,
-    #[allow(non_snake_case)]
-    pub SolenoidSmall: StrikerConstants,
+fn default_max_duty() -> f64 {
+    1.0
 }
 
+fn default_max_hold_duration() -> f64 {
+    5000.0
+}
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+fn default_min_recovery_time() -> f64 {
+    10.0
+}
+
+impl StrikerConstants {
+    /// Apply a single unit's overrides on top of the constants for its kind
+    pub fn with_overrides(self, overrides: Option<&StrikerConstantsOverrides>) -> Self {
+        let Some(o) = overrides else { return self };
+        Self {
+            driver: self.driver,
+            min_min_hit_duration: o.min_min_hit_duration.unwrap_or(self.min_min_hit_duration),
+            max_min_hit_duration: o.max_min_hit_duration.unwrap_or(self.max_min_hit_duration),
+            min_max_hit_duration: o.min_max_hit_duration.unwrap_or(self.min_max_hit_duration),
+            max_max_hit_duration: o.max_max_hit_duration.unwrap_or(self.max_max_hit_duration),
+            default_min_hit_duration: o.default_min_hit_duration.unwrap_or(self.default_min_hit_duration),
+            default_max_hit_duration: o.default_max_hit_duration.unwrap_or(self.default_max_hit_duration),
+            increment: o.increment.unwrap_or(self.increment),
+            min_duty: o.min_duty.unwrap_or(self.min_duty),
+            max_duty: o.max_duty.unwrap_or(self.max_duty),
+            max_hold_duration: o.max_hold_duration.unwrap_or(self.max_hold_duration),
+            min_recovery_time: o.min_recovery_time.unwrap_or(self.min_recovery_time),
+        }
+    }
+}
+
+/// Changes to the constants of a kind for a single striker (e.g. one unit with a stiffer spring). Anything left out
+/// uses the kind's value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StrikerConstantsOverrides {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_min_hit_duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_min_hit_duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_max_hit_duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_max_hit_duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_min_hit_duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_max_hit_duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub increment: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_duty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_duty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_hold_duration: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_recovery_time: Option<f64>,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemConstants {
-    pub strikers: StrikerConstantsMap,
+    /// Schema version of the file, which older files are migrated up to when loaded
+    #[serde(default = "current_version")]
+    pub version: u32,
+    /// Every kind of hardware strikers and modifiers can use, by name
+    pub kinds: BTreeMap<HardwareKind, StrikerConstants>,
+    /// GPIO pins that must not be used for strikers or modifiers (e.g. I2C and UART)
+    #[serde(default = "default_reserved_pins")]
+    pub reserved_pins: Vec<u8>,
+}
+
+fn current_version() -> u32 {
+    SYSTEM_CONSTANTS_VERSION
+}
+
+/// ID EEPROM (0, 1), I2C (2, 3) and UART (14, 15)
+fn default_reserved_pins() -> Vec<u8> {
+    vec![0, 1, 2, 3, 14, 15]
+}
+
+pub const DEFAULT_SYSTEM_CONSTANTS_PATH: &str = "system-constants.yaml";
+
+
+impl SystemConstants {
+    /// Load a system constants file and parse it into a SystemConstants struct, reporting every problem with it. A
+    /// file from an older schema version is migrated, and saved back (after backing it up) if the result is valid
+    pub fn load(path: &str) -> Result<Self, ValidationReport> {
+        let text = std::fs::read_to_string(path).map_err(|e| ValidationReport::with_issue(path, "", format!("unable to read file: {}", e)))?;
+        let migrated = migrate_text(&text, Schema::SystemConstants).map_err(|e| ValidationReport::with_issue(path, "", e))?;
+        let constants: SystemConstants = serde_yaml::from_str(&migrated.text).map_err(|e| ValidationReport::with_issue(path, "", e))?;
+        validate_system_constants(path, &constants).into_result()?;
+        if let Some(from_version) = migrated.from_version {
+            match write_migrated(Path::new(path), &migrated, &PersistenceConfig::default()) {
+                Ok(backup_path) => println!(
+                    "Migrated {} from schema version {} to {} (previous version backed up to {})",
+                    path, from_version, SYSTEM_CONSTANTS_VERSION, backup_path.unwrap_or_default().display(),
+                ),
+                Err(e) => eprintln!("Migrated {} from schema version {} but unable to save it: {}", path, from_version, e),
+            }
+        }
+        Ok(constants)
+    }
+
+    /// Get the constants for the given kind, or None if no such kind is defined
+    pub fn get_striker_constants(&self, kind: &HardwareKind) -> Option<&StrikerConstants> {
+        self.kinds.get(kind)
+    }
+
+    /// Get the names of every kind, for error messages
+    pub fn kind_names(&self) -> Vec<String> {
+        self.kinds.keys().map(|kind| kind.to_string()).collect()
+    }
 }
 
 lazy_static! {
-    pub static ref SYSTEM_CONSTANTS: SystemConstants = {
-        // load system-constants.yaml and parse it into a SystemConstants struct
-        Config::builder()
-            .add_source(File::with_name("system-constants.yaml").required(true))
-            .build()
-            .unwrap()
-            .try_deserialize()
-            .unwrap()
-    };
+    /// The system constants in use. Set at startup (and on hot-reload), or loaded from the default path on first use
+    static ref SYSTEM_CONSTANTS: RwLock<Option<SystemConstants>> = RwLock::new(None);
+}
+
+/// Get a copy of the system constants in use, loading them from the default path if none have been set (an error
+/// if they can't be loaded from there)
+pub fn get_system_constants() -> Result<SystemConstants, String> {
+    if let Some(constants) = SYSTEM_CONSTANTS.read().unwrap().as_ref() {
+        return Ok(constants.clone());
+    }
+    let mut system_constants = SYSTEM_CONSTANTS.write().unwrap();
+    if let Some(constants) = system_constants.as_ref() {
+        return Ok(constants.clone());
+    }
+    let constants = SystemConstants::load(DEFAULT_SYSTEM_CONSTANTS_PATH).map_err(|e| e.to_string())?;
+    Ok(system_constants.insert(constants).clone())
+}
+
+/// Get the constants for the given kind from the system constants in use, or an error naming the kinds that are
+/// defined if there's no such kind
+pub fn get_striker_constants(kind: &HardwareKind) -> Result<StrikerConstants, String> {
+    let lookup = |constants: &SystemConstants| constants.get_striker_constants(kind).copied().ok_or_else(|| {
+        format!("No hardware kind named {} in the system constants (defined kinds: {})", kind, constants.kind_names().join(", "))
+    });
+    if let Some(constants) = SYSTEM_CONSTANTS.read().unwrap().as_ref() {
+        return lookup(constants);
+    }
+    lookup(&get_system_constants()?)
+}
+
+/// Replace the system constants in use
+pub fn set_system_constants(constants: SystemConstants) {
+    *SYSTEM_CONSTANTS.write().unwrap() = Some(constants);
 }
 
@@ -0,0 +1,929 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::comms::cc_mapping::CcTarget;
+use crate::hardware::modifier::ModifierData;
+use crate::hardware::striker::StrikerData;
+use crate::hardware::actuator::StepperSettings;
+use crate::hardware::adc::MCP3008_CHANNELS;
+use crate::hardware::expander::{PCA9685_MAX_FREQUENCY, PCA9685_MIN_FREQUENCY};
+use crate::hardware::hit_sensor::{HitSensorConfig, HitSensorInput, HIT_SENSOR_THRESHOLD_RANGE, HIT_SENSOR_WINDOW_RANGE};
+use crate::hardware::output::{hardware_pwm_channel, OutputMode};
+use crate::hardware::pin_address::PinAddress;
+use crate::hardware::striker_hardware_util::{DriverType, HardwareKind};
+use crate::hardware::trigger::{TriggerConfig, TRIGGER_THRESHOLD_RANGE};
+use crate::playback::articulation::{Articulation, ArticulationData};
+use crate::playback::scheduler::TEMPO_SCALE_RANGE;
+use crate::playback::dynamics::{RATIO_RANGE, VELOCITY_CURVE_RANGE, VELOCITY_GAIN_RANGE, VELOCITY_OFFSET_RANGE, VELOCITY_RANGE, VELOCITY_SCALE_RANGE};
+use crate::playback::humanize::HUMANIZE_AMOUNT_RANGE;
+use crate::system::configuration::{Configuration, DEFAULT_KIT_NAME};
+use crate::system::system_constants::{StrikerConstants, SystemConstants};
+
+/// Highest BCM GPIO number broken out on the raspberry pi header
+const MAX_GPIO_PIN: u8 = 27;
+/// GPIO pins of the I2C bus that expander boards are on
+const I2C_GPIO_PINS: [u8; 2] = [2, 3];
+/// GPIO pins of each SPI bus on the header that MCP3008 converters can be on
+const SPI_GPIO_PINS: [&[u8]; 2] = [&[7, 8, 9, 10, 11], &[16, 17, 18, 19, 20, 21]];
+/// Number of chip select lines on each SPI bus on the header
+const SPI_CHIP_SELECTS: [u8; 2] = [2, 3];
+/// Highest MIDI note number
+const MAX_NOTE: u8 = 127;
+/// Highest MIDI controller number that isn't a channel mode message
+const MAX_CONTROLLER: u8 = 119;
+/// Number of MIDI channels, numbered from 1
+const MIDI_CHANNELS: u8 = 16;
+
+/// A single problem found in a configuration or system constants file
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationIssue {
+    /// Where in the file the problem is, e.g. `strikers[1].max_hit_duration`
+    pub path: String,
+    /// What's wrong
+    pub message: String,
+}
+
+/// Every problem found in a configuration or system constants file
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    /// The file (or remote write) that was checked
+    pub source: String,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn new(source: &str) -> Self {
+        Self { source: source.to_string(), issues: vec![] }
+    }
+
+    /// Create a report with a single problem
+    pub fn with_issue(source: &str, path: &str, message: impl ToString) -> Self {
+        let mut report = Self::new(source);
+        report.push(path, message);
+        report
+    }
+
+    pub fn push(&mut self, path: &str, message: impl ToString) {
+        self.issues.push(ValidationIssue { path: path.to_string(), message: message.to_string() });
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Turn the report into a Result, so it can be passed up with `?` when there are problems
+    pub fn into_result(self) -> Result<(), ValidationReport> {
+        if self.is_valid() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_valid() {
+            return write!(f, "{} is valid", self.source);
+        }
+        write!(f, "{} has {} problem{}:", self.source, self.issues.len(), if self.issues.len() == 1 { "" } else { "s" })?;
+        for issue in self.issues.iter() {
+            match issue.path.as_str() {
+                "" => write!(f, "\n  {}", issue.message)?,
+                path => write!(f, "\n  {}: {}", path, issue.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationReport {}
+
+/// Parse configuration YAML, collecting every problem with it rather than stopping at the first
+pub fn parse_configuration(source: &str, text: &str, constants: &SystemConstants) -> Result<Configuration, ValidationReport> {
+    let config: Configuration = serde_yaml::from_str(text).map_err(|e| ValidationReport::with_issue(source, "", e))?;
+    validate_configuration(source, &config, constants).into_result().map(|_| config)
+}
+
+/// Check an already-parsed configuration (e.g. one written by a remote) against the system constants
+pub fn validate_configuration(source: &str, config: &Configuration, constants: &SystemConstants) -> ValidationReport {
+    let mut report = ValidationReport::new(source);
+    check_configuration(config, constants, &mut report);
+    report
+}
+
+/// Check that the system constants are consistent with themselves
+pub fn validate_system_constants(source: &str, constants: &SystemConstants) -> ValidationReport {
+    let mut report = ValidationReport::new(source);
+    if constants.kinds.is_empty() {
+        report.push("kinds", "must define at least one kind");
+    }
+    for (kind, c) in constants.kinds.iter() {
+        if kind.name().trim().is_empty() {
+            report.push("kinds", "kind names must not be empty");
+        }
+        check_striker_constants(&format!("kinds.{}", kind), c, &mut report);
+    }
+    for (i, pin) in constants.reserved_pins.iter().enumerate() {
+        if *pin > MAX_GPIO_PIN {
+            report.push(&format!("reserved_pins[{}]", i), format!("{} is not a GPIO pin (0-{})", pin, MAX_GPIO_PIN));
+        }
+    }
+    report
+}
+
+/// Check that the constants of a kind (or a single striker's, with its overrides applied) are consistent with
+/// themselves. Servos and steppers don't use the hit durations, so only their max_hold_duration is checked
+fn check_striker_constants(path: &str, c: &StrikerConstants, report: &mut ValidationReport) {
+    if !is_positive(c.max_hold_duration) {
+        report.push(&format!("{}.max_hold_duration", path), format!("must be greater than 0, got {}", c.max_hold_duration));
+    }
+    if c.min_recovery_time < 0.0 || c.min_recovery_time.is_nan() {
+        report.push(&format!("{}.min_recovery_time", path), format!("must not be negative, got {}", c.min_recovery_time));
+    }
+    if matches!(c.driver, DriverType::Servo | DriverType::Stepper) {
+        return;
+    }
+    let bounds = [
+        ("min_min_hit_duration", c.min_min_hit_duration),
+        ("max_min_hit_duration", c.max_min_hit_duration),
+        ("min_max_hit_duration", c.min_max_hit_duration),
+        ("max_max_hit_duration", c.max_max_hit_duration),
+        ("increment", c.increment),
+    ];
+    for (field, value) in bounds {
+        if !is_positive(value) {
+            report.push(&format!("{}.{}", path, field), format!("must be greater than 0, got {}", value));
+        }
+    }
+    if c.min_min_hit_duration > c.max_min_hit_duration {
+        report.push(&format!("{}.min_min_hit_duration", path), "must not be greater than max_min_hit_duration");
+    }
+    if c.min_max_hit_duration > c.max_max_hit_duration {
+        report.push(&format!("{}.min_max_hit_duration", path), "must not be greater than max_max_hit_duration");
+    }
+    if !(c.min_min_hit_duration..=c.max_min_hit_duration).contains(&c.default_min_hit_duration) {
+        report.push(&format!("{}.default_min_hit_duration", path), "must be between min_min_hit_duration and max_min_hit_duration");
+    }
+    if !(c.min_max_hit_duration..=c.max_max_hit_duration).contains(&c.default_max_hit_duration) {
+        report.push(&format!("{}.default_max_hit_duration", path), "must be between min_max_hit_duration and max_max_hit_duration");
+    }
+    if !(0.0..=1.0).contains(&c.min_duty) {
+        report.push(&format!("{}.min_duty", path), format!("must be between 0 and 1, got {}", c.min_duty));
+    }
+    if !(0.0..=1.0).contains(&c.max_duty) {
+        report.push(&format!("{}.max_duty", path), format!("must be between 0 and 1, got {}", c.max_duty));
+    } else if c.min_duty > c.max_duty {
+        report.push(&format!("{}.min_duty", path), "must not be greater than max_duty");
+    }
+}
+
+/// Run every check on a parsed configuration
+fn check_configuration(config: &Configuration, constants: &SystemConstants, report: &mut ValidationReport) {
+    if config.kits.is_empty() {
+        check_kit("", &config.strikers, &config.modifiers, &config.articulations, constants, report);
+        if let Some(default_kit) = config.default_kit.as_ref().filter(|name| *name != DEFAULT_KIT_NAME) {
+            report.push("default_kit", format!("no kit named {:?}", default_kit));
+        }
+    } else {
+        if !config.strikers.is_empty() || !config.modifiers.is_empty() || !config.articulations.is_empty() {
+            report.push("", "top-level strikers, modifiers and articulations can't be used alongside kits (move them into a kit)");
+        }
+        // kit name (lowercased) to the path that first used it
+        let mut kit_names: HashMap<String, String> = HashMap::new();
+        for (i, kit) in config.kits.iter().enumerate() {
+            let path = format!("kits[{}]", i);
+            if kit.name.trim().is_empty() {
+                report.push(&format!("{}.name", path), "must not be empty");
+            } else if let Some(first) = kit_names.get(&kit.name.to_lowercase()) {
+                report.push(&format!("{}.name", path), format!("{:?} is already used by {}", kit.name, first));
+            } else {
+                kit_names.insert(kit.name.to_lowercase(), path.clone());
+            }
+            check_kit(&format!("{}.", path), &kit.strikers, &kit.modifiers, &kit.articulations, constants, report);
+        }
+        if let Some(default_kit) = config.default_kit.as_ref().filter(|name| !kit_names.contains_key(&name.to_lowercase())) {
+            report.push("default_kit", format!("no kit named {:?}", default_kit));
+        }
+    }
+
+    check_cc_mappings(config, report);
+    check_humanize(config, report);
+    check_triggers(config, report);
+
+    if let Err(e) = config.ble.validate() {
+        report.push("ble", e);
+    }
+    let watchdog = &config.watchdog;
+    for (field, value) in [("check_interval_ms", watchdog.check_interval_ms), ("stall_timeout_ms", watchdog.stall_timeout_ms)] {
+        if !is_positive(value) {
+            report.push(&format!("watchdog.{}", field), format!("must be greater than 0, got {}", value));
+        }
+    }
+    if !is_positive(watchdog.grace_ms) && watchdog.grace_ms != 0.0 {
+        report.push("watchdog.grace_ms", format!("must not be negative, got {}", watchdog.grace_ms));
+    }
+    if config.hot_reload.poll_interval_ms == 0 {
+        report.push("hot_reload.poll_interval_ms", "must be greater than 0");
+    }
+    let logging = &config.logging;
+    if logging.directory.trim().is_empty() {
+        report.push("logging.directory", "must not be empty");
+    }
+    if logging.max_file_size_kb == 0 {
+        report.push("logging.max_file_size_kb", "must be greater than 0");
+    }
+    if !is_positive(logging.max_file_age_minutes) && logging.max_file_age_minutes != 0.0 {
+        report.push("logging.max_file_age_minutes", format!("must not be negative, got {}", logging.max_file_age_minutes));
+    }
+}
+
+/// Check the global velocity processing and the controller mappings. Mapped strikers only need to be in one of the
+/// kits, since a mapping does nothing while its striker's kit isn't loaded
+fn check_cc_mappings(config: &Configuration, report: &mut ValidationReport) {
+    check_range("velocity_scale", config.velocity_scale, VELOCITY_SCALE_RANGE, report);
+    check_range("dynamics.threshold", config.dynamics.threshold, VELOCITY_RANGE, report);
+    check_range("dynamics.ratio", config.dynamics.ratio, RATIO_RANGE, report);
+    check_range("dynamics.ceiling", config.dynamics.ceiling, VELOCITY_RANGE, report);
+    check_range("tempo_scale", config.tempo_scale, TEMPO_SCALE_RANGE, report);
+    let kits = config.all_kits();
+    for (i, mapping) in config.cc_mappings.iter().enumerate() {
+        let path = format!("cc_mappings[{}]", i);
+        if mapping.cc > MAX_CONTROLLER {
+            report.push(&format!("{}.cc", path), format!("{} is not a MIDI controller number (0-{})", mapping.cc, MAX_CONTROLLER));
+        }
+        if let Some(channel) = mapping.channel.filter(|channel| !(1..=MIDI_CHANNELS).contains(channel)) {
+            report.push(&format!("{}.channel", path), format!("{} is not a MIDI channel (1-{})", channel, MIDI_CHANNELS));
+        }
+        for (field, value) in [("min", mapping.min), ("max", mapping.max)] {
+            if value.is_some_and(|value| !value.is_finite()) {
+                report.push(&format!("{}.{}", path, field), "must be a number");
+            }
+        }
+        if let Some((striker, _)) = mapping.target.striker_parameter() {
+            if !kits.iter().flat_map(|kit| &kit.strikers).any(|data| data.name.eq_ignore_ascii_case(striker)) {
+                report.push(&format!("{}.target.striker", path), format!("no striker named {:?} in any kit", striker));
+            }
+        }
+        match &mapping.target {
+            CcTarget::Kit if mapping.min.is_some() || mapping.max.is_some() => {
+                report.push(&path, "min and max can't be used with the Kit target, which spreads the kits over the whole range");
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Check the global humanize settings
+fn check_humanize(config: &Configuration, report: &mut ValidationReport) {
+    let humanize = &config.humanize;
+    check_range("humanize.amount", humanize.amount, HUMANIZE_AMOUNT_RANGE, report);
+    let amounts = humanize.amounts;
+    check_humanize_amounts("humanize", [
+        ("timing_jitter", Some(amounts.timing_jitter)),
+        ("timing_drift", Some(amounts.timing_drift)),
+        ("velocity_variation", Some(amounts.velocity_variation)),
+    ], report);
+    if !is_positive(humanize.drift_period) {
+        report.push("humanize.drift_period", format!("must be greater than 0, got {}", humanize.drift_period));
+    }
+    for (i, offset) in humanize.groove.iter().enumerate() {
+        if !offset.is_finite() {
+            report.push(&format!("humanize.groove[{}]", i), "must be a number");
+        }
+    }
+}
+
+/// Check the trigger pads. The SPI buses they're read from have their own pins, which no kit may use
+fn check_triggers(config: &Configuration, report: &mut ValidationReport) {
+    let triggers: &TriggerConfig = &config.triggers;
+    if !(1..=MIDI_CHANNELS).contains(&triggers.channel) {
+        report.push("triggers.channel", format!("{} is not a MIDI channel (1-{})", triggers.channel, MIDI_CHANNELS));
+    }
+    if !(triggers.crosstalk_window >= 0.0 && triggers.crosstalk_window.is_finite()) {
+        report.push("triggers.crosstalk_window", format!("must not be negative, got {}", triggers.crosstalk_window));
+    }
+    check_range("triggers.crosstalk_ratio", triggers.crosstalk_ratio, (0.0, 1.0), report);
+    let kits = config.all_kits();
+    // name (lowercased), note and converter channel to the path that first used it
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut notes: HashMap<u8, String> = HashMap::new();
+    let mut channels: HashMap<(u8, u8, u8), String> = HashMap::new();
+    let mut checked_buses: Vec<u8> = vec![];
+    for (i, pad) in triggers.pads.iter().enumerate() {
+        let path = format!("triggers.pads[{}]", i);
+        if pad.name.trim().is_empty() {
+            report.push(&format!("{}.name", path), "must not be empty");
+        } else if let Some(first) = names.get(&pad.name.to_lowercase()) {
+            report.push(&format!("{}.name", path), format!("{:?} is already used by {}", pad.name, first));
+        } else {
+            names.insert(pad.name.to_lowercase(), path.clone());
+        }
+        if pad.note > MAX_NOTE {
+            report.push(&format!("{}.note", path), format!("{} is not a MIDI note number (0-{})", pad.note, MAX_NOTE));
+        } else if let Some(first) = notes.get(&pad.note) {
+            report.push(&format!("{}.note", path), format!("{} is already used by {}", pad.note, first));
+        } else {
+            notes.insert(pad.note, path.clone());
+        }
+        let (bus, chip_select) = (pad.adc.bus, pad.adc.chip_select);
+        match SPI_CHIP_SELECTS.get(bus as usize) {
+            None => report.push(&format!("{}.bus", path), format!("{} is not an SPI bus on the header (0-{})", bus, SPI_CHIP_SELECTS.len() - 1)),
+            Some(chip_selects) if chip_select >= *chip_selects => report.push(
+                &format!("{}.chip_select", path),
+                format!("SPI bus {} has no chip select {} (0-{})", bus, chip_select, chip_selects - 1),
+            ),
+            Some(_) => {},
+        }
+        if pad.channel >= MCP3008_CHANNELS {
+            report.push(&format!("{}.channel", path), format!("the MCP3008 has no channel {} (0-{})", pad.channel, MCP3008_CHANNELS - 1));
+        } else if let Some(first) = channels.get(&(bus, chip_select, pad.channel)) {
+            report.push(&format!("{}.channel", path), format!("channel {} is already read by {}", pad.channel, first));
+        } else {
+            channels.insert((bus, chip_select, pad.channel), path.clone());
+        }
+        check_range(&format!("{}.threshold", path), pad.threshold, TRIGGER_THRESHOLD_RANGE, report);
+        if !(pad.sensitivity > pad.threshold && pad.sensitivity <= 1.0) {
+            report.push(&format!("{}.sensitivity", path), format!("must be above the threshold and at most 1, got {}", pad.sensitivity));
+        }
+        for (field, value) in [("scan_time", pad.scan_time), ("mask_time", pad.mask_time)] {
+            if !is_positive(value) {
+                report.push(&format!("{}.{}", path, field), format!("must be greater than 0, got {}", value));
+            }
+        }
+        if pad.mask_time < pad.scan_time {
+            report.push(&format!("{}.mask_time", path), "must not be shorter than scan_time");
+        }
+        check_range(&format!("{}.velocity_curve", path), pad.velocity_curve, VELOCITY_CURVE_RANGE, report);
+        let Some(bus_pins) = SPI_GPIO_PINS.get(bus as usize).filter(|_| !checked_buses.contains(&bus)) else { continue };
+        checked_buses.push(bus);
+        for kit in &kits {
+            let used = kit.strikers.iter()
+                .flat_map(|striker| {
+                    let dir_pin = striker.stepper.map(|stepper| stepper.dir_pin);
+                    let sensor_pin = striker.sensor.and_then(|sensor| sensor.gpio_pin()).map(PinAddress::Gpio);
+                    [Some(striker.pin), dir_pin, sensor_pin].into_iter().flatten().map(move |pin| (pin, &striker.name))
+                })
+                .chain(kit.modifiers.iter().map(|modifier| (modifier.pin, &modifier.name)));
+            for (pin, name) in used {
+                if let Some(gpio) = pin.gpio().filter(|gpio| bus_pins.contains(gpio)) {
+                    report.push(&format!("{}.bus", path), format!("SPI bus {} needs GPIO {}, which {} uses in kit {}", bus, gpio, name, kit.name));
+                }
+            }
+        }
+    }
+}
+
+/// Check that humanize amounts (the global ones or a striker's changes to them) aren't negative
+fn check_humanize_amounts(path: &str, amounts: [(&str, Option<f64>); 3], report: &mut ValidationReport) {
+    for (field, value) in amounts {
+        if let Some(value) = value.filter(|value| !(*value >= 0.0 && value.is_finite())) {
+            report.push(&format!("{}.{}", path, field), format!("must not be negative, got {}", value));
+        }
+    }
+}
+
+/// Check the strikers, modifiers and articulations of one kit. Names, notes and pins only need to be unique within a
+/// kit, since only one kit is loaded at a time. `prefix` is the kit's path in the file
+fn check_kit(
+    prefix: &str,
+    strikers: &[StrikerData],
+    modifiers: &[ModifierData],
+    articulations: &[ArticulationData],
+    constants: &SystemConstants,
+    report: &mut ValidationReport,
+) {
+    // name (lowercased, since the console matches names case-insensitively), note and pin to the path that first used it
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut notes: HashMap<u8, String> = HashMap::new();
+    let mut pins: HashMap<PinAddress, String> = HashMap::new();
+
+    // Outputs can use more than one pin (e.g. a stepper's STEP and DIR), given as (field, pin)
+    let mut check_output = |path: &str, name: &str, note: u8, output_pins: &[(&str, PinAddress)], report: &mut ValidationReport| {
+        if name.trim().is_empty() {
+            report.push(&format!("{}.name", path), "must not be empty");
+        } else if let Some(first) = names.get(&name.to_lowercase()) {
+            report.push(&format!("{}.name", path), format!("{:?} is already used by {}", name, first));
+        } else {
+            names.insert(name.to_lowercase(), path.to_string());
+        }
+        if note > MAX_NOTE {
+            report.push(&format!("{}.note", path), format!("{} is not a MIDI note number (0-{})", note, MAX_NOTE));
+        } else if let Some(first) = notes.get(&note) {
+            report.push(&format!("{}.note", path), format!("{} is already used by {}", note, first));
+        } else {
+            notes.insert(note, path.to_string());
+        }
+        for (field, pin) in output_pins {
+            let pin_path = format!("{}.{}", path, field);
+            let gpio = pin.gpio();
+            if gpio.is_some_and(|gpio| gpio > MAX_GPIO_PIN) {
+                report.push(&pin_path, format!("{} is not a GPIO pin (0-{})", pin, MAX_GPIO_PIN));
+            } else if gpio.is_some_and(|gpio| constants.reserved_pins.contains(&gpio)) {
+                report.push(&pin_path, format!("{} is reserved (see reserved_pins in the system constants)", pin));
+            } else if let Some(first) = pins.get(pin) {
+                report.push(&pin_path, format!("{} is already used by {}", pin, first));
+            } else {
+                pins.insert(*pin, pin_path);
+            }
+        }
+    };
+
+    let mut pca9685_frequencies: HashMap<u16, (f64, String)> = HashMap::new();
+    for (i, striker) in strikers.iter().enumerate() {
+        let path = format!("{}strikers[{}]", prefix, i);
+        let mut output_pins = vec![("pin", striker.pin)];
+        if let Some(stepper) = striker.stepper {
+            output_pins.push(("stepper.dir_pin", stepper.dir_pin));
+        }
+        // A GPIO sensor is an input, but it still needs a pin of its own
+        if let Some(pin) = striker.sensor.and_then(|sensor| sensor.gpio_pin()) {
+            output_pins.push(("sensor.pin", PinAddress::Gpio(pin)));
+        }
+        check_output(&path, &striker.name, striker.note, &output_pins, report);
+        if let Some(sensor) = &striker.sensor {
+            check_hit_sensor(&format!("{}.sensor", path), sensor, report);
+        }
+        if let Some(curve) = striker.velocity_curve {
+            check_range(&format!("{}.velocity_curve", path), curve, VELOCITY_CURVE_RANGE, report);
+        }
+        if let Some(gain) = striker.velocity_gain {
+            check_range(&format!("{}.velocity_gain", path), gain, VELOCITY_GAIN_RANGE, report);
+        }
+        if let Some(offset) = striker.velocity_offset {
+            check_range(&format!("{}.velocity_offset", path), offset, VELOCITY_OFFSET_RANGE, report);
+        }
+        if let Some(humanize) = striker.humanize {
+            check_humanize_amounts(&format!("{}.humanize", path), [
+                ("timing_jitter", humanize.timing_jitter),
+                ("timing_drift", humanize.timing_drift),
+                ("velocity_variation", humanize.velocity_variation),
+            ], report);
+        }
+        match constants.get_striker_constants(&striker.kind) {
+            Some(kind_constants) => {
+                let unit_constants = kind_constants.with_overrides(striker.overrides.as_ref());
+                if striker.overrides.is_some() {
+                    check_striker_constants(&format!("{}.overrides", path), &unit_constants, report);
+                }
+                check_durations(&path, striker.min_hit_duration, striker.max_hit_duration, &unit_constants, report);
+                check_driver_settings(&path, striker, &unit_constants, report);
+                check_board_pin(&path, striker, &unit_constants, &mut pca9685_frequencies, report);
+            },
+            None => check_kind(&path, &striker.kind, constants, report),
+        }
+    }
+    // Two pins can share a hardware PWM channel, but two strikers can't drive it differently
+    let mut pwm_channels: HashMap<u8, String> = HashMap::new();
+    for (i, striker) in strikers.iter().enumerate() {
+        if striker.output_mode() != OutputMode::HardwarePwm {
+            continue;
+        }
+        let Some(channel) = striker.pin.gpio().and_then(hardware_pwm_channel) else { continue };
+        let channel = channel as u8;
+        let section = if striker.servo.is_some_and(|servo| servo.hardware) { "servo" } else { "pwm" };
+        let path = format!("{}strikers[{}]", prefix, i);
+        match pwm_channels.get(&channel) {
+            Some(first) => report.push(&format!("{}.{}.hardware", path, section), format!("hardware PWM channel {} is already used by {}", channel, first)),
+            None => {
+                pwm_channels.insert(channel, path);
+            },
+        }
+    }
+    for (i, modifier) in modifiers.iter().enumerate() {
+        let path = format!("{}modifiers[{}]", prefix, i);
+        check_output(&path, &modifier.name, modifier.note, &[("pin", modifier.pin)], report);
+        match constants.get_striker_constants(&modifier.kind) {
+            Some(kind_constants) if matches!(kind_constants.driver, DriverType::Servo | DriverType::Stepper) => report.push(
+                &format!("{}.kind", path),
+                format!("{} has the {:?} driver, but modifiers can only be switched on and off", modifier.kind, kind_constants.driver),
+            ),
+            Some(_) => {},
+            None => check_kind(&path, &modifier.kind, constants, report),
+        }
+        if !strikers.iter().any(|striker| striker.name == modifier.target) {
+            report.push(&format!("{}.target", path), format!("no striker named {:?}", modifier.target));
+        }
+    }
+    // controller number to the path that first used it
+    let mut ccs: HashMap<u8, String> = HashMap::new();
+    for (i, articulation) in articulations.iter().enumerate() {
+        let path = format!("{}articulations[{}]", prefix, i);
+        let name = &articulation.name;
+        if name.trim().is_empty() {
+            report.push(&format!("{}.name", path), "must not be empty");
+        } else if let Some(first) = names.get(&name.to_lowercase()) {
+            report.push(&format!("{}.name", path), format!("{:?} is already used by {}", name, first));
+        } else {
+            names.insert(name.to_lowercase(), path.clone());
+        }
+        if let Some(note) = articulation.note {
+            if note > MAX_NOTE {
+                report.push(&format!("{}.note", path), format!("{} is not a MIDI note number (0-{})", note, MAX_NOTE));
+            } else if let Some(first) = notes.get(&note) {
+                report.push(&format!("{}.note", path), format!("{} is already used by {}", note, first));
+            } else {
+                notes.insert(note, path.clone());
+            }
+        }
+        if let Some(cc) = articulation.cc {
+            if cc > MAX_CONTROLLER {
+                report.push(&format!("{}.cc", path), format!("{} is not a MIDI controller number (0-{})", cc, MAX_CONTROLLER));
+            } else if let Some(first) = ccs.get(&cc) {
+                report.push(&format!("{}.cc", path), format!("{} is already used by {}", cc, first));
+            } else {
+                ccs.insert(cc, path.clone());
+            }
+        }
+        if !strikers.iter().any(|striker| striker.name.eq_ignore_ascii_case(&articulation.striker)) {
+            report.push(&format!("{}.striker", path), format!("no striker named {:?}", articulation.striker));
+        }
+        check_articulation(&format!("{}.articulation", path), &articulation.articulation, report);
+    }
+    // The I2C bus has its own pins, which are usually reserved anyway
+    if pins.keys().any(|pin| pin.i2c_address().is_some()) {
+        for (pin, path) in pins.iter().filter(|(pin, _)| pin.gpio().is_some_and(|gpio| I2C_GPIO_PINS.contains(&gpio))) {
+            report.push(path, format!("GPIO {} is needed for the I2C bus, which this kit's expander boards are on", pin));
+        }
+    }
+    // As does each SPI bus that sensors are read from
+    let spi_buses: Vec<u8> = strikers.iter()
+        .filter_map(|striker| match striker.sensor?.input {
+            HitSensorInput::Mcp3008 { bus, .. } => Some(bus),
+            HitSensorInput::Gpio { .. } => None,
+        })
+        .collect();
+    for (pin, path) in &pins {
+        let Some(gpio) = pin.gpio() else { continue };
+        if let Some(bus) = spi_buses.iter().find(|bus| SPI_GPIO_PINS.get(**bus as usize).is_some_and(|pins| pins.contains(&gpio))) {
+            report.push(path, format!("GPIO {} is needed for SPI bus {}, which this kit's hit sensors are read from", gpio, bus));
+        }
+    }
+}
+
+/// Check the settings of a striker's hit sensor
+fn check_hit_sensor(path: &str, sensor: &HitSensorConfig, report: &mut ValidationReport) {
+    check_range(&format!("{}.window", path), sensor.window, HIT_SENSOR_WINDOW_RANGE, report);
+    let HitSensorInput::Mcp3008 { bus, chip_select, channel, threshold } = sensor.input else { return };
+    match SPI_CHIP_SELECTS.get(bus as usize) {
+        None => report.push(&format!("{}.bus", path), format!("{} is not an SPI bus on the header (0-{})", bus, SPI_CHIP_SELECTS.len() - 1)),
+        Some(chip_selects) if chip_select >= *chip_selects => report.push(
+            &format!("{}.chip_select", path),
+            format!("SPI bus {} has no chip select {} (0-{})", bus, chip_select, chip_selects - 1),
+        ),
+        Some(_) => {},
+    }
+    if channel >= MCP3008_CHANNELS {
+        report.push(&format!("{}.channel", path), format!("the MCP3008 has no channel {} (0-{})", channel, MCP3008_CHANNELS - 1));
+    }
+    check_range(&format!("{}.threshold", path), threshold, HIT_SENSOR_THRESHOLD_RANGE, report);
+}
+
+/// Check that a striker only has settings for its kind's driver, then check those settings
+fn check_driver_settings(path: &str, striker: &StrikerData, constants: &StrikerConstants, report: &mut ValidationReport) {
+    let sections = [
+        ("pwm", DriverType::Pwm, striker.pwm.is_some()),
+        ("servo", DriverType::Servo, striker.servo.is_some()),
+        ("stepper", DriverType::Stepper, striker.stepper.is_some()),
+    ];
+    for (section, driver, present) in sections {
+        if present && constants.driver != driver {
+            report.push(
+                &format!("{}.{}", path, section),
+                format!("only used by kinds with the {:?} driver, and {} has the {:?} driver", driver, striker.kind, constants.driver),
+            );
+        }
+    }
+    match constants.driver {
+        DriverType::Solenoid => {},
+        DriverType::Pwm => check_pwm(path, striker, constants, report),
+        DriverType::Servo => check_servo(path, striker, constants, report),
+        DriverType::Stepper => match striker.stepper {
+            Some(stepper) => check_stepper(&format!("{}.stepper", path), &stepper, constants, report),
+            None => report.push(
+                &format!("{}.stepper", path),
+                format!("{} has the Stepper driver, so the striker needs stepper settings (at least a dir_pin)", striker.kind),
+            ),
+        },
+    }
+}
+
+/// Check a striker's PWM settings against its kind
+fn check_pwm(path: &str, striker: &StrikerData, constants: &StrikerConstants, report: &mut ValidationReport) {
+    let Some(pwm) = striker.pwm else { return };
+    let path = format!("{}.pwm", path);
+    if !is_positive(pwm.frequency) {
+        report.push(&format!("{}.frequency", path), format!("must be greater than 0, got {}", pwm.frequency));
+    }
+    if pwm.kick_duration < 0.0 || pwm.kick_duration.is_nan() {
+        report.push(&format!("{}.kick_duration", path), format!("must not be negative, got {}", pwm.kick_duration));
+    }
+    if !(constants.min_duty..=constants.max_duty).contains(&pwm.hold_duty) {
+        report.push(
+            &format!("{}.hold_duty", path),
+            format!("{} is outside the allowed range {}-{}", pwm.hold_duty, constants.min_duty, constants.max_duty),
+        );
+    }
+    if pwm.hardware && striker.pin.gpio().and_then(hardware_pwm_channel).is_none() {
+        report.push(&format!("{}.hardware", path), format!("pin {} has no hardware PWM channel (use GPIO 12, 13, 18 or 19)", striker.pin));
+    }
+}
+
+/// Check that a striker on an I2C board only asks for what the board can do. MCP23017 pins can only be switched on
+/// and off, and every channel of a PCA9685 runs at one PWM frequency (`pca9685_frequencies` maps each board to the
+/// frequency and path of the first striker that set it)
+fn check_board_pin(
+    path: &str,
+    striker: &StrikerData,
+    constants: &StrikerConstants,
+    pca9685_frequencies: &mut HashMap<u16, (f64, String)>,
+    report: &mut ValidationReport,
+) {
+    let Some(frequency) = striker.pwm_frequency(constants.driver) else { return };
+    match striker.pin {
+        PinAddress::Gpio(_) => {},
+        PinAddress::Mcp23017 { .. } => report.push(
+            &format!("{}.pin", path),
+            format!("{} can only be switched on and off, but {} has the {:?} driver", striker.pin, striker.kind, constants.driver),
+        ),
+        PinAddress::Pca9685 { address, .. } => {
+            if !(PCA9685_MIN_FREQUENCY..=PCA9685_MAX_FREQUENCY).contains(&frequency) {
+                report.push(
+                    &format!("{}.pin", path),
+                    format!("a PCA9685 can run at {}-{}Hz, not {}Hz", PCA9685_MIN_FREQUENCY, PCA9685_MAX_FREQUENCY, frequency),
+                );
+            }
+            match pca9685_frequencies.get(&address) {
+                Some((first_frequency, first)) if *first_frequency != frequency => report.push(
+                    &format!("{}.pin", path),
+                    format!("every channel of the PCA9685 at {:#04x} runs at one frequency, and {} uses {}Hz, not {}Hz", address, first, first_frequency, frequency),
+                ),
+                Some(_) => {},
+                None => {
+                    pca9685_frequencies.insert(address, (frequency, path.to_string()));
+                },
+            }
+        },
+    }
+}
+
+/// Check a servo striker's settings (the defaults if it has none) against its kind
+fn check_servo(path: &str, striker: &StrikerData, constants: &StrikerConstants, report: &mut ValidationReport) {
+    let servo = striker.servo.unwrap_or_default();
+    let path = format!("{}.servo", path);
+    if !is_positive(servo.max_angle) {
+        report.push(&format!("{}.max_angle", path), format!("must be greater than 0, got {}", servo.max_angle));
+    }
+    for (field, angle) in [("rest_angle", servo.rest_angle), ("strike_angle", servo.strike_angle)] {
+        if !(0.0..=servo.max_angle).contains(&angle) {
+            report.push(&format!("{}.{}", path, field), format!("{} is outside the servo's range 0-{}", angle, servo.max_angle));
+        }
+    }
+    if !is_positive(servo.min_pulse_width) || servo.max_pulse_width <= servo.min_pulse_width {
+        report.push(
+            &format!("{}.max_pulse_width", path),
+            format!("must be greater than min_pulse_width, which must be greater than 0 (got {}-{})", servo.min_pulse_width, servo.max_pulse_width),
+        );
+    }
+    if !is_positive(servo.frequency) {
+        report.push(&format!("{}.frequency", path), format!("must be greater than 0, got {}", servo.frequency));
+    } else if servo.max_pulse_width >= 1_000_000.0 / servo.frequency {
+        report.push(
+            &format!("{}.frequency", path),
+            format!("pulses every {}us are too short for a {}us pulse width", 1_000_000.0 / servo.frequency, servo.max_pulse_width),
+        );
+    }
+    if !is_positive(servo.travel_time) {
+        report.push(&format!("{}.travel_time", path), format!("must be greater than 0, got {}", servo.travel_time));
+    } else if servo.travel_time * 2.0 > constants.max_hold_duration {
+        report.push(
+            &format!("{}.travel_time", path),
+            format!("a hit swings out and back in {}ms, longer than the kind's max_hold_duration of {}ms", servo.travel_time * 2.0, constants.max_hold_duration),
+        );
+    }
+    if servo.hardware && striker.pin.gpio().and_then(hardware_pwm_channel).is_none() {
+        report.push(&format!("{}.hardware", path), format!("pin {} has no hardware PWM channel (use GPIO 12, 13, 18 or 19)", striker.pin));
+    }
+}
+
+/// Check a stepper striker's settings against its kind
+fn check_stepper(path: &str, stepper: &StepperSettings, constants: &StrikerConstants, report: &mut ValidationReport) {
+    if stepper.steps == 0 {
+        report.push(&format!("{}.steps", path), "must be greater than 0");
+    }
+    if !is_positive(stepper.step_rate) {
+        report.push(&format!("{}.step_rate", path), format!("must be greater than 0, got {}", stepper.step_rate));
+    } else if stepper.stroke_duration().as_secs_f64() * 1000.0 > constants.max_hold_duration {
+        report.push(
+            &format!("{}.steps", path),
+            format!(
+                "a full velocity hit takes {:.0}ms, longer than the kind's max_hold_duration of {}ms",
+                stepper.stroke_duration().as_secs_f64() * 1000.0, constants.max_hold_duration
+            ),
+        );
+    }
+}
+
+/// Check that an articulation's timings and velocities make sense
+fn check_articulation(path: &str, articulation: &Articulation, report: &mut ValidationReport) {
+    for (setting, problem) in articulation.problems() {
+        report.push(&format!("{}.{}", path, setting), problem);
+    }
+}
+
+/// Check that a kind is defined in the system constants
+fn check_kind(path: &str, kind: &HardwareKind, constants: &SystemConstants, report: &mut ValidationReport) {
+    if constants.get_striker_constants(kind).is_none() {
+        report.push(
+            &format!("{}.kind", path),
+            format!("unknown kind {:?} (expected one of {})", kind.name(), constants.kind_names().join(", ")),
+        );
+    }
+}
+
+/// Check that a number is greater than 0 (and not NaN)
+fn is_positive(value: f64) -> bool {
+    value > 0.0
+}
+
+/// Check that a value is within a range, inclusive
+fn check_range(path: &str, value: f64, (lowest, highest): (f64, f64), report: &mut ValidationReport) {
+    if !(lowest..=highest).contains(&value) {
+        report.push(path, format!("must be between {} and {}, got {}", lowest, highest, value));
+    }
+}
+
+/// Check a striker's hit durations against the bounds for its kind
+fn check_durations(path: &str, min_hit_duration: Option<f64>, max_hit_duration: Option<f64>, bounds: &StrikerConstants, report: &mut ValidationReport) {
+    if let Some(min) = min_hit_duration {
+        if !(bounds.min_min_hit_duration..=bounds.max_min_hit_duration).contains(&min) {
+            report.push(
+                &format!("{}.min_hit_duration", path),
+                format!("{} is outside the allowed range {}-{}", min, bounds.min_min_hit_duration, bounds.max_min_hit_duration),
+            );
+        }
+    }
+    if let Some(max) = max_hit_duration {
+        if !(bounds.min_max_hit_duration..=bounds.max_max_hit_duration).contains(&max) {
+            report.push(
+                &format!("{}.max_hit_duration", path),
+                format!("{} is outside the allowed range {}-{}", max, bounds.min_max_hit_duration, bounds.max_max_hit_duration),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "test.yaml";
+
+    fn constants() -> SystemConstants {
+        SystemConstants::load(concat!(env!("CARGO_MANIFEST_DIR"), "/system-constants.yaml")).unwrap()
+    }
+
+    /// Get the problems with a configuration as (path, message) pairs, in the order they were found
+    fn problems(yaml: &str) -> Vec<(String, String)> {
+        match parse_configuration(SOURCE, yaml, &constants()) {
+            Ok(_) => vec![],
+            Err(report) => report.issues.into_iter().map(|issue| (issue.path, issue.message)).collect(),
+        }
+    }
+
+    fn problem(path: &str, message: &str) -> (String, String) {
+        (path.to_string(), message.to_string())
+    }
+
+    /// A configuration with the given strikers and modifiers, and anything else (YAML at the top level) after them
+    fn kit(strikers: &str, modifiers: &str, rest: &str) -> String {
+        format!("strikers:\n{}\nmodifiers:\n{}\n{}", strikers, modifiers, rest)
+    }
+
+    const KICK: &str = "  - { name: Kick, kind: SolenoidBig, pin: 5, note: 36 }";
+    const SNARE: &str = "  - { name: Snare, kind: SolenoidSmall, pin: 6, note: 38 }";
+
+    #[test]
+    fn a_valid_kit_has_no_problems() {
+        assert_eq!(problems(&kit(&format!("{}\n{}", KICK, SNARE), "  []", "")), vec![]);
+    }
+
+    #[test]
+    fn duplicate_notes_and_pins_name_the_first_use() {
+        let strikers = format!("{}\n  - {{ name: Snare, kind: SolenoidSmall, pin: 5, note: 36 }}", KICK);
+        assert_eq!(problems(&kit(&strikers, "  []", "")), vec![
+            problem("strikers[1].note", "36 is already used by strikers[0]"),
+            problem("strikers[1].pin", "5 is already used by strikers[0].pin"),
+        ]);
+    }
+
+    #[test]
+    fn reserved_pins_are_refused() {
+        let strikers = "  - { name: Kick, kind: SolenoidBig, pin: 14, note: 36 }";
+        assert_eq!(problems(&kit(strikers, "  []", "")), vec![
+            problem("strikers[0].pin", "14 is reserved (see reserved_pins in the system constants)"),
+        ]);
+    }
+
+    #[test]
+    fn hit_durations_must_be_within_the_kind() {
+        let strikers = "  - { name: Kick, kind: SolenoidBig, pin: 5, note: 36, min_hit_duration: 4.0, max_hit_duration: 100.0 }";
+        assert_eq!(problems(&kit(strikers, "  []", "")), vec![
+            problem("strikers[0].min_hit_duration", "4 is outside the allowed range 8-50"),
+            problem("strikers[0].max_hit_duration", "100 is outside the allowed range 25-75"),
+        ]);
+    }
+
+    #[test]
+    fn modifiers_must_target_a_striker_in_their_kit() {
+        let modifiers = "  - { name: Open, kind: SolenoidBig, pin: 6, note: 46, target: HiHat }";
+        assert_eq!(problems(&kit(KICK, modifiers, "")), vec![
+            problem("modifiers[0].target", "no striker named \"HiHat\""),
+        ]);
+    }
+
+    #[test]
+    fn cc_mappings_are_checked() {
+        let rest = "cc_mappings:\n  - { cc: 120, target: { type: VelocityScale } }\n  - { cc: 7, channel: 17, target: { type: VelocityGain, striker: Tom } }";
+        assert_eq!(problems(&kit(KICK, "  []", rest)), vec![
+            problem("cc_mappings[0].cc", "120 is not a MIDI controller number (0-119)"),
+            problem("cc_mappings[1].channel", "17 is not a MIDI channel (1-16)"),
+            problem("cc_mappings[1].target.striker", "no striker named \"Tom\" in any kit"),
+        ]);
+    }
+
+    #[test]
+    fn trigger_pads_cant_share_a_note_or_use_a_missing_channel() {
+        let rest = "triggers:\n  pads:\n    - { name: Pad, note: 37, channel: 0 }\n    - { name: Pad2, note: 37, channel: 8 }";
+        assert_eq!(problems(&kit(KICK, "  []", rest)), vec![
+            problem("triggers.pads[1].note", "37 is already used by triggers.pads[0]"),
+            problem("triggers.pads[1].channel", "the MCP3008 has no channel 8 (0-7)"),
+        ]);
+    }
+
+    #[test]
+    fn trigger_spi_bus_pins_cant_be_used_by_a_kit() {
+        let strikers = "  - { name: Kick, kind: SolenoidBig, pin: 10, note: 36 }";
+        let rest = "triggers:\n  pads:\n    - { name: Pad, note: 37, channel: 0 }";
+        assert_eq!(problems(&kit(strikers, "  []", rest)), vec![
+            problem("triggers.pads[0].bus", "SPI bus 0 needs GPIO 10, which Kick uses in kit default"),
+        ]);
+    }
+
+    #[test]
+    fn hit_sensor_spi_bus_pins_cant_be_used_by_the_kit() {
+        let strikers = format!(
+            "  - {{ name: Kick, kind: SolenoidBig, pin: 5, note: 36, sensor: {{ type: Mcp3008, channel: 0 }} }}\n  {}",
+            "- { name: Snare, kind: SolenoidSmall, pin: 9, note: 38 }",
+        );
+        assert_eq!(problems(&kit(&strikers, "  []", "")), vec![
+            problem("strikers[1].pin", "GPIO 9 is needed for SPI bus 0, which this kit's hit sensors are read from"),
+        ]);
+    }
+
+    #[test]
+    fn expander_boards_need_the_i2c_pins() {
+        let strikers = "  - { name: Kick, kind: SolenoidBig, pin: 2, note: 36 }\n  - { name: Snare, kind: SolenoidSmall, pin: \"mcp23017@0x20:A0\", note: 38 }";
+        // GPIO 2 is usually reserved as well, which would be reported instead
+        let constants = SystemConstants { reserved_pins: vec![], ..constants() };
+        let report = parse_configuration(SOURCE, &kit(strikers, "  []", ""), &constants).unwrap_err();
+        let issues: Vec<(String, String)> = report.issues.into_iter().map(|issue| (issue.path, issue.message)).collect();
+        assert_eq!(issues, vec![
+            problem("strikers[0].pin", "GPIO 2 is needed for the I2C bus, which this kit's expander boards are on"),
+        ]);
+    }
+
+    #[test]
+    fn ble_settings_are_checked() {
+        let rest = "ble:\n  device_name: \"\"";
+        assert_eq!(problems(&kit(KICK, "  []", rest)), vec![problem("ble", "device_name must not be empty")]);
+    }
+
+    #[test]
+    fn every_problem_in_a_file_is_reported() {
+        let strikers = "  - { name: Kick, kind: SolenoidBig, pin: 14, note: 36, max_hit_duration: 100.0 }";
+        let report = parse_configuration(SOURCE, &kit(strikers, "  []", "velocity_scale: 3.0"), &constants()).unwrap_err();
+        assert_eq!(report.source, SOURCE);
+        assert_eq!(report.issues.len(), 3);
+        assert!(report.to_string().starts_with("test.yaml has 3 problems:"));
+    }
+
+    #[test]
+    fn unknown_kinds_list_the_defined_ones() {
+        let strikers = "  - { name: Kick, kind: Cannon, pin: 5, note: 36 }";
+        let found = problems(&kit(strikers, "  []", ""));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, "strikers[0].kind");
+        assert!(found[0].1.starts_with("unknown kind \"Cannon\" (expected one of "), "{}", found[0].1);
+    }
+
+    #[test]
+    fn system_constants_report_each_bad_bound() {
+        let mut constants = constants();
+        let kind = constants.kinds.keys().find(|kind| kind.name() == "SolenoidBig").unwrap().clone();
+        let kind_constants = constants.kinds.get_mut(&kind).unwrap();
+        kind_constants.increment = 0.0;
+        kind_constants.min_duty = -0.5;
+        let issues = validate_system_constants(SOURCE, &constants).issues;
+        let paths: Vec<&str> = issues.iter().map(|issue| issue.path.as_str()).collect();
+        assert_eq!(paths, vec![format!("kinds.{}.increment", kind), format!("kinds.{}.min_duty", kind)]);
+    }
+}